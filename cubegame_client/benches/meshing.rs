@@ -0,0 +1,153 @@
+//! Time taken to turn generated chunks into meshes, from `ChunkData` and from the layouts
+//! `cubegame_lib`'s `chunk_data` benchmark compares it against (one palette for the whole chunk,
+//! and every block stored inline)
+//!
+//! To compare further changes to a layout, save a baseline before changing it and compare against
+//! it after: `cargo bench --bench meshing -- --save-baseline before`, then
+//! `cargo bench --bench meshing -- --baseline before`
+
+use std::collections::HashMap;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use cubegame::render::mesher::{self, ChunkBlocks, ChunkBorders};
+use cubegame::render::TextureRefs;
+use cubegame_lib::{
+	light::ChunkLight, worldgen, BlockData, ChunkData, ChunkPos, Direction, LocalBlockPos,
+	WorldGenesisData, BLOCKS_PER_CHUNK,
+};
+
+/// A chunk with a single palette and an index for every block
+struct FlatChunk {
+	pos: ChunkPos,
+	palette: Vec<BlockData>,
+	blocks: Box<[u16; BLOCKS_PER_CHUNK]>,
+}
+impl FlatChunk {
+	fn from_chunk(chunk: &ChunkData) -> FlatChunk {
+		let mut palette_indices = HashMap::new();
+		let mut flat = FlatChunk {
+			pos: chunk.pos,
+			palette: Vec::new(),
+			blocks: vec![0; BLOCKS_PER_CHUNK]
+				.into_boxed_slice()
+				.try_into()
+				.unwrap(),
+		};
+		for (pos, block) in chunk.iter() {
+			let index = *palette_indices.entry(block).or_insert_with(|| {
+				flat.palette.push(block);
+				(flat.palette.len() - 1) as u16
+			});
+			flat.blocks[pos.to_index()] = index;
+		}
+		flat
+	}
+}
+impl ChunkBlocks for FlatChunk {
+	fn pos(&self) -> ChunkPos {
+		self.pos
+	}
+
+	fn get(&self, pos: LocalBlockPos) -> BlockData {
+		self.palette[self.blocks[pos.to_index()] as usize]
+	}
+}
+
+/// A chunk with every block stored as is
+struct InlineChunk {
+	pos: ChunkPos,
+	blocks: Box<[BlockData; BLOCKS_PER_CHUNK]>,
+}
+impl InlineChunk {
+	fn from_chunk(chunk: &ChunkData) -> InlineChunk {
+		let mut inline = InlineChunk {
+			pos: chunk.pos,
+			blocks: vec![BlockData::default(); BLOCKS_PER_CHUNK]
+				.into_boxed_slice()
+				.try_into()
+				.unwrap(),
+		};
+		for (pos, block) in chunk.iter() {
+			inline.blocks[pos.to_index()] = block;
+		}
+		inline
+	}
+}
+impl ChunkBlocks for InlineChunk {
+	fn pos(&self) -> ChunkPos {
+		self.pos
+	}
+
+	fn get(&self, pos: LocalBlockPos) -> BlockData {
+		self.blocks[pos.to_index()]
+	}
+}
+
+fn meshing(c: &mut Criterion) {
+	let genesis = WorldGenesisData { seed: 1234 };
+	let pos = ChunkPos { x: 3, z: -2 };
+	let chunk = worldgen::generate_chunk(&genesis, pos);
+	let light = ChunkLight::compute(&chunk);
+	// missing textures all fall back to the same one, which doesn't change how quads merge
+	let textures = TextureRefs::new(HashMap::new());
+
+	// surrounded by generated chunks, like it would be in a world
+	let mut borders = ChunkBorders::default();
+	for (dir, x, z) in [
+		(Direction::PosX, 1, 0),
+		(Direction::NegX, -1, 0),
+		(Direction::PosZ, 0, 1),
+		(Direction::NegZ, 0, -1),
+	] {
+		let neighbor = worldgen::generate_chunk(
+			&genesis,
+			ChunkPos {
+				x: pos.x + x,
+				z: pos.z + z,
+			},
+		);
+		borders.set_side(dir, &neighbor, &ChunkLight::compute(&neighbor));
+	}
+
+	let flat = FlatChunk::from_chunk(&chunk);
+	let inline = InlineChunk::from_chunk(&chunk);
+	let mut group = c.benchmark_group("mesh generated chunk");
+	group.bench_function("sectioned", |b| {
+		b.iter(|| {
+			mesher::generate_chunk_mesh(
+				black_box(&*chunk),
+				black_box(&light),
+				black_box(&borders),
+				&textures,
+			)
+		})
+	});
+	group.bench_function("flat", |b| {
+		b.iter(|| {
+			mesher::generate_chunk_mesh(
+				black_box(&flat),
+				black_box(&light),
+				black_box(&borders),
+				&textures,
+			)
+		})
+	});
+	group.bench_function("inline", |b| {
+		b.iter(|| {
+			mesher::generate_chunk_mesh(
+				black_box(&inline),
+				black_box(&light),
+				black_box(&borders),
+				&textures,
+			)
+		})
+	});
+	group.finish();
+
+	c.bench_function("light generated chunk", |b| {
+		b.iter(|| ChunkLight::compute(black_box(&chunk)))
+	});
+}
+
+criterion_group!(benches, meshing);
+criterion_main!(benches);
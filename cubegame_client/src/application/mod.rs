@@ -1,6 +1,6 @@
 mod framerate;
 
-use crate::{game::Game, render::Renderer, INTEGRATED_SERVER_PORT};
+use crate::{game::Game, render::Renderer, settings::ClientSettings, INTEGRATED_SERVER_PORT};
 use framerate::FramerateManager;
 use std::sync::Arc;
 use winit::event::{DeviceEvent, DeviceId};
@@ -21,16 +21,17 @@ pub enum ApplicationState {
 		game: Game,
 		framerate_manager: FramerateManager,
 	},
-	/// Uninitialized state, with the attributes to initialize the window with
-	Uninitialized(WindowAttributes),
+	/// Uninitialized state, with the attributes to initialize the window with and the settings to
+	/// start with
+	Uninitialized(WindowAttributes, ClientSettings),
 }
 impl ApplicationState {
-	pub fn new(window_attributes: WindowAttributes) -> ApplicationState {
-		ApplicationState::Uninitialized(window_attributes)
+	pub fn new(window_attributes: WindowAttributes, settings: ClientSettings) -> ApplicationState {
+		ApplicationState::Uninitialized(window_attributes, settings)
 	}
 
 	fn initialize(&mut self, event_loop: &ActiveEventLoop) -> Result<(), ()> {
-		if let ApplicationState::Uninitialized(window_attributes) = self {
+		if let ApplicationState::Uninitialized(window_attributes, settings) = self {
 			// Creating application and all its state and stuff
 			let window = Arc::new(
 				event_loop
@@ -38,7 +39,17 @@ impl ApplicationState {
 					.expect("Failed to create window"),
 			);
 
-			let renderer = match Renderer::new(window.clone()) {
+			// TODO support connecting to external servers
+			// connecting to game server first, block textures depend on the server's block types
+			let game_server_uri = http::Uri::builder()
+				.scheme("ws")
+				.authority(format!("localhost:{}", INTEGRATED_SERVER_PORT))
+				.path_and_query("/")
+				.build()
+				.unwrap();
+			let game = Game::new(game_server_uri, window.clone())?;
+
+			let renderer = match Renderer::new(window.clone(), settings) {
 				Ok(renderer) => renderer,
 				Err(()) => {
 					return Err(());
@@ -49,16 +60,6 @@ impl ApplicationState {
 			let mut framerate_manager = FramerateManager::new();
 			framerate_manager.set_max_fps(60);
 
-			// TODO support connecting to external servers
-			// connecting to game server
-			let game_server_uri = http::Uri::builder()
-				.scheme("ws")
-				.authority(format!("localhost:{}", INTEGRATED_SERVER_PORT))
-				.path_and_query("/")
-				.build()
-				.unwrap();
-			let game = Game::new(game_server_uri, window.clone())?;
-
 			*self = ApplicationState::InGame {
 				renderer,
 				window,
@@ -74,11 +75,14 @@ impl ApplicationState {
 impl ApplicationHandler for ApplicationState {
 	fn resumed(&mut self, event_loop: &ActiveEventLoop) {
 		match self {
-			ApplicationState::Uninitialized(_) => {
+			ApplicationState::Uninitialized(..) => {
 				// try to initialize
 				if self.initialize(event_loop).is_ok() {
 					// "resend" resume event if initialization was successful
 					self.resumed(event_loop);
+				} else {
+					log::error!("Failed to start game, exiting");
+					event_loop.exit();
 				}
 			}
 			_ => {}
@@ -108,9 +112,20 @@ impl ApplicationHandler for ApplicationState {
 
 						let dt = framerate_manager.tick();
 						game.update(dt);
+						if !game.is_connected() {
+							log::error!("Disconnected from server, exiting");
+							event_loop.exit();
+							return;
+						}
 
 						window.set_title(
-							format!("Cubegame ({} fps)", framerate_manager.current_fps).as_str(),
+							format!(
+								"Cubegame ({} fps, {} chunks drawn, {} culled)",
+								framerate_manager.current_fps,
+								renderer.chunk_stats.drawn,
+								renderer.chunk_stats.culled
+							)
+							.as_str(),
 						);
 						window.request_redraw();
 
@@ -135,7 +150,7 @@ impl ApplicationHandler for ApplicationState {
 					_ => {}
 				}
 			}
-			ApplicationState::Uninitialized(_) => match event {
+			ApplicationState::Uninitialized(..) => match event {
 				WindowEvent::CloseRequested => event_loop.exit(),
 				_ => {}
 			},
@@ -152,7 +167,7 @@ impl ApplicationHandler for ApplicationState {
 			ApplicationState::InGame { game, .. } => {
 				game.handle_device_event(&event);
 			}
-			ApplicationState::Uninitialized(_) => {}
+			ApplicationState::Uninitialized(..) => {}
 		}
 	}
 
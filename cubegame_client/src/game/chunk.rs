@@ -1,37 +1,57 @@
-use crate::render::mesher;
-use crate::render::objects::{Lines, Mesh};
-use cubegame_lib::{worldgen, ChunkData, ChunkDeltaData, WorldGenesisData};
+use crate::render::mesher::{self, ChunkMeshes};
+use crate::render::objects::Lines;
+use cubegame_lib::{
+	light::ChunkLight, worldgen, BlockData, ChunkData, ChunkDeltaData, LocalBlockPos,
+	WorldGenesisData,
+};
 
 pub struct LoadedChunk {
 	/// Chunk data: blocks
 	pub data: Box<ChunkData>,
-	/// This chunks meshes (one for each texture)
-	pub meshes: Vec<Mesh>,
+	/// Sky and block light of every block
+	pub light: ChunkLight,
+	/// This chunks meshes, with every block in it
+	pub meshes: ChunkMeshes,
 	/// This chunks debug lines
 	pub border_lines: Lines,
 	pub needs_remesh: bool,
+	/// Whether a worker is currently building new meshes for this chunk
+	pub meshing: bool,
 }
 impl LoadedChunk {
 	/// Loads new chunk from chunk data
 	///
 	/// (Does not generate meshes) (but does generate chunk borders cus those never change)
-	pub fn load_from_delta(delta: ChunkDeltaData) -> LoadedChunk {
+	///
+	/// The chunk is lit on its own, light from the chunks around it still needs to be spread into it
+	pub fn load_from_delta(delta: ChunkDeltaData, genesis: &WorldGenesisData) -> LoadedChunk {
 		let chunk_pos = delta.pos;
 
 		// data from the world generator
-		let mut chunk = worldgen::generate_chunk(&WorldGenesisData::default(), chunk_pos);
+		let mut chunk = worldgen::generate_chunk(genesis, chunk_pos);
 		let border_lines = mesher::generate_chunk_border_lines(chunk.as_ref());
 
 		// overwriting block data with blocks from chunk delta
 		for (pos, data) in delta.blocks {
-			chunk.blocks[pos.to_index()] = data;
+			chunk.set(pos, data);
 		}
+		let light = ChunkLight::compute(&chunk);
 
 		LoadedChunk {
 			data: chunk,
-			meshes: Vec::new(),
+			light,
+			meshes: ChunkMeshes::empty(),
 			border_lines,
 			needs_remesh: true,
+			meshing: false,
 		}
 	}
+
+	/// Changes a block, the chunk gets remeshed before it's rendered again
+	///
+	/// Doesn't update light, see `light::update_block`
+	pub fn set_block(&mut self, pos: LocalBlockPos, block: BlockData) {
+		self.data.set(pos, block);
+		self.needs_remesh = true;
+	}
 }
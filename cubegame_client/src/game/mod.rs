@@ -1,36 +1,71 @@
 mod chunk;
 pub mod controller;
+mod network;
 pub mod player;
+mod workers;
 pub mod world;
 
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::{
-	time::Instant,
-	{net::TcpStream, time::Duration},
-};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use cubegame_lib::{communication::*, ChunkPos};
+use cubegame_lib::blocks;
+use cubegame_lib::light;
+use cubegame_lib::time::WorldTime;
+use cubegame_lib::{
+	communication::*, BlockData, BlockPos, ChunkPos, LocalBlockPos, WorldGenesisData,
+};
 use http::Uri;
-use tungstenite::{connect, stream::MaybeTlsStream, Message, WebSocket};
 use winit::event::{DeviceEvent, ElementState, KeyEvent, WindowEvent};
 use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::{CursorGrabMode, Window};
 
-use crate::render::{mesher, Renderer};
-use chunk::LoadedChunk;
+use crate::render::Renderer;
 use controller::PlayerController;
+use network::{Incoming, ServerConnection};
+use workers::{Job, JobResult, WorkerPool};
 use world::WorldData;
 
 /// Chunk render distance radius
-const RENDER_DISTANCE: u32 = 8;
+pub const RENDER_DISTANCE: u32 = 8;
+/// Most chunks that can be requested from the server without having been received yet
+const MAX_CHUNK_REQUESTS_IN_FLIGHT: usize = 16;
+/// Name of the world to join, it's created if it doesn't exist yet
+const WORLD_NAME: &str = "world";
+/// Player name used when none is given in the `CUBEGAME_PLAYER_NAME` environment variable
+const DEFAULT_PLAYER_NAME: &str = "Player";
+/// Optional protocol features this client supports
+const CLIENT_CAPABILITIES: &[&str] = &[
+	capabilities::WORLD_MANAGEMENT,
+	capabilities::BLOCK_EVENTS,
+	capabilities::WORLD_TIME,
+	capabilities::BLOCK_REGISTRY,
+];
+
+/// A chunk a worker is generating
+#[derive(Default)]
+struct GeneratingChunk {
+	/// Block changes received since it was sent to the worker
+	changes: Vec<(LocalBlockPos, BlockData)>,
+	/// Whether it has already been sent back to a worker to be relit with earlier changes
+	relighting: bool,
+}
 
 /// Struct that represents everything to run the actual cubegame
 pub struct Game {
 	window: Arc<Window>,
 	pub world_data: WorldData,
 	controller: PlayerController,
-	/// Web socket connection to a game server
-	socket: WebSocket<MaybeTlsStream<TcpStream>>,
+	/// Connection to a game server
+	connection: ServerConnection,
+	/// Whether the connection to the server is still usable
+	connected: bool,
+	/// Chunks that have been requested but not received yet
+	chunk_requests: HashMap<RequestId, ChunkPos>,
+	/// Chunks being generated by workers
+	generating_chunks: HashMap<ChunkPos, GeneratingChunk>,
+	/// Generates and meshes chunks in the background
+	workers: WorkerPool,
 	/// For ticking once per second
 	last_slow_tick: Instant,
 	in_menu: bool,
@@ -38,7 +73,14 @@ pub struct Game {
 impl Game {
 	pub fn new(server_url: Uri, window: Arc<Window>) -> Result<Game, ()> {
 		// connecting to server
-		let (socket, _addr) = match connect(&server_url) {
+		let player_name =
+			std::env::var("CUBEGAME_PLAYER_NAME").unwrap_or(DEFAULT_PLAYER_NAME.to_string());
+		let hello = ServerMessage::Hello {
+			protocol_version: PROTOCOL_VERSION,
+			player_name,
+			capabilities: CLIENT_CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+		};
+		let (connection, welcome) = match ServerConnection::open(&server_url, hello) {
 			Ok(r) => r,
 			Err(e) => {
 				log::error!("Failed to connect to game server at {}: {}", server_url, e);
@@ -46,15 +88,30 @@ impl Game {
 			}
 		};
 		log::info!("Connected to game server at {}", server_url);
+		let server_capabilities = check_welcome(welcome)?;
 
-		let game = Game {
+		let mut game = Game {
 			window,
-			world_data: WorldData::new(),
+			world_data: WorldData::new(WorldGenesisData::default(), WorldTime::default()),
 			controller: PlayerController::new(),
-			socket,
+			connection,
+			connected: true,
+			chunk_requests: HashMap::new(),
+			generating_chunks: HashMap::new(),
+			workers: WorkerPool::new(),
 			last_slow_tick: Instant::now(),
 			in_menu: false,
 		};
+		if server_capabilities
+			.iter()
+			.any(|c| c == capabilities::BLOCK_REGISTRY)
+		{
+			game.sync_block_registry()?;
+		} else {
+			log::warn!("Server can't share its block types, assuming they're the built in ones");
+		}
+		let (genesis, time) = game.join_world(WORLD_NAME)?;
+		game.world_data = WorldData::new(genesis, time);
 		if game.window.has_focus() {
 			game.grab_cursor();
 		}
@@ -64,28 +121,125 @@ impl Game {
 	pub fn update(&mut self, dt: f32) {
 		// updating player from inputs
 		self.world_data.player.update(dt, &mut self.controller);
+		self.world_data.update_target_outline();
 
+		self.workers.set_focus(self.world_data.player.chunk_pos());
+		self.handle_finished_jobs();
+		let mut result = self.handle_incoming().and_then(|_| self.request_chunks());
 		if self.last_slow_tick.elapsed() > Duration::from_secs(1) {
 			self.last_slow_tick = Instant::now();
-			let res = self.load_chunks();
-			if res.is_err() {
-				log::error!("Error while loading/unloading chunks");
-			}
+			result = result.and_then(|_| self.unload_chunks());
+		}
+		if let Err(e) = result {
+			log::error!("Error while communicating with server: {}", e);
+			self.disconnect();
 		}
 	}
 
+	/// Whether the game is still connected to its server
+	pub fn is_connected(&self) -> bool {
+		self.connected
+	}
+
 	/// Cleaning up stuff
 	pub fn shutdown(&mut self) {
-		self.socket.close(None).unwrap();
+		self.disconnect();
 		self.release_cursor();
 	}
 
-	/// Loads/unloads chunks based on player position
-	fn load_chunks(&mut self) -> Result<(), ()> {
-		// chunk that player is in
+	/// Closes the connection to the server, if it isn't already
+	fn disconnect(&mut self) {
+		if !self.connected {
+			return;
+		}
+		self.connected = false;
+		self.connection.close();
+	}
+
+	/// Gets the server's block types and installs them, so block ids mean the same thing on both
+	/// sides
+	///
+	/// Needs to happen before any chunks are loaded or textures are read
+	fn sync_block_registry(&mut self) -> Result<(), ()> {
+		match self
+			.connection
+			.request(ServerMessage::GetBlockRegistry)
+			.map_err(log_lost_connection)?
+		{
+			ServerResponse::BlockRegistry(registry) => {
+				log::debug!("Received {} block types", registry.iter().count());
+				blocks::install_registry(registry).inspect_err(|_| {
+					log::error!("Server's block types differ from the ones already in use")
+				})
+			}
+			response => {
+				log::error!("Failed to get block types from server: {:?}", response);
+				Err(())
+			}
+		}
+	}
+
+	/// Loads a world on the server, creating it first if it doesn't exist
+	///
+	/// Returns the world's genesis data and current time
+	fn join_world(&mut self, name: &str) -> Result<(WorldGenesisData, WorldTime), ()> {
+		let load_world = ServerMessage::LoadWorld(name.to_string());
+		match self
+			.connection
+			.request(load_world)
+			.map_err(log_lost_connection)?
+		{
+			ServerResponse::WorldLoaded(genesis, time) => return Ok((genesis, time)),
+			ServerResponse::Err(ErrorMessage::WorldDoesNotExist) => {}
+			response => {
+				log::error!("Failed to load world \"{}\": {:?}", name, response);
+				return Err(());
+			}
+		}
+
+		log::info!("World \"{}\" does not exist, creating it", name);
+		let create_world =
+			ServerMessage::CreateWorld(name.to_string(), WorldGenesisData { seed: new_seed() });
+		let response = self
+			.connection
+			.request(create_world)
+			.map_err(log_lost_connection)?;
+		if !matches!(response, ServerResponse::Ack) {
+			log::error!("Failed to create world \"{}\": {:?}", name, response);
+			return Err(());
+		}
+		let load_world = ServerMessage::LoadWorld(name.to_string());
+		match self
+			.connection
+			.request(load_world)
+			.map_err(log_lost_connection)?
+		{
+			ServerResponse::WorldLoaded(genesis, time) => Ok((genesis, time)),
+			response => {
+				log::error!("Failed to load world \"{}\": {:?}", name, response);
+				Err(())
+			}
+		}
+	}
+
+	/// Whether a chunk is close enough to the player to be loaded
+	fn should_be_loaded(&self, chunk: ChunkPos) -> bool {
 		let player_chunk = self.world_data.player.chunk_pos();
+		let dist =
+			(((chunk.x - player_chunk.x).pow(2) + (chunk.z - player_chunk.z).pow(2)) as f32).sqrt();
+		dist < RENDER_DISTANCE as f32
+	}
+
+	/// Requests missing chunks around the player, closest first, without waiting for them
+	fn request_chunks(&mut self) -> Result<(), CommunicationError> {
+		if self.chunk_requests.len() >= MAX_CHUNK_REQUESTS_IN_FLIGHT {
+			return Ok(());
+		}
 
+		// chunk that player is in
+		let player_chunk = self.world_data.player.chunk_pos();
 		let render_dist = RENDER_DISTANCE as i32;
+		let mut missing = Vec::new();
 		for x in (-render_dist)..=render_dist {
 			for z in (-render_dist)..=render_dist {
 				// relative chunk position
@@ -93,35 +247,163 @@ impl Game {
 					x: x + player_chunk.x,
 					z: z + player_chunk.z,
 				};
+				if self.should_be_loaded(chunk)
+					&& !self.world_data.chunks.contains_key(&chunk)
+					&& !self.generating_chunks.contains_key(&chunk)
+					&& !self.chunk_requests.values().any(|c| *c == chunk)
+				{
+					missing.push((x.pow(2) + z.pow(2), chunk));
+				}
+			}
+		}
+		missing.sort_unstable_by_key(|(dist, _)| *dist);
 
-				let dist = ((x.pow(2) + z.pow(2)) as f32).sqrt();
-				if dist < RENDER_DISTANCE as f32 {
-					// chunk should be loaded
-					if !self.world_data.chunks.contains_key(&chunk) {
-						self.send_msg(ServerMessage::LoadChunk(chunk));
-						let response = self.recv_response()?;
-
-						if let ServerResponse::LoadChunkOK(data) = response {
-							self.world_data
-								.chunks
-								.insert(chunk, LoadedChunk::load_from_delta(data));
-						} else {
-							log::error!(
-								"Received unexpected response while requesting chunk data: {:?}",
-								response
-							);
-							return Err(());
+		let free_slots = MAX_CHUNK_REQUESTS_IN_FLIGHT - self.chunk_requests.len();
+		for (_dist, chunk) in missing.into_iter().take(free_slots) {
+			let id = self.connection.send(ServerMessage::LoadChunk(chunk))?;
+			self.chunk_requests.insert(id, chunk);
+		}
+		Ok(())
+	}
+
+	/// Unloads chunks that are too far from the player
+	fn unload_chunks(&mut self) -> Result<(), CommunicationError> {
+		let far_chunks: Vec<ChunkPos> = self
+			.world_data
+			.chunks
+			.keys()
+			.copied()
+			.filter(|chunk| !self.should_be_loaded(*chunk))
+			.collect();
+		for chunk in far_chunks {
+			let _unloaded_chunk = self.world_data.chunks.remove(&chunk);
+			self.world_data.remesh_neighbors(chunk);
+			self.workers.cancel(chunk);
+			// no need to hear about changes to it anymore
+			self.connection.send(ServerMessage::UnloadChunk(chunk))?;
+		}
+
+		let far_generating_chunks: Vec<ChunkPos> = self
+			.generating_chunks
+			.keys()
+			.copied()
+			.filter(|chunk| !self.should_be_loaded(*chunk))
+			.collect();
+		for chunk in far_generating_chunks {
+			// a result that still arrives gets ignored
+			self.generating_chunks.remove(&chunk);
+			self.workers.cancel(chunk);
+			self.connection.send(ServerMessage::UnloadChunk(chunk))?;
+		}
+		Ok(())
+	}
+
+	/// Handles everything the server sent since the last update
+	fn handle_incoming(&mut self) -> Result<(), CommunicationError> {
+		while let Some(incoming) = self.connection.poll()? {
+			match incoming {
+				Incoming::Response(id, response) => self.handle_response(id, response)?,
+				Incoming::Event(event) => self.handle_event(event),
+			}
+		}
+		Ok(())
+	}
+
+	/// Handles the response to a request that wasn't waited for
+	fn handle_response(
+		&mut self,
+		id: RequestId,
+		response: ServerResponse,
+	) -> Result<(), CommunicationError> {
+		let Some(chunk) = self.chunk_requests.remove(&id) else {
+			// acknowledgements of unloaded chunks
+			return match response {
+				ServerResponse::Ack => Ok(()),
+				response => Err(CommunicationError::UnexpectedMessage(format!(
+					"{:?} in response to request {}",
+					response, id
+				))),
+			};
+		};
+
+		match response {
+			ServerResponse::LoadChunkOK(data) => {
+				if self.should_be_loaded(chunk) {
+					self.workers.submit(Job::Generate {
+						delta: data,
+						genesis: self.world_data.genesis,
+					});
+					self.generating_chunks
+						.insert(chunk, GeneratingChunk::default());
+				} else {
+					// player moved away while it was on its way
+					self.connection.send(ServerMessage::UnloadChunk(chunk))?;
+				}
+				Ok(())
+			}
+			response => Err(CommunicationError::UnexpectedMessage(format!(
+				"{:?} while requesting chunk data",
+				response
+			))),
+		}
+	}
+
+	/// Applies a change made by the server
+	fn handle_event(&mut self, event: ServerEvent) {
+		match event {
+			ServerEvent::BlockChanged(chunk_pos, block_pos, block) => {
+				// changes to chunks that aren't loaded don't matter
+				if let Some(chunk) = self.world_data.chunks.get_mut(&chunk_pos) {
+					chunk.set_block(block_pos, block);
+					self.world_data
+						.remesh_neighbors_of_block(chunk_pos, block_pos);
+					light::update_block(&mut self.world_data, chunk_pos, block_pos);
+				} else if let Some(generating) = self.generating_chunks.get_mut(&chunk_pos) {
+					generating.changes.push((block_pos, block));
+				}
+			}
+			ServerEvent::TimeSync(time) => self.world_data.clock.set(time),
+		}
+	}
+
+	/// Takes in chunks and meshes that workers have finished
+	fn handle_finished_jobs(&mut self) {
+		while let Some(result) = self.workers.try_recv() {
+			match result {
+				JobResult::Generated(mut chunk) => {
+					let pos = chunk.data.pos;
+					// it was unloaded while being generated
+					let Some(generating) = self.generating_chunks.get_mut(&pos) else {
+						continue;
+					};
+					if !generating.changes.is_empty() && !generating.relighting {
+						// relighting a whole chunk is too slow for the main thread
+						for (block_pos, block) in generating.changes.drain(..) {
+							chunk.set_block(block_pos, block);
 						}
+						generating.relighting = true;
+						self.workers.submit(Job::Relight(Box::new(chunk)));
+						continue;
 					}
-				} else {
-					// chunk does not need to be loaded
-					if self.world_data.chunks.contains_key(&chunk) {
-						let _unloaded_chunk = self.world_data.chunks.remove(&chunk);
+					// only once, so chunks that keep changing still get loaded
+					let late_changes = self.generating_chunks.remove(&pos).unwrap().changes;
+					self.world_data.chunks.insert(pos, chunk);
+					// their faces against this chunk are hidden now
+					self.world_data.remesh_neighbors(pos);
+					light::spread_across_borders(&mut self.world_data, pos);
+					// changes since it was relit are applied like to any loaded chunk
+					for (block_pos, block) in late_changes {
+						self.handle_event(ServerEvent::BlockChanged(pos, block_pos, block));
+					}
+				}
+				JobResult::Meshed(pos, meshes) => {
+					if let Some(chunk) = self.world_data.chunks.get_mut(&pos) {
+						chunk.meshes = meshes;
+						chunk.meshing = false;
 					}
 				}
 			}
 		}
-		Ok(())
 	}
 
 	pub fn handle_device_event(&mut self, event: &DeviceEvent) {
@@ -165,19 +447,54 @@ impl Game {
 		}
 	}
 
-	/// Remeshes chunks if they need to be, also binds meshes' local bind groups
+	/// Queues chunks to be remeshed if they need to be, also uploads finished meshes and binds
+	/// their local bind groups
 	pub fn prep_meshes(&mut self, renderer: &Renderer) {
-		for (_pos, chunk) in self.world_data.chunks.iter_mut() {
-			// remeshing chunks
-			if chunk.needs_remesh {
-				chunk.meshes = mesher::generate_chunk_meshes(&chunk.data);
-				chunk.needs_remesh = false;
-			}
-			for mesh in chunk.meshes.iter_mut() {
-				mesh.load_buffers(renderer);
+		// remeshing chunks in the background, the old meshes are kept until then
+		let to_remesh: Vec<ChunkPos> = self
+			.world_data
+			.chunks
+			.iter()
+			.filter(|(_, chunk)| chunk.needs_remesh && !chunk.meshing)
+			.map(|(pos, _)| *pos)
+			.collect();
+		let textures = renderer.block_texture_refs();
+		for pos in to_remesh {
+			let borders = self.world_data.chunk_borders(pos);
+			let chunk = self.world_data.chunks.get_mut(&pos).unwrap();
+			self.workers.submit(Job::Mesh(
+				chunk.data.clone(),
+				chunk.light.clone(),
+				borders,
+				textures.clone(),
+			));
+			chunk.needs_remesh = false;
+			chunk.meshing = true;
+		}
+
+		// keeping translucent quads drawn back to front as the camera moves between blocks
+		let camera = self.world_data.player.pos;
+		let camera_block = BlockPos::from_world_pos(camera);
+		for chunk in self.world_data.chunks.values_mut() {
+			let meshes = &mut chunk.meshes;
+			if meshes.translucent.n_verts == 0
+				|| meshes.translucent_order.sorted_from == Some(camera_block)
+			{
+				continue;
 			}
+			let indices = meshes.translucent_order.sorted_indices(camera);
+			meshes.translucent.reorder_indices(indices, renderer);
+			meshes.translucent_order.sorted_from = Some(camera_block);
+		}
+
+		for chunk in self.world_data.chunks.values_mut() {
+			chunk.meshes.opaque.load_buffers(renderer);
+			chunk.meshes.translucent.load_buffers(renderer);
 			chunk.border_lines.load_buffers(renderer);
 		}
+		if let Some((_, outline)) = self.world_data.target_outline.as_mut() {
+			outline.load_buffers(renderer);
+		}
 	}
 
 	fn open_menu(&mut self) {
@@ -203,24 +520,56 @@ impl Game {
 		self.window.set_cursor_visible(true);
 		self.window.set_cursor_grab(CursorGrabMode::None).unwrap()
 	}
+}
 
-	/// Helper function to send server messages
-	fn send_msg(&mut self, msg: ServerMessage) {
-		// serialize message
-		// send over socket
-		self.socket
-			.send(Message::Binary(msg.encode().into()))
-			.unwrap()
-	}
-
-	/// Helper function to receive server responses
-	fn recv_response(&mut self) -> Result<ServerResponse, ()> {
-		let received = self.socket.read().unwrap();
-		if let Message::Binary(data) = received {
-			Ok(Communication::decode(&data))
-		} else {
-			log::error!("Received unexpected message: {:?}", received);
+/// Makes sure the server accepted the handshake and supports everything this client needs
+///
+/// Returns the server's capabilities
+fn check_welcome(response: ServerResponse) -> Result<Vec<String>, ()> {
+	match response {
+		ServerResponse::Welcome {
+			protocol_version,
+			capabilities,
+		} => {
+			log::debug!(
+				"Server protocol version {}, capabilities: {:?}",
+				protocol_version,
+				capabilities
+			);
+			if !capabilities
+				.iter()
+				.any(|c| c == capabilities::WORLD_MANAGEMENT)
+			{
+				log::error!("Cannot join server: it does not support loading worlds");
+				return Err(());
+			}
+			Ok(capabilities)
+		}
+		ServerResponse::Err(ErrorMessage::IncompatibleProtocolVersion { server, client }) => {
+			log::error!(
+				"Cannot join server: it uses protocol version {} but this client uses version {}, {} needs to be updated",
+				server,
+				client,
+				if server > client { "the client" } else { "the server" }
+			);
+			Err(())
+		}
+		response => {
+			log::error!("Unexpected response to handshake: {:?}", response);
 			Err(())
 		}
 	}
 }
+
+/// Logs an error for a connection that broke in the middle of joining
+fn log_lost_connection(e: CommunicationError) {
+	log::error!("Lost connection to server: {}", e);
+}
+
+/// Picks a seed for a new world
+fn new_seed() -> u32 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_nanos() as u32)
+		.unwrap_or(0)
+}
@@ -0,0 +1,251 @@
+use std::{
+	collections::VecDeque,
+	io,
+	net::TcpStream,
+	sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+	thread::{self, JoinHandle},
+	time::Duration,
+};
+
+use cubegame_lib::communication::*;
+use http::Uri;
+use tungstenite::{connect, stream::MaybeTlsStream, Message, WebSocket};
+
+/// How long the network thread waits for data from the server before checking for requests to send
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// Something received from the server
+#[derive(Debug)]
+pub enum Incoming {
+	/// Response to the request with this id
+	Response(RequestId, ServerResponse),
+	Event(ServerEvent),
+}
+
+/// What the game asks the network thread to do
+enum Outgoing {
+	Request(RequestId, ServerMessage),
+	Close,
+}
+
+/// Connection to a game server
+///
+/// After the handshake the socket is owned by a network thread, so that encoding, decoding and
+/// waiting on the socket never block the game, and requests can be sent without waiting for the
+/// responses to the previous ones
+pub struct ServerConnection {
+	outgoing: Sender<Outgoing>,
+	incoming: Receiver<Result<Incoming, CommunicationError>>,
+	/// Things received while waiting for a specific response, handed out by `poll` first
+	backlog: VecDeque<Incoming>,
+	next_id: RequestId,
+	thread: Option<JoinHandle<()>>,
+}
+impl ServerConnection {
+	/// Connects to a server and sends `hello`, giving the connection and the server's reply to it
+	///
+	/// Only unencrypted `ws://` urls are supported, the network thread needs to set a read timeout
+	/// on the underlying tcp stream
+	pub fn open(
+		server_url: &Uri,
+		hello: ServerMessage,
+	) -> Result<(ServerConnection, ServerResponse), CommunicationError> {
+		if server_url.scheme_str() != Some("ws") {
+			return Err(unsupported_scheme(server_url));
+		}
+		let (mut socket, _addr) =
+			connect(server_url).map_err(|e| CommunicationError::Transport(e.to_string()))?;
+
+		// the handshake happens before anything else, so it's fine to block on it
+		send(&mut socket, &hello)?;
+		let reply = loop {
+			if let Some(reply) = recv(&mut socket)? {
+				break reply;
+			}
+		};
+
+		// reads need to time out every so often to send requests
+		let MaybeTlsStream::Plain(stream) = socket.get_ref() else {
+			return Err(unsupported_scheme(server_url));
+		};
+		stream
+			.set_read_timeout(Some(POLL_INTERVAL))
+			.map_err(|e| CommunicationError::Transport(e.to_string()))?;
+		let (outgoing, outgoing_receiver) = channel();
+		let (incoming_sender, incoming) = channel();
+		let thread = thread::Builder::new()
+			.name("network".to_string())
+			.spawn(move || run_network_thread(socket, outgoing_receiver, incoming_sender))
+			.map_err(|e| CommunicationError::Transport(e.to_string()))?;
+
+		let connection = ServerConnection {
+			outgoing,
+			incoming,
+			backlog: VecDeque::new(),
+			next_id: 0,
+			thread: Some(thread),
+		};
+		Ok((connection, reply))
+	}
+
+	/// Queues a request to be sent, its response comes out of `poll` with the returned id
+	pub fn send(&mut self, msg: ServerMessage) -> Result<RequestId, CommunicationError> {
+		let id = self.next_id;
+		self.next_id = self.next_id.wrapping_add(1);
+		self.outgoing
+			.send(Outgoing::Request(id, msg))
+			.map_err(|_| CommunicationError::Transport("network thread stopped".to_string()))?;
+		Ok(id)
+	}
+
+	/// Gets the next thing received from the server, if there is one
+	pub fn poll(&mut self) -> Result<Option<Incoming>, CommunicationError> {
+		if let Some(incoming) = self.backlog.pop_front() {
+			return Ok(Some(incoming));
+		}
+		match self.incoming.try_recv() {
+			Ok(incoming) => incoming.map(Some),
+			Err(TryRecvError::Empty) => Ok(None),
+			Err(TryRecvError::Disconnected) => Err(CommunicationError::Transport(
+				"network thread stopped".to_string(),
+			)),
+		}
+	}
+
+	/// Sends a request and waits for its response
+	///
+	/// Anything else received in the meantime is kept for `poll`
+	pub fn request(&mut self, msg: ServerMessage) -> Result<ServerResponse, CommunicationError> {
+		let id = self.send(msg)?;
+		loop {
+			let incoming = self.incoming.recv().map_err(|_| {
+				CommunicationError::Transport("network thread stopped".to_string())
+			})??;
+			match incoming {
+				Incoming::Response(response_id, response) if response_id == id => {
+					return Ok(response)
+				}
+				incoming => self.backlog.push_back(incoming),
+			}
+		}
+	}
+
+	/// Closes the connection and waits for the network thread to finish
+	pub fn close(&mut self) {
+		// the thread might already have stopped because of an error
+		let _ = self.outgoing.send(Outgoing::Close);
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+impl Drop for ServerConnection {
+	fn drop(&mut self) {
+		self.close();
+	}
+}
+
+/// Sends requests and receives responses until the connection is closed or fails
+fn run_network_thread(
+	mut socket: Socket,
+	outgoing: Receiver<Outgoing>,
+	incoming: Sender<Result<Incoming, CommunicationError>>,
+) {
+	let result = (|| loop {
+		// sending everything that was queued since last time
+		loop {
+			match outgoing.try_recv() {
+				Ok(Outgoing::Request(id, message)) => {
+					let request = ServerMessage::Request {
+						id,
+						message: Box::new(message),
+					};
+					socket
+						.write(Message::Binary(request.encode()?.into()))
+						.map_err(|e| CommunicationError::Transport(e.to_string()))?;
+				}
+				// game doesn't need the connection anymore
+				Ok(Outgoing::Close) | Err(TryRecvError::Disconnected) => return Ok(()),
+				Err(TryRecvError::Empty) => break,
+			}
+		}
+		socket
+			.flush()
+			.map_err(|e| CommunicationError::Transport(e.to_string()))?;
+
+		let received = match recv(&mut socket)? {
+			Some(ServerResponse::Response { id, response }) => Incoming::Response(id, *response),
+			Some(ServerResponse::Event(event)) => Incoming::Event(event),
+			Some(response) => {
+				return Err(CommunicationError::UnexpectedMessage(format!(
+					"{:?} without a request id",
+					response
+				)))
+			}
+			None => continue,
+		};
+		if incoming.send(Ok(received)).is_err() {
+			// game is gone
+			return Ok(());
+		}
+	})();
+
+	if let Err(e) = result {
+		let _ = incoming.send(Err(e));
+	}
+	// the connection might already be broken, nothing to do about errors here
+	let _ = socket.close(None);
+	let _ = socket.flush();
+}
+
+/// Sends a message directly over the socket
+fn send(socket: &mut Socket, msg: &ServerMessage) -> Result<(), CommunicationError> {
+	socket
+		.send(Message::Binary(msg.encode()?.into()))
+		.map_err(|e| CommunicationError::Transport(e.to_string()))
+}
+
+/// Receives a server response from the socket, if one arrives before the read times out
+fn recv(socket: &mut Socket) -> Result<Option<ServerResponse>, CommunicationError> {
+	loop {
+		let received = match socket.read() {
+			Ok(msg) => msg,
+			Err(tungstenite::Error::Io(e))
+				if matches!(
+					e.kind(),
+					io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+				) =>
+			{
+				return Ok(None);
+			}
+			Err(e) => return Err(CommunicationError::Transport(e.to_string())),
+		};
+		match received {
+			Message::Binary(data) => return ServerResponse::decode(&data).map(Some),
+			// these get handled by tungstenite
+			Message::Ping(_) | Message::Pong(_) => {}
+			Message::Close(frame) => {
+				return Err(CommunicationError::Transport(match frame {
+					Some(frame) => format!("closed by server: {}", frame.reason),
+					None => "closed by server".to_string(),
+				}));
+			}
+			_ => {
+				return Err(CommunicationError::UnexpectedMessage(format!(
+					"{:?}",
+					received
+				)))
+			}
+		}
+	}
+}
+
+/// Error for server urls that aren't plain `ws://` ones
+fn unsupported_scheme(server_url: &Uri) -> CommunicationError {
+	CommunicationError::Transport(format!(
+		"unsupported server url \"{}\", only ws:// is supported",
+		server_url
+	))
+}
@@ -3,7 +3,7 @@ use std::f32::consts::PI;
 use nalgebra::{Matrix4, Point3, Rotation3, Vector3};
 
 use crate::game::controller::PlayerController;
-use cubegame_lib::{ChunkPos, CHUNK_WIDTH};
+use cubegame_lib::{BlockPos, ChunkPos, CHUNK_WIDTH};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Player {
@@ -105,10 +105,7 @@ impl Player {
 
 	/// Gets the chunk that this player is in
 	pub fn chunk_pos(&self) -> ChunkPos {
-		ChunkPos {
-			x: (self.pos[0] / (CHUNK_WIDTH as f32)).floor() as i32,
-			z: (self.pos[2] / (CHUNK_WIDTH as f32)).floor() as i32,
-		}
+		BlockPos::from_world_pos(self.pos).chunk()
 	}
 
 	pub fn view_matrix(&self) -> Matrix4<f32> {
@@ -0,0 +1,185 @@
+use std::{
+	sync::{
+		mpsc::{channel, Receiver, Sender},
+		Arc, Condvar, Mutex,
+	},
+	thread::{self, JoinHandle},
+};
+
+use cubegame_lib::{light::ChunkLight, ChunkData, ChunkDeltaData, ChunkPos, WorldGenesisData};
+
+use super::chunk::LoadedChunk;
+use crate::render::{
+	mesher::{self, ChunkBorders, ChunkMeshes},
+	TextureRefs,
+};
+
+/// Threads kept free for the main and network threads when sizing the pool
+const RESERVED_THREADS: usize = 2;
+
+/// Slow work on a chunk that can be done away from the render thread
+pub enum Job {
+	/// Generate a chunk's terrain and apply its delta
+	Generate {
+		delta: ChunkDeltaData,
+		genesis: WorldGenesisData,
+	},
+	/// Recompute the light of a chunk whose blocks changed after it was generated
+	Relight(Box<LoadedChunk>),
+	/// Build a chunk's meshes (they still need to be uploaded to the gpu afterwards)
+	Mesh(Box<ChunkData>, ChunkLight, ChunkBorders, TextureRefs),
+}
+impl Job {
+	fn chunk_pos(&self) -> ChunkPos {
+		match self {
+			Job::Generate { delta, .. } => delta.pos,
+			Job::Relight(chunk) => chunk.data.pos,
+			Job::Mesh(data, ..) => data.pos,
+		}
+	}
+
+	fn run(self) -> JobResult {
+		match self {
+			Job::Generate { delta, genesis } => {
+				JobResult::Generated(LoadedChunk::load_from_delta(delta, &genesis))
+			}
+			Job::Relight(mut chunk) => {
+				chunk.light = ChunkLight::compute(&chunk.data);
+				JobResult::Generated(*chunk)
+			}
+			Job::Mesh(data, light, borders, textures) => JobResult::Meshed(
+				data.pos,
+				mesher::generate_chunk_mesh(&*data, &light, &borders, &textures),
+			),
+		}
+	}
+}
+
+/// A finished job
+pub enum JobResult {
+	Generated(LoadedChunk),
+	Meshed(ChunkPos, ChunkMeshes),
+}
+
+/// Jobs waiting for a worker
+struct Queue {
+	jobs: Vec<Job>,
+	/// Jobs for chunks closest to this one are done first
+	focus: ChunkPos,
+	shutting_down: bool,
+}
+impl Queue {
+	/// Takes the job closest to the focus
+	fn take_closest(&mut self) -> Option<Job> {
+		let focus = self.focus;
+		let (i, _) = self.jobs.iter().enumerate().min_by_key(|(_, job)| {
+			let pos = job.chunk_pos();
+			(pos.x - focus.x).pow(2) + (pos.z - focus.z).pow(2)
+		})?;
+		Some(self.jobs.swap_remove(i))
+	}
+}
+
+struct Shared {
+	queue: Mutex<Queue>,
+	job_available: Condvar,
+}
+
+/// Threads that generate and mesh chunks in the background
+pub struct WorkerPool {
+	shared: Arc<Shared>,
+	results: Receiver<JobResult>,
+	threads: Vec<JoinHandle<()>>,
+}
+impl WorkerPool {
+	/// Starts a worker for every core that isn't needed by the rest of the game
+	pub fn new() -> WorkerPool {
+		let n_threads = thread::available_parallelism()
+			.map(|n| n.get().saturating_sub(RESERVED_THREADS))
+			.unwrap_or(1)
+			.max(1);
+
+		let shared = Arc::new(Shared {
+			queue: Mutex::new(Queue {
+				jobs: Vec::new(),
+				focus: ChunkPos { x: 0, z: 0 },
+				shutting_down: false,
+			}),
+			job_available: Condvar::new(),
+		});
+		let (result_sender, results) = channel();
+		let threads = (0..n_threads)
+			.map(|i| {
+				let shared = shared.clone();
+				let results = result_sender.clone();
+				thread::Builder::new()
+					.name(format!("chunk worker {}", i))
+					.spawn(move || run_worker(&shared, results))
+					.expect("failed to start chunk worker thread")
+			})
+			.collect();
+		log::debug!("Started {} chunk worker threads", n_threads);
+
+		WorkerPool {
+			shared,
+			results,
+			threads,
+		}
+	}
+
+	/// Queues a job for the next free worker
+	pub fn submit(&self, job: Job) {
+		self.shared.queue.lock().unwrap().jobs.push(job);
+		self.shared.job_available.notify_one();
+	}
+
+	/// Makes jobs closest to this chunk get done first
+	pub fn set_focus(&self, pos: ChunkPos) {
+		self.shared.queue.lock().unwrap().focus = pos;
+	}
+
+	/// Drops every queued job for a chunk, jobs that have already started still finish
+	pub fn cancel(&self, pos: ChunkPos) {
+		self.shared
+			.queue
+			.lock()
+			.unwrap()
+			.jobs
+			.retain(|job| job.chunk_pos() != pos);
+	}
+
+	/// Gets a finished job's result, if there is one
+	pub fn try_recv(&self) -> Option<JobResult> {
+		self.results.try_recv().ok()
+	}
+}
+impl Drop for WorkerPool {
+	fn drop(&mut self) {
+		self.shared.queue.lock().unwrap().shutting_down = true;
+		self.shared.job_available.notify_all();
+		for thread in self.threads.drain(..) {
+			let _ = thread.join();
+		}
+	}
+}
+
+fn run_worker(shared: &Shared, results: Sender<JobResult>) {
+	loop {
+		let job = {
+			let mut queue = shared.queue.lock().unwrap();
+			loop {
+				if queue.shutting_down {
+					return;
+				}
+				if let Some(job) = queue.take_closest() {
+					break job;
+				}
+				queue = shared.job_available.wait(queue).unwrap();
+			}
+		};
+		if results.send(job.run()).is_err() {
+			// pool is gone
+			return;
+		}
+	}
+}
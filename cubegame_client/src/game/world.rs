@@ -1,22 +1,156 @@
 use std::collections::HashMap;
 
-use cubegame_lib::ChunkPos;
+use cubegame_lib::light::{LightChannel, LightWorld};
+use cubegame_lib::raycast::{self, RaycastHit};
+use cubegame_lib::time::{WorldClock, WorldTime};
+use cubegame_lib::{
+	BlockData, BlockPos, ChunkPos, Direction, LocalBlockPos, WorldGenesisData, CHUNK_WIDTH,
+};
 
 use crate::game::chunk::LoadedChunk;
 use crate::game::player::Player;
+use crate::render::mesher::{self, ChunkBorders};
+use crate::render::objects::Lines;
+
+/// How far away the player can reach blocks from
+pub const PLAYER_REACH: f32 = 8.0;
+
+/// Directions of the chunks next to a chunk
+const HORIZONTAL_DIRECTIONS: [Direction; 4] = [
+	Direction::PosX,
+	Direction::NegX,
+	Direction::PosZ,
+	Direction::NegZ,
+];
 
 /// Data about the loaded world
 pub struct WorldData {
+	/// Data the server's world was generated with, used to regenerate terrain locally
+	pub genesis: WorldGenesisData,
 	/// client's player
 	pub player: Player,
 	/// Loaded chunks
 	pub chunks: HashMap<ChunkPos, LoadedChunk>,
+	/// Time in the world, kept in step with the server's
+	pub clock: WorldClock,
+	/// Outline around the block the player is looking at, and where that block is
+	pub target_outline: Option<(BlockPos, Lines)>,
 }
 impl WorldData {
-	pub fn new() -> Self {
+	pub fn new(genesis: WorldGenesisData, time: WorldTime) -> Self {
 		WorldData {
+			genesis,
 			player: Player::new(),
 			chunks: HashMap::new(),
+			clock: WorldClock::new(time),
+			target_outline: None,
+		}
+	}
+
+	/// Collects the blocks and light of loaded neighbouring chunks that touch a chunk, for meshing it
+	pub fn chunk_borders(&self, pos: ChunkPos) -> ChunkBorders {
+		let mut borders = ChunkBorders::default();
+		for dir in HORIZONTAL_DIRECTIONS {
+			if let Some(neighbor) = self.chunks.get(&neighbor_chunk(pos, dir)) {
+				borders.set_side(dir, &neighbor.data, &neighbor.light);
+			}
+		}
+		borders
+	}
+
+	/// Makes every loaded chunk next to a chunk get remeshed, for when that chunk is loaded or
+	/// unloaded
+	pub fn remesh_neighbors(&mut self, pos: ChunkPos) {
+		for dir in HORIZONTAL_DIRECTIONS {
+			if let Some(neighbor) = self.chunks.get_mut(&neighbor_chunk(pos, dir)) {
+				neighbor.needs_remesh = true;
+			}
 		}
 	}
+
+	/// Gets the block the player is looking at, if there is one within reach
+	pub fn targeted_block(&self) -> Option<RaycastHit> {
+		raycast::raycast(
+			self.player.pos,
+			self.player.facing_vec().into(),
+			PLAYER_REACH,
+			|chunk, pos| self.block(chunk, pos),
+		)
+	}
+
+	/// Moves the target outline to the block the player is looking at now
+	pub fn update_target_outline(&mut self) {
+		let target = self.targeted_block().map(|hit| hit.pos);
+		if target != self.target_outline.as_ref().map(|(pos, _)| *pos) {
+			self.target_outline =
+				target.map(|pos| (pos, mesher::generate_block_outline_lines(pos)));
+		}
+	}
+
+	/// Makes the chunks next to a changed block get remeshed, if the block is on the chunk's edge
+	pub fn remesh_neighbors_of_block(&mut self, chunk: ChunkPos, block: LocalBlockPos) {
+		let last = CHUNK_WIDTH as u8 - 1;
+		for dir in HORIZONTAL_DIRECTIONS {
+			let on_edge = match dir {
+				Direction::PosX => block.x() == last,
+				Direction::NegX => block.x() == 0,
+				Direction::PosZ => block.z() == last,
+				_ => block.z() == 0,
+			};
+			if !on_edge {
+				continue;
+			}
+			if let Some(neighbor) = self.chunks.get_mut(&neighbor_chunk(chunk, dir)) {
+				neighbor.needs_remesh = true;
+			}
+		}
+	}
+}
+
+impl LightWorld for WorldData {
+	fn block(&self, chunk: ChunkPos, pos: LocalBlockPos) -> Option<BlockData> {
+		self.chunks.get(&chunk).map(|chunk| chunk.data.get(pos))
+	}
+
+	fn light(&self, chunk: ChunkPos, pos: LocalBlockPos, channel: LightChannel) -> u8 {
+		self.chunks
+			.get(&chunk)
+			.map_or(0, |chunk| chunk.light.get(pos, channel))
+	}
+
+	/// Chunks whose light changes get remeshed, along with neighbours that can see the change
+	fn set_light(&mut self, chunk: ChunkPos, pos: LocalBlockPos, channel: LightChannel, level: u8) {
+		let Some(loaded) = self.chunks.get_mut(&chunk) else {
+			return;
+		};
+		if loaded.light.get(pos, channel) == level {
+			return;
+		}
+		loaded.light.set(pos, channel, level);
+		loaded.needs_remesh = true;
+		self.remesh_neighbors_of_block(chunk, pos);
+	}
+}
+
+/// Position of the chunk next to a chunk in a horizontal direction
+fn neighbor_chunk(pos: ChunkPos, dir: Direction) -> ChunkPos {
+	match dir {
+		Direction::PosX => ChunkPos {
+			x: pos.x + 1,
+			z: pos.z,
+		},
+		Direction::NegX => ChunkPos {
+			x: pos.x - 1,
+			z: pos.z,
+		},
+		Direction::PosZ => ChunkPos {
+			x: pos.x,
+			z: pos.z + 1,
+		},
+		Direction::NegZ => ChunkPos {
+			x: pos.x,
+			z: pos.z - 1,
+		},
+		Direction::PosY | Direction::NegY => pos,
+	}
 }
@@ -1,17 +1,29 @@
 mod application;
 pub mod game;
 pub mod render;
+mod settings;
 
+use std::path::Path;
+use std::sync::Arc;
+
+use cubegame_lib::blocks::BlockRegistry;
+use cubegame_server::ServerState;
 use winit::{
 	event_loop::{ControlFlow, EventLoop},
 	window::Window,
 };
 
 use application::ApplicationState;
+use settings::ClientSettings;
 
 pub const INTEGRATED_SERVER_PORT: u16 = 5005;
+/// Directory the integrated server saves its worlds in
+const INTEGRATED_SERVER_SAVE_DIR: &str = "./saves";
+/// File the integrated server reads its block types from
+const BLOCK_REGISTRY_PATH: &str = "./assets/blocks.ron";
 
 const CHUNK_BORDER_COLOR: [f32; 3] = [1.0, 0.0, 0.0];
+const BLOCK_OUTLINE_COLOR: [f32; 3] = [0.0, 0.0, 0.0];
 
 pub fn run_client() {
 	env_logger::init();
@@ -25,13 +37,31 @@ pub fn run_client() {
 		.with_title("Cubegame")
 		.with_active(true);
 
-	let mut app = ApplicationState::new(window_attributes);
+	let settings = ClientSettings::from_env();
+	log::debug!("Settings: {:?}", settings);
+	let mut app = ApplicationState::new(window_attributes, settings);
 
 	// spawning integrated server
+	let blocks = match BlockRegistry::load(Path::new(BLOCK_REGISTRY_PATH)) {
+		Ok(blocks) => blocks,
+		Err(e) => {
+			log::error!("Failed to load block types: {}", e);
+			return;
+		}
+	};
+	let server_state = match ServerState::with_blocks(INTEGRATED_SERVER_SAVE_DIR, blocks) {
+		Ok(state) => Arc::new(state),
+		Err(()) => {
+			log::error!("Failed to create integrated server");
+			return;
+		}
+	};
+	let integrated_server_state = server_state.clone();
 	match std::thread::Builder::new()
 		.name("integrated_server".to_string())
 		.spawn(|| {
-			if cubegame_server::run_server(INTEGRATED_SERVER_PORT).is_err() {
+			if cubegame_server::run_server(INTEGRATED_SERVER_PORT, integrated_server_state).is_err()
+			{
 				log::error!("Integrated server exited with failure");
 			}
 		}) {
@@ -44,4 +74,7 @@ pub fn run_client() {
 
 	log::info!("Starting");
 	event_loop.run_app(&mut app).expect("Event loop error");
+
+	// saving the world before the integrated server gets killed
+	server_state.flush();
 }
@@ -0,0 +1,83 @@
+use cubegame_lib::time::{WorldTime, NIGHT_DAYLIGHT};
+use cubegame_lib::CHUNK_WIDTH;
+use nalgebra::Matrix4;
+
+/// Sky colours at noon: [horizon, zenith]
+const DAY_SKY: [[f32; 3]; 2] = [[0.62, 0.78, 1.0], [0.22, 0.45, 0.92]];
+/// Sky colours at midnight: [horizon, zenith]
+const NIGHT_SKY: [[f32; 3]; 2] = [[0.03, 0.04, 0.08], [0.005, 0.006, 0.02]];
+/// Horizon colour while the sun is rising or setting
+const SUNSET_HORIZON: [f32; 3] = [0.95, 0.45, 0.2];
+/// Fraction of the fog's range where it starts to show
+const FOG_START: f32 = 0.6;
+
+/// Per frame data about the world's surroundings (sky, sun and camera), shared by the render passes
+///
+/// Matches the `Environment` struct in the shaders
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct EnvironmentUniform {
+	/// Inverse of the camera's view projection matrix, for turning screen positions into view rays
+	pub inv_view_proj: [[f32; 4]; 4],
+	/// Camera position (w is unused)
+	pub camera_pos: [f32; 4],
+	/// Direction towards the sun (w is unused)
+	pub sun_dir: [f32; 4],
+	/// Colour of the sky at the horizon (w is unused)
+	pub horizon_color: [f32; 4],
+	/// Colour of the sky straight up (w is unused)
+	pub zenith_color: [f32; 4],
+	/// Colour that far away blocks fade into (w is unused)
+	pub fog_color: [f32; 4],
+	/// How bright sky light is, from 0 to 1
+	pub sky_light: f32,
+	/// Horizontal distance from the camera where fog starts
+	pub fog_start: f32,
+	/// Horizontal distance from the camera where blocks are completely fogged
+	pub fog_end: f32,
+	_padding: f32,
+}
+impl EnvironmentUniform {
+	/// `render_distance` is the radius of loaded chunks around the camera
+	pub fn new(
+		time: WorldTime,
+		view_proj: &Matrix4<f32>,
+		camera_pos: [f32; 3],
+		render_distance: u32,
+	) -> Self {
+		let inv_view_proj = view_proj.try_inverse().unwrap_or_else(Matrix4::identity);
+		let sun_dir = time.sun_direction();
+
+		// how far from night to day it is
+		let daylight = time.daylight();
+		let day = (daylight - NIGHT_DAYLIGHT) / (1.0 - NIGHT_DAYLIGHT);
+		let mut horizon = lerp(NIGHT_SKY[0], DAY_SKY[0], day);
+		let zenith = lerp(NIGHT_SKY[1], DAY_SKY[1], day);
+
+		// the horizon glows while the sun is close to it
+		let glow = (1.0 - sun_dir[1].abs() / 0.3).clamp(0.0, 1.0);
+		horizon = lerp(horizon, SUNSET_HORIZON, glow * 0.7);
+
+		// chunks are loaded by their distance from the camera's chunk,
+		// so one chunk less is the furthest that is always loaded in every direction
+		let fog_end = render_distance.saturating_sub(1) as f32 * CHUNK_WIDTH as f32;
+
+		EnvironmentUniform {
+			inv_view_proj: inv_view_proj.into(),
+			camera_pos: [camera_pos[0], camera_pos[1], camera_pos[2], 1.0],
+			sun_dir: [sun_dir[0], sun_dir[1], sun_dir[2], 0.0],
+			horizon_color: [horizon[0], horizon[1], horizon[2], 1.0],
+			zenith_color: [zenith[0], zenith[1], zenith[2], 1.0],
+			// fading into the horizon hides where the world ends
+			fog_color: [horizon[0], horizon[1], horizon[2], 1.0],
+			sky_light: daylight,
+			fog_start: fog_end * FOG_START,
+			fog_end,
+			_padding: 0.0,
+		}
+	}
+}
+
+fn lerp(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+	[0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+}
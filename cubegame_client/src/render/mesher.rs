@@ -1,264 +1,624 @@
 use crate::render::objects::lines::LineVert;
 use crate::render::objects::mesh::vert::MeshVert;
 use crate::render::objects::{Lines, Mesh};
-use crate::render::texture::atlas::TextureAtlasKey;
-use crate::CHUNK_BORDER_COLOR;
-use cubegame_lib::blocks::{BlockTextureLayout, BlockType};
+use crate::render::texture::{atlas::TextureAtlasKey, TextureRef, TextureRefs};
+use crate::{BLOCK_OUTLINE_COLOR, CHUNK_BORDER_COLOR};
+use cubegame_lib::blocks::{BlockOpacity, BlockTextureLayout, BlockType};
+use cubegame_lib::light::{ChunkLight, LightChannel, MAX_LIGHT};
 use cubegame_lib::{
-	blocks::AIR_BLOCK_ID, ChunkData, Direction, Directions, LocalBlockPos, CHUNK_WIDTH,
+	BlockData, BlockPos, ChunkData, ChunkPos, Direction, Directions, LocalBlockPos, CHUNK_WIDTH,
 	WORLD_HEIGHT,
 };
 use std::collections::HashMap;
 
-/// Turns a chunk into meshes
-///
-/// Current implementation creates one conjoined objects per texture
-/// TODO remove redundant rendering on the sides of the chunk
-/// TODO randomize texture orientation
-pub fn generate_chunk_meshes(data: &ChunkData) -> Vec<Mesh> {
-	let chunk_pos = data.pos;
-	let mut total_verts = 0;
-	let mut total_tris = 0;
+/// Number of blocks on one side of a chunk
+const BLOCKS_PER_SIDE: usize = CHUNK_WIDTH * WORLD_HEIGHT;
 
-	// a list of faces at each block pos for each texture
-	let mut meshes: HashMap<TextureAtlasKey, Vec<(LocalBlockPos, Directions)>> = HashMap::new();
+/// Brightness of a vert for each ambient occlusion level, from fully occluded to not occluded
+const AO_BRIGHTNESS: [f32; 4] = [0.45, 0.65, 0.82, 1.0];
 
-	for (i, block) in data.blocks.iter().enumerate() {
-		if block.type_id == AIR_BLOCK_ID {
-			continue;
-		}
-		let local_pos = LocalBlockPos::from_index(i);
-
-		// optimization: choosing which faces to render
-		let mut faces = Directions::all_flags();
-		// for each direction, check if there is a neighbor in this chunk, and check if that neighbor is air
-		if let Some(neighbor) = local_pos.get_neighbor(Direction::PosX) {
-			if data.blocks[neighbor.to_index()].type_id != AIR_BLOCK_ID {
-				faces ^= Directions::PosX;
-			}
-		}
-		if let Some(neighbor) = local_pos.get_neighbor(Direction::NegX) {
-			if data.blocks[neighbor.to_index()].type_id != AIR_BLOCK_ID {
-				faces ^= Directions::NegX;
-			}
-		}
-		if let Some(neighbor) = local_pos.get_neighbor(Direction::PosY) {
-			if data.blocks[neighbor.to_index()].type_id != AIR_BLOCK_ID {
-				faces ^= Directions::PosY;
-			}
-		}
-		if let Some(neighbor) = local_pos.get_neighbor(Direction::NegY) {
-			if data.blocks[neighbor.to_index()].type_id != AIR_BLOCK_ID {
-				faces ^= Directions::NegY;
-			}
-		}
-		if let Some(neighbor) = local_pos.get_neighbor(Direction::PosZ) {
-			if data.blocks[neighbor.to_index()].type_id != AIR_BLOCK_ID {
-				faces ^= Directions::PosZ;
-			}
-		}
-		if let Some(neighbor) = local_pos.get_neighbor(Direction::NegZ) {
-			if data.blocks[neighbor.to_index()].type_id != AIR_BLOCK_ID {
-				faces ^= Directions::NegZ;
-			}
-		}
+/// How far block outlines sit outside the block, so its faces don't cover them
+const OUTLINE_OFFSET: f32 = 0.002;
 
-		let block_type = BlockType::from_id(block.type_id);
-		let mut insert_faces = |tex_key, faces| {
-			if meshes.contains_key(&tex_key) {
-				meshes.get_mut(&tex_key).unwrap().push((local_pos, faces));
-			} else {
-				meshes.insert(tex_key, vec![(local_pos, faces)]);
-			}
+/// Light levels of a block: [sky, block]
+type LightLevels = [u8; 2];
+/// Blocks along one side of a chunk, with their light
+type BorderSide = Box<[(BlockData, LightLevels)]>;
+/// Light levels used for blocks whose light isn't known, like above the world or in unloaded chunks
+const UNKNOWN_LIGHT: LightLevels = [MAX_LIGHT, 0];
+
+/// Blocks of neighbouring chunks that touch the sides of a chunk, so that faces hidden by them can
+/// be culled, and faces next to them can be lit
+///
+/// Sides without a loaded neighbour are `None`, faces facing them are kept
+#[derive(Clone, Default)]
+pub struct ChunkBorders {
+	/// PosX, NegX, PosZ and NegZ sides, indexed by y then by whichever of x or z runs along the side
+	sides: [Option<BorderSide>; 4],
+}
+impl ChunkBorders {
+	/// Copies the blocks of `neighbor` that touch the side of the chunk in direction `dir`, and
+	/// their light
+	pub fn set_side(&mut self, dir: Direction, neighbor: &ChunkData, light: &ChunkLight) {
+		let Some(side) = side_index(dir) else {
+			return;
 		};
-		match block_type.texture_layout {
-			// dont care about orientation when its a uniform block
-			BlockTextureLayout::Uniform(_) => {
-				insert_faces(TextureAtlasKey::Block(block.type_id), faces);
-			}
-			// if its not uniform, face matters
-			_ => {
-				for (_, face) in Directions::flags() {
-					if faces.contains(*face) {
-						insert_faces(
-							TextureAtlasKey::BlockFace(block.type_id, (*face).into()),
-							*face,
-						);
-					}
-				}
-			}
+		let last = CHUNK_WIDTH as u8 - 1;
+		let blocks = (0..BLOCKS_PER_SIDE)
+			.map(|i| {
+				let y = (i / CHUNK_WIDTH) as u8;
+				let along = (i % CHUNK_WIDTH) as u8;
+				// the neighbour's opposite side is the one touching this chunk
+				let pos = match dir {
+					Direction::PosX => LocalBlockPos::new(0, y, along),
+					Direction::NegX => LocalBlockPos::new(last, y, along),
+					Direction::PosZ => LocalBlockPos::new(along, y, 0),
+					_ => LocalBlockPos::new(along, y, last),
+				};
+				let levels = [
+					light.get(pos, LightChannel::Sky),
+					light.get(pos, LightChannel::Block),
+				];
+				(neighbor.get(pos), levels)
+			})
+			.collect();
+		self.sides[side] = Some(blocks);
+	}
+
+	/// Gets the block outside the chunk next to `pos` in direction `dir` and its light, if it is
+	/// known
+	fn get(&self, pos: LocalBlockPos, dir: Direction) -> Option<(BlockData, LightLevels)> {
+		let side = self.sides[side_index(dir)?].as_ref()?;
+		let along = match dir {
+			Direction::PosX | Direction::NegX => pos.z(),
+			_ => pos.x(),
+		};
+		Some(side[pos.y() as usize * CHUNK_WIDTH + along as usize])
+	}
+}
+
+/// Index of a horizontal direction in `ChunkBorders::sides`
+fn side_index(dir: Direction) -> Option<usize> {
+	match dir {
+		Direction::PosX => Some(0),
+		Direction::NegX => Some(1),
+		Direction::PosZ => Some(2),
+		Direction::NegZ => Some(3),
+		Direction::PosY | Direction::NegY => None,
+	}
+}
+
+/// A rectangle of faces in one layer of a chunk that all point the same way and share a texture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Quad {
+	dir: Direction,
+	/// Position along the axis the faces point along
+	layer: usize,
+	/// Corner with the lowest coordinates, on the face's u and v axes (see `face_axes`)
+	u: usize,
+	v: usize,
+	/// Size in blocks along the u and v axes
+	width: usize,
+	height: usize,
+	/// Whether the texture is turned a quarter turn, so it runs along the u axis instead of v
+	rotated: bool,
+	shading: FaceShading,
+}
+
+/// How the corners of a face are shaded, in the same order as `unit_face_verts`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FaceShading {
+	/// Ambient occlusion level of each corner
+	ao: [u8; 4],
+	/// Light levels of each corner, in quarters of a level since they're averaged from the blocks
+	/// around the corner
+	light: [LightLevels; 4],
+}
+impl FaceShading {
+	/// Whether every corner is shaded the same, so nothing is lost by stretching it over a bigger
+	/// quad
+	fn is_uniform(&self) -> bool {
+		self.ao.iter().all(|&level| level == self.ao[0])
+			&& self.light.iter().all(|&levels| levels == self.light[0])
+	}
+}
+
+/// Meshes of a chunk, one for each render pass
+pub struct ChunkMeshes {
+	/// Opaque and cutout blocks, drawn first
+	pub opaque: Mesh,
+	/// Translucent blocks, blended over everything else
+	pub translucent: Mesh,
+	/// Where the translucent mesh's quads are, for drawing them back to front
+	pub translucent_order: QuadOrder,
+}
+impl ChunkMeshes {
+	pub fn empty() -> ChunkMeshes {
+		ChunkMeshes {
+			opaque: Mesh::empty(),
+			translucent: Mesh::empty(),
+			translucent_order: QuadOrder::default(),
 		}
 	}
+}
+
+/// Where the quads of a mesh are, so they can be drawn back to front from wherever the camera is
+#[derive(Default)]
+pub struct QuadOrder {
+	/// Center of each quad in the world, with the indices of its two tris
+	quads: Vec<([f32; 3], [u32; 6])>,
+	/// Block the camera was in when the mesh's indices were last sorted, `None` until they are
+	pub sorted_from: Option<BlockPos>,
+}
+impl QuadOrder {
+	/// Indices of every quad, from the quad furthest from `camera` to the closest
+	pub fn sorted_indices(&self, camera: [f32; 3]) -> Vec<u32> {
+		let mut quads: Vec<(f32, &[u32; 6])> = self
+			.quads
+			.iter()
+			.map(|(center, indices)| {
+				let distance: f32 = (0..3).map(|i| (center[i] - camera[i]).powi(2)).sum();
+				(distance, indices)
+			})
+			.collect();
+		quads.sort_unstable_by(|(a, _), (b, _)| b.total_cmp(a));
+		quads
+			.into_iter()
+			.flat_map(|(_, indices)| *indices)
+			.collect()
+	}
+}
 
+/// Blocks of a chunk, as the mesher reads them
+///
+/// Implemented by `ChunkData`, other storage layouts only implement it to be compared against it in
+/// benchmarks
+pub trait ChunkBlocks {
+	fn pos(&self) -> ChunkPos;
+	fn get(&self, pos: LocalBlockPos) -> BlockData;
+	/// Whether the section at `y` (in blocks) is known to be all air, so it can be skipped
+	fn is_air_section(&self, _y: usize) -> bool {
+		false
+	}
+}
+impl ChunkBlocks for ChunkData {
+	fn pos(&self) -> ChunkPos {
+		self.pos
+	}
+
+	fn get(&self, pos: LocalBlockPos) -> BlockData {
+		ChunkData::get(self, pos)
+	}
+
+	fn is_air_section(&self, y: usize) -> bool {
+		ChunkData::is_air_section(self, y)
+	}
+}
+
+/// Turns a chunk into meshes
+///
+/// Neighbouring faces with the same texture and shading are merged into bigger quads, each vert
+/// knows where its texture is so the whole chunk is drawn at once
+/// TODO randomize texture orientation
+pub fn generate_chunk_mesh(
+	data: &impl ChunkBlocks,
+	light: &ChunkLight,
+	borders: &ChunkBorders,
+	textures: &TextureRefs,
+) -> ChunkMeshes {
+	let chunk_pos = data.pos();
 	let pos_offset = [
 		chunk_pos.x as f32 * CHUNK_WIDTH as f32,
 		0.0,
 		chunk_pos.z as f32 * CHUNK_WIDTH as f32,
 	];
-	// now we gotta turn each list of faces into a mesh
-	let meshes: Vec<Mesh> = meshes
+	let (translucent, opaque): (Vec<_>, Vec<_>) = generate_quads(data, light, borders, true)
 		.into_iter()
-		.map(|(tex_key, faces_list)| {
-			let mut verts: Vec<MeshVert> = Vec::with_capacity(24);
-			let mut indices: Vec<u32> = Vec::with_capacity(36);
-
-			for (pos, faces) in faces_list.into_iter() {
-				// Helper function to set up verts and indices for each face
-				let mut add_face = |mut new_verts: [MeshVert; 4]| {
-					let n_verts = verts.len() as u32;
-					indices.extend_from_slice(&[
-						n_verts,
-						n_verts + 1,
-						n_verts + 2,
-						n_verts,
-						n_verts + 2,
-						n_verts + 3,
-					]);
-					for v in new_verts.iter_mut() {
-						v.pos[0] += pos.x() as f32;
-						v.pos[1] += pos.y() as f32;
-						v.pos[2] += pos.z() as f32;
-					}
-					verts.extend_from_slice(&new_verts);
-				};
+		.partition(|(tex_key, _)| is_translucent(*tex_key));
 
-				if faces.contains(Directions::PosX) {
-					// right face
-
-					add_face([
-						MeshVert {
-							pos: [1.0, 0.0, 1.0],
-							tex_coord: [0.0, 1.0],
-						},
-						MeshVert {
-							pos: [1.0, 0.0, 0.0],
-							tex_coord: [1.0, 1.0],
-						},
-						MeshVert {
-							pos: [1.0, 1.0, 0.0],
-							tex_coord: [1.0, 0.0],
-						},
-						MeshVert {
-							pos: [1.0, 1.0, 1.0],
-							tex_coord: [0.0, 0.0],
-						},
-					]);
-				}
-				if faces.contains(Directions::NegX) {
-					add_face([
-						MeshVert {
-							pos: [0.0, 0.0, 0.0],
-							tex_coord: [0.0, 1.0],
-						},
-						MeshVert {
-							pos: [0.0, 0.0, 1.0],
-							tex_coord: [1.0, 1.0],
-						},
-						MeshVert {
-							pos: [0.0, 1.0, 1.0],
-							tex_coord: [1.0, 0.0],
-						},
-						MeshVert {
-							pos: [0.0, 1.0, 0.0],
-							tex_coord: [0.0, 0.0],
-						},
-					]);
-				}
-				if faces.contains(Directions::PosY) {
-					add_face([
-						MeshVert {
-							pos: [0.0, 1.0, 1.0],
-							tex_coord: [0.0, 1.0],
-						},
-						MeshVert {
-							pos: [1.0, 1.0, 1.0],
-							tex_coord: [1.0, 1.0],
-						},
-						MeshVert {
-							pos: [1.0, 1.0, 0.0],
-							tex_coord: [1.0, 0.0],
-						},
-						MeshVert {
-							pos: [0.0, 1.0, 0.0],
-							tex_coord: [0.0, 0.0],
-						},
-					]);
-				}
-				if faces.contains(Directions::NegY) {
-					add_face([
-						MeshVert {
-							pos: [0.0, 0.0, 0.0],
-							tex_coord: [0.0, 1.0],
-						},
-						MeshVert {
-							pos: [1.0, 0.0, 0.0],
-							tex_coord: [1.0, 1.0],
-						},
-						MeshVert {
-							pos: [1.0, 0.0, 1.0],
-							tex_coord: [1.0, 0.0],
-						},
-						MeshVert {
-							pos: [0.0, 0.0, 1.0],
-							tex_coord: [0.0, 0.0],
-						},
-					]);
-				}
-				if faces.contains(Directions::PosZ) {
-					add_face([
-						MeshVert {
-							pos: [0.0, 0.0, 1.0],
-							tex_coord: [0.0, 1.0],
-						},
-						MeshVert {
-							pos: [1.0, 0.0, 1.0],
-							tex_coord: [1.0, 1.0],
-						},
-						MeshVert {
-							pos: [1.0, 1.0, 1.0],
-							tex_coord: [1.0, 0.0],
-						},
-						MeshVert {
-							pos: [0.0, 1.0, 1.0],
-							tex_coord: [0.0, 0.0],
-						},
-					]);
+	let (translucent, translucent_order) = build_mesh(translucent, textures, pos_offset);
+	ChunkMeshes {
+		opaque: build_mesh(opaque, textures, pos_offset).0,
+		translucent,
+		translucent_order,
+	}
+}
+
+/// Whether a texture belongs to a translucent block, and goes in the translucent mesh
+fn is_translucent(key: TextureAtlasKey) -> bool {
+	let type_id = match key {
+		TextureAtlasKey::Block(type_id, _) | TextureAtlasKey::BlockFace(type_id, ..) => type_id,
+		TextureAtlasKey::Null => return false,
+	};
+	BlockType::from_id(type_id).opacity == BlockOpacity::Translucent
+}
+
+/// Puts quads into a mesh at `pos_offset`, along with where each of them ended up
+fn build_mesh(
+	quads: Vec<(TextureAtlasKey, Vec<Quad>)>,
+	textures: &TextureRefs,
+	pos_offset: [f32; 3],
+) -> (Mesh, QuadOrder) {
+	let n_quads: usize = quads.iter().map(|(_, quads)| quads.len()).sum();
+
+	let mut verts: Vec<MeshVert> = Vec::with_capacity(n_quads * 4);
+	let mut indices: Vec<u32> = Vec::with_capacity(n_quads * 6);
+	let mut quad_order = QuadOrder::default();
+	for (tex_key, quads) in quads {
+		let texture = textures.get(tex_key);
+		for quad in quads {
+			let n_verts = verts.len() as u32;
+			// splitting the quad along the brighter diagonal, otherwise occlusion of a single corner
+			// gets stretched across the quad
+			let [a0, a1, a2, a3] = quad.shading.ao;
+			let order = if a0 + a2 < a1 + a3 {
+				[1, 2, 3, 1, 3, 0]
+			} else {
+				[0, 1, 2, 0, 2, 3]
+			};
+			let quad_indices = order.map(|i| n_verts + i);
+			let quad_verts = quad_verts(quad, texture);
+			let center = [0, 1, 2].map(|axis| {
+				pos_offset[axis] + quad_verts.iter().map(|v| v.pos[axis]).sum::<f32>() / 4.0
+			});
+			indices.extend(quad_indices);
+			verts.extend_from_slice(&quad_verts);
+			quad_order.quads.push((center, quad_indices));
+		}
+	}
+	(Mesh::new(verts, indices, pos_offset), quad_order)
+}
+
+/// Finds every visible face in a chunk, grouped by texture
+///
+/// With `merge` set, faces are greedily merged into the biggest rectangles possible, otherwise
+/// every face is its own quad
+fn generate_quads(
+	data: &impl ChunkBlocks,
+	light: &ChunkLight,
+	borders: &ChunkBorders,
+	merge: bool,
+) -> HashMap<TextureAtlasKey, Vec<Quad>> {
+	let mut quads: HashMap<TextureAtlasKey, Vec<Quad>> = HashMap::new();
+
+	for (_, face) in Directions::flags() {
+		let dir: Direction = (*face).into();
+		let (normal_axis, u_axis, v_axis) = face_axes(dir);
+		let (u_size, v_size) = (axis_size(u_axis), axis_size(v_axis));
+
+		// texture, texture rotation and shading of every visible face in a layer
+		let mut mask: Vec<Option<(TextureAtlasKey, bool, FaceShading)>> =
+			vec![None; u_size * v_size];
+		for layer in 0..axis_size(normal_axis) {
+			for v in 0..v_size {
+				for u in 0..u_size {
+					let mut coords = [0; 3];
+					coords[normal_axis] = layer;
+					coords[u_axis] = u;
+					coords[v_axis] = v;
+					// air has no faces, so there's nothing to look at in sections of only air
+					if data.is_air_section(coords[1]) {
+						mask[v * u_size + u] = None;
+						continue;
+					}
+					let pos = LocalBlockPos::new(coords[0] as u8, coords[1] as u8, coords[2] as u8);
+					mask[v * u_size + u] =
+						face_texture(data, borders, pos, dir).map(|(key, rotated)| {
+							(key, rotated, face_shading(data, light, borders, pos, dir))
+						});
 				}
-				if faces.contains(Directions::NegZ) {
-					add_face([
-						MeshVert {
-							pos: [1.0, 0.0, 0.0],
-							tex_coord: [0.0, 1.0],
-						},
-						MeshVert {
-							pos: [0.0, 0.0, 0.0],
-							tex_coord: [1.0, 1.0],
-						},
-						MeshVert {
-							pos: [0.0, 1.0, 0.0],
-							tex_coord: [1.0, 0.0],
-						},
-						MeshVert {
-							pos: [1.0, 1.0, 0.0],
-							tex_coord: [0.0, 0.0],
-						},
-					]);
+			}
+
+			// taking the biggest rectangle starting at each face that isn't part of one yet
+			for v in 0..v_size {
+				for u in 0..u_size {
+					let Some((key, rotated, shading)) = mask[v * u_size + u] else {
+						continue;
+					};
+					let mut width = 1;
+					let mut height = 1;
+					// shading is interpolated across the whole quad, so only faces without any
+					// shading differences can be merged
+					if merge && shading.is_uniform() {
+						let face = Some((key, rotated, shading));
+						while u + width < u_size && mask[v * u_size + u + width] == face {
+							width += 1;
+						}
+						while v + height < v_size
+							&& (u..(u + width)).all(|u| mask[(v + height) * u_size + u] == face)
+						{
+							height += 1;
+						}
+					}
+					for v in v..(v + height) {
+						for u in u..(u + width) {
+							mask[v * u_size + u] = None;
+						}
+					}
+					quads.entry(key).or_default().push(Quad {
+						dir,
+						layer,
+						u,
+						v,
+						width,
+						height,
+						rotated,
+						shading,
+					});
 				}
 			}
-			total_verts += verts.len();
-			total_tris += verts.len();
-			Mesh::new(verts, indices, pos_offset, tex_key)
-		})
-		.collect();
-
-	/*log::debug!(
-		"Remeshed chunk at {} - {} meshes, {} verts, {} tris",
-		chunk_pos,
-		meshes.len(),
-		total_verts,
-		total_tris
-	);*/
-	meshes
+		}
+	}
+	quads
+}
+
+/// Gets the texture of a block's face and whether it's turned a quarter turn, `None` if the face is
+/// hidden or there is no block
+///
+/// The texture depends on the block's state, which can pick a different texture variant or turn
+/// the block on its side
+fn face_texture(
+	data: &impl ChunkBlocks,
+	borders: &ChunkBorders,
+	pos: LocalBlockPos,
+	dir: Direction,
+) -> Option<(TextureAtlasKey, bool)> {
+	let block = data.get(pos);
+	let block_type = BlockType::from_id(block.type_id);
+	if block_type.opacity == BlockOpacity::Invisible {
+		return None;
+	}
+
+	// optimization: faces hidden by the block next to them (in this chunk or the next one) aren't
+	// rendered
+	let neighbor = match pos.get_neighbor(dir) {
+		Some(neighbor) => Some(data.get(neighbor)),
+		None => borders.get(pos, dir).map(|(block, _)| block),
+	};
+	if neighbor.is_some_and(|n| block_type.face_hidden_by(BlockType::from_id(n.type_id))) {
+		return None;
+	}
+
+	let (variant, layout) = block_type.texture_layout_in(block.state);
+	let up = block_type.up_direction(block.state);
+	let key = match layout {
+		// dont care about orientation when its a uniform block
+		BlockTextureLayout::Uniform(_) => TextureAtlasKey::Block(block.type_id, variant),
+		// if its not uniform, face matters
+		_ => TextureAtlasKey::BlockFace(block.type_id, variant, upright_face(dir, up)),
+	};
+	Some((key, is_texture_rotated(dir, up)))
+}
+
+/// Which face `dir` would be if the block was turned upright, for a block whose top faces `up`
+///
+/// Turning the block swaps the y axis with the axis it's on
+fn upright_face(dir: Direction, up: Direction) -> Direction {
+	use Direction::*;
+	match (up, dir) {
+		(PosX, PosX) | (PosZ, PosZ) => PosY,
+		(PosX, NegX) | (PosZ, NegZ) => NegY,
+		(PosX, PosY) => PosX,
+		(PosX, NegY) => NegX,
+		(PosZ, PosY) => PosZ,
+		(PosZ, NegY) => NegZ,
+		_ => dir,
+	}
+}
+
+/// Whether the texture of a side face needs a quarter turn so that it runs along the axis the
+/// block's top faces, for a block whose top faces `up`
+fn is_texture_rotated(dir: Direction, up: Direction) -> bool {
+	let (normal_axis, u_axis, _) = face_axes(dir);
+	let (up_axis, _, _) = face_axes(up);
+	normal_axis != up_axis && u_axis == up_axis
+}
+
+/// Ambient occlusion and light of each corner of a block's face, in the same order as
+/// `unit_face_verts`
+///
+/// Each corner is shaded by the blocks touching it in front of the face: the one right in front, the
+/// two next to the corner and the one diagonal to it. Opaque ones darken it with ambient occlusion
+/// (0 to 3, 3 being unoccluded), the light of ones that let light through is averaged
+fn face_shading(
+	data: &impl ChunkBlocks,
+	light: &ChunkLight,
+	borders: &ChunkBorders,
+	pos: LocalBlockPos,
+	dir: Direction,
+) -> FaceShading {
+	let (normal_axis, u_axis, v_axis) = face_axes(dir);
+	let mut front = [pos.x() as i32, pos.y() as i32, pos.z() as i32];
+	front[normal_axis] += match dir {
+		Direction::PosX | Direction::PosY | Direction::PosZ => 1,
+		_ => -1,
+	};
+	let block_at = |du: i32, dv: i32| {
+		let mut coords = front;
+		coords[u_axis] += du;
+		coords[v_axis] += dv;
+		block_at(data, light, borders, coords)
+	};
+	let is_solid = |block: Option<(BlockData, LightLevels)>| {
+		block.is_some_and(|(block, _)| !BlockType::from_id(block.type_id).is_transparent())
+	};
+	let front_light = block_at(0, 0).map_or(UNKNOWN_LIGHT, |(_, levels)| levels);
+
+	let mut shading = FaceShading {
+		ao: [3; 4],
+		light: [[0; 2]; 4],
+	};
+	for (i, (corner, _)) in unit_face_verts(dir).into_iter().enumerate() {
+		// corners at 0 on an axis are next to the block before it, corners at 1 the one after
+		let du = if corner[u_axis] == 0.0 { -1 } else { 1 };
+		let dv = if corner[v_axis] == 0.0 { -1 } else { 1 };
+		let side_u = block_at(du, 0);
+		let side_v = block_at(0, dv);
+		// the diagonal block can't be seen from a corner enclosed by both sides
+		let enclosed = is_solid(side_u) && is_solid(side_v);
+		let diagonal = if enclosed { None } else { block_at(du, dv) };
+
+		shading.ao[i] = if enclosed {
+			0
+		} else {
+			3 - is_solid(side_u) as u8 - is_solid(side_v) as u8 - is_solid(diagonal) as u8
+		};
+
+		let mut sum = [front_light[0] as u32, front_light[1] as u32];
+		let mut count = 1;
+		for (_, levels) in [side_u, side_v, diagonal]
+			.into_iter()
+			.flatten()
+			.filter(|(block, _)| BlockType::from_id(block.type_id).is_transparent())
+		{
+			sum[0] += levels[0] as u32;
+			sum[1] += levels[1] as u32;
+			count += 1;
+		}
+		shading.light[i] = sum.map(|sum| ((sum * 4 + count / 2) / count) as u8);
+	}
+	shading
+}
+
+/// Block and light levels at a position relative to the chunk, which can be up to a block outside
+/// of it
+///
+/// `None` outside the world height or in chunks that aren't known, including the chunks diagonal to
+/// this one, which aren't part of `ChunkBorders`
+fn block_at(
+	data: &impl ChunkBlocks,
+	light: &ChunkLight,
+	borders: &ChunkBorders,
+	[x, y, z]: [i32; 3],
+) -> Option<(BlockData, LightLevels)> {
+	if !(0..WORLD_HEIGHT as i32).contains(&y) {
+		return None;
+	}
+	let width = CHUNK_WIDTH as i32;
+	let inside = |n: i32| (0..width).contains(&n);
+	match (inside(x), inside(z)) {
+		(true, true) => {
+			let pos = LocalBlockPos::new(x as u8, y as u8, z as u8);
+			let levels = [
+				light.get(pos, LightChannel::Sky),
+				light.get(pos, LightChannel::Block),
+			];
+			Some((data.get(pos), levels))
+		}
+		(false, true) => {
+			let (edge, dir) = if x < 0 {
+				(0, Direction::NegX)
+			} else {
+				(width - 1, Direction::PosX)
+			};
+			borders.get(LocalBlockPos::new(edge as u8, y as u8, z as u8), dir)
+		}
+		(true, false) => {
+			let (edge, dir) = if z < 0 {
+				(0, Direction::NegZ)
+			} else {
+				(width - 1, Direction::PosZ)
+			};
+			borders.get(LocalBlockPos::new(x as u8, y as u8, edge as u8), dir)
+		}
+		(false, false) => None,
+	}
+}
+
+/// Axes (0 = x, 1 = y, 2 = z) that a face points along, and that its texture's x and y run along
+fn face_axes(dir: Direction) -> (usize, usize, usize) {
+	match dir {
+		Direction::PosX | Direction::NegX => (0, 2, 1),
+		Direction::PosY | Direction::NegY => (1, 0, 2),
+		Direction::PosZ | Direction::NegZ => (2, 0, 1),
+	}
+}
+
+/// Number of blocks in a chunk along an axis
+fn axis_size(axis: usize) -> usize {
+	if axis == 1 {
+		WORLD_HEIGHT
+	} else {
+		CHUNK_WIDTH
+	}
+}
+
+/// Makes the verts of a quad by stretching a single block face over it
+///
+/// Texture coords are stretched too, the shader repeats the texture once per block
+fn quad_verts(quad: Quad, texture: TextureRef) -> [MeshVert; 4] {
+	let (normal_axis, u_axis, v_axis) = face_axes(quad.dir);
+	let mut corner = 0;
+	unit_face_verts(quad.dir).map(|(mut pos, tex_coord)| {
+		let ao = AO_BRIGHTNESS[quad.shading.ao[corner] as usize];
+		let light = quad.shading.light[corner].map(|level| level as f32 / (MAX_LIGHT * 4) as f32);
+		corner += 1;
+		pos[normal_axis] += quad.layer as f32;
+		pos[u_axis] = quad.u as f32 + pos[u_axis] * quad.width as f32;
+		pos[v_axis] = quad.v as f32 + pos[v_axis] * quad.height as f32;
+		let tex_coord = if quad.rotated {
+			[
+				tex_coord[1] * quad.height as f32,
+				(1.0 - tex_coord[0]) * quad.width as f32,
+			]
+		} else {
+			[
+				tex_coord[0] * quad.width as f32,
+				tex_coord[1] * quad.height as f32,
+			]
+		};
+		MeshVert {
+			pos,
+			tex_coord,
+			atlas_rect: texture.rect,
+			texture_layer: texture.layer,
+			ao,
+			light,
+		}
+	})
+}
+
+/// Verts (position, texture coord) of a single block's face, for a block at 0, 0, 0
+fn unit_face_verts(dir: Direction) -> [([f32; 3], [f32; 2]); 4] {
+	match dir {
+		// right face
+		Direction::PosX => [
+			([1.0, 0.0, 1.0], [0.0, 1.0]),
+			([1.0, 0.0, 0.0], [1.0, 1.0]),
+			([1.0, 1.0, 0.0], [1.0, 0.0]),
+			([1.0, 1.0, 1.0], [0.0, 0.0]),
+		],
+		Direction::NegX => [
+			([0.0, 0.0, 0.0], [0.0, 1.0]),
+			([0.0, 0.0, 1.0], [1.0, 1.0]),
+			([0.0, 1.0, 1.0], [1.0, 0.0]),
+			([0.0, 1.0, 0.0], [0.0, 0.0]),
+		],
+		Direction::PosY => [
+			([0.0, 1.0, 1.0], [0.0, 1.0]),
+			([1.0, 1.0, 1.0], [1.0, 1.0]),
+			([1.0, 1.0, 0.0], [1.0, 0.0]),
+			([0.0, 1.0, 0.0], [0.0, 0.0]),
+		],
+		Direction::NegY => [
+			([0.0, 0.0, 0.0], [0.0, 1.0]),
+			([1.0, 0.0, 0.0], [1.0, 1.0]),
+			([1.0, 0.0, 1.0], [1.0, 0.0]),
+			([0.0, 0.0, 1.0], [0.0, 0.0]),
+		],
+		Direction::PosZ => [
+			([0.0, 0.0, 1.0], [0.0, 1.0]),
+			([1.0, 0.0, 1.0], [1.0, 1.0]),
+			([1.0, 1.0, 1.0], [1.0, 0.0]),
+			([0.0, 1.0, 1.0], [0.0, 0.0]),
+		],
+		Direction::NegZ => [
+			([1.0, 0.0, 0.0], [0.0, 1.0]),
+			([0.0, 0.0, 0.0], [1.0, 1.0]),
+			([0.0, 1.0, 0.0], [1.0, 0.0]),
+			([1.0, 1.0, 0.0], [0.0, 0.0]),
+		],
+	}
 }
 
 pub fn generate_chunk_border_lines(data: &ChunkData) -> Lines {
@@ -278,3 +638,388 @@ pub fn generate_chunk_border_lines(data: &ChunkData) -> Lines {
 	let z = data.pos.z as f32 * width;
 	Lines::new(verts, [x, 0.0, z], CHUNK_BORDER_COLOR)
 }
+
+/// Lines along the edges of a block, for outlining the block the player is looking at
+pub fn generate_block_outline_lines(pos: BlockPos) -> Lines {
+	let (low, high) = (-OUTLINE_OFFSET, 1.0 + OUTLINE_OFFSET);
+	// the bits of a corner's index pick which side of the block it's on along x, y and z
+	let corner = |i: usize| {
+		LineVert::new(
+			if i & 1 == 0 { low } else { high },
+			if i & 2 == 0 { low } else { high },
+			if i & 4 == 0 { low } else { high },
+		)
+	};
+	let mut verts = Vec::new();
+	for i in 0..8 {
+		for axis in [1, 2, 4] {
+			if i & axis == 0 {
+				verts.push(corner(i));
+				verts.push(corner(i | axis));
+			}
+		}
+	}
+	Lines::new(
+		verts,
+		[pos.x as f32, pos.y as f32, pos.z as f32],
+		BLOCK_OUTLINE_COLOR,
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use cubegame_lib::blocks::registry;
+	use cubegame_lib::{worldgen, ChunkPos, WorldGenesisData};
+
+	fn count_quads(quads: &HashMap<TextureAtlasKey, Vec<Quad>>) -> usize {
+		quads.values().map(|quads| quads.len()).sum()
+	}
+
+	fn count_faces(quads: &HashMap<TextureAtlasKey, Vec<Quad>>) -> usize {
+		quads
+			.values()
+			.flatten()
+			.map(|quad| quad.width * quad.height)
+			.sum()
+	}
+
+	#[test]
+	fn merging_generated_chunk_reduces_quads() {
+		let genesis = WorldGenesisData { seed: 1234 };
+		let chunk = worldgen::generate_chunk(&genesis, ChunkPos { x: 3, z: -2 });
+		let borders = ChunkBorders::default();
+
+		let per_face = generate_quads(&*chunk, &ChunkLight::compute(&chunk), &borders, false);
+		let merged = generate_quads(&*chunk, &ChunkLight::compute(&chunk), &borders, true);
+		// merged quads cover exactly the same faces
+		assert_eq!(count_faces(&per_face), count_faces(&merged));
+		assert_eq!(count_faces(&per_face), count_quads(&per_face));
+		assert!(count_quads(&merged) * 4 < count_quads(&per_face));
+	}
+
+	#[test]
+	fn flat_layer_becomes_one_quad_per_side() {
+		let mut chunk = ChunkData::new(ChunkPos { x: 0, z: 0 });
+		for x in 0..CHUNK_WIDTH as u8 {
+			for z in 0..CHUNK_WIDTH as u8 {
+				chunk.set(LocalBlockPos::new(x, 10, z), BlockData::new(2));
+			}
+		}
+
+		let quads = generate_quads(
+			&chunk,
+			&ChunkLight::compute(&chunk),
+			&ChunkBorders::default(),
+			true,
+		);
+		assert_eq!(count_quads(&quads), 6);
+		let top = quads
+			.values()
+			.flatten()
+			.find(|quad| quad.dir == Direction::PosY)
+			.unwrap();
+		assert_eq!(
+			(top.layer, top.width, top.height),
+			(10, CHUNK_WIDTH, CHUNK_WIDTH)
+		);
+	}
+
+	#[test]
+	fn corners_next_to_blocks_are_occluded() {
+		let mut chunk = ChunkData::new(ChunkPos { x: 0, z: 0 });
+		let floor = LocalBlockPos::new(5u8, 10, 5);
+		chunk.set(floor, BlockData::new(2));
+		let borders = ChunkBorders::default();
+		let light = ChunkLight::dark();
+		assert_eq!(
+			face_shading(&chunk, &light, &borders, floor, Direction::PosY).ao,
+			[3; 4]
+		);
+
+		// a block next to the top face darkens the two corners touching it
+		chunk.set(LocalBlockPos::new(6u8, 11, 5), BlockData::new(2));
+		assert_eq!(
+			face_shading(&chunk, &light, &borders, floor, Direction::PosY).ao,
+			[3, 2, 2, 3]
+		);
+		// a diagonal one darkens its corner further
+		chunk.set(LocalBlockPos::new(6u8, 11, 6), BlockData::new(2));
+		assert_eq!(
+			face_shading(&chunk, &light, &borders, floor, Direction::PosY).ao,
+			[3, 1, 2, 3]
+		);
+		// blocks on both sides of a corner fully occlude it
+		chunk.set(LocalBlockPos::new(5u8, 11, 6), BlockData::new(2));
+		assert_eq!(
+			face_shading(&chunk, &light, &borders, floor, Direction::PosY).ao,
+			[2, 0, 2, 3]
+		);
+	}
+
+	#[test]
+	fn occluded_faces_are_not_merged() {
+		let mut chunk = ChunkData::new(ChunkPos { x: 0, z: 0 });
+		for x in 0..CHUNK_WIDTH as u8 {
+			for z in 0..CHUNK_WIDTH as u8 {
+				chunk.set(LocalBlockPos::new(x, 10, z), BlockData::new(2));
+			}
+		}
+		chunk.set(LocalBlockPos::new(8u8, 11, 8), BlockData::new(2));
+
+		let quads = generate_quads(
+			&chunk,
+			&ChunkLight::compute(&chunk),
+			&ChunkBorders::default(),
+			true,
+		);
+		let floor_tops: Vec<&Quad> = quads
+			.values()
+			.flatten()
+			.filter(|quad| quad.dir == Direction::PosY && quad.layer == 10)
+			.collect();
+		// the 8 faces around the block are shaded and stay on their own
+		let shaded = floor_tops
+			.iter()
+			.filter(|quad| quad.shading.ao != [3; 4])
+			.count();
+		assert_eq!(shaded, 8);
+		for quad in &floor_tops {
+			if quad.width * quad.height > 1 {
+				assert_eq!(quad.shading.ao, [3; 4]);
+			}
+		}
+		let covered: usize = floor_tops.iter().map(|quad| quad.width * quad.height).sum();
+		assert_eq!(covered, CHUNK_WIDTH * CHUNK_WIDTH - 1);
+	}
+
+	#[test]
+	fn faces_against_neighbouring_chunks_are_culled() {
+		let mut chunk = ChunkData::new(ChunkPos { x: 0, z: 0 });
+		chunk.set(LocalBlockPos::new(15u8, 0, 0), BlockData::new(2));
+		let mut neighbor = chunk.clone();
+		neighbor.pos = ChunkPos { x: 1, z: 0 };
+		neighbor.set(LocalBlockPos::new(0u8, 0, 0), BlockData::new(2));
+
+		assert_eq!(
+			count_quads(&generate_quads(
+				&chunk,
+				&ChunkLight::compute(&chunk),
+				&ChunkBorders::default(),
+				true
+			)),
+			6
+		);
+		let mut borders = ChunkBorders::default();
+		borders.set_side(Direction::PosX, &neighbor, &ChunkLight::compute(&neighbor));
+		let quads = generate_quads(&chunk, &ChunkLight::compute(&chunk), &borders, true);
+		assert_eq!(count_quads(&quads), 5);
+		assert!(!quads
+			.values()
+			.flatten()
+			.any(|quad| quad.dir == Direction::PosX));
+	}
+
+	#[test]
+	fn see_through_blocks_only_hide_faces_they_should() {
+		let mut chunk = ChunkData::new(ChunkPos { x: 0, z: 0 });
+		let faces_between = |chunk: &ChunkData, a: u8, b: u8| {
+			let quads = generate_quads(
+				chunk,
+				&ChunkLight::compute(chunk),
+				&ChunkBorders::default(),
+				false,
+			);
+			let a_faces = quads
+				.values()
+				.flatten()
+				.filter(|quad| quad.dir == Direction::PosX && quad.layer == a as usize)
+				.count();
+			let b_faces = quads
+				.values()
+				.flatten()
+				.filter(|quad| quad.dir == Direction::NegX && quad.layer == b as usize)
+				.count();
+			(a_faces, b_faces)
+		};
+		let glass = BlockData::new(6);
+		let leaves = BlockData::new(7);
+		let stone = BlockData::new(2);
+
+		// glass next to glass looks like one block
+		chunk.set(LocalBlockPos::new(4u8, 10, 4), glass);
+		chunk.set(LocalBlockPos::new(5u8, 10, 4), glass);
+		assert_eq!(faces_between(&chunk, 4, 5), (0, 0));
+		// stone shows through glass, but hides the glass face against it
+		chunk.set(LocalBlockPos::new(5u8, 10, 4), stone);
+		assert_eq!(faces_between(&chunk, 4, 5), (0, 1));
+		// leaves have gaps, so their faces show through each other
+		chunk.set(LocalBlockPos::new(4u8, 10, 4), leaves);
+		chunk.set(LocalBlockPos::new(5u8, 10, 4), leaves);
+		assert_eq!(faces_between(&chunk, 4, 5), (1, 1));
+
+		// glass goes in its own mesh
+		chunk.set(LocalBlockPos::new(4u8, 10, 4), glass);
+		let quads = generate_quads(
+			&chunk,
+			&ChunkLight::compute(&chunk),
+			&ChunkBorders::default(),
+			true,
+		);
+		let translucent: usize = quads
+			.iter()
+			.filter(|(key, _)| is_translucent(**key))
+			.map(|(_, quads)| quads.len())
+			.sum();
+		assert_eq!(translucent, 6);
+	}
+
+	#[test]
+	fn translucent_quads_are_sorted_back_to_front() {
+		let mut chunk = ChunkData::new(ChunkPos { x: 1, z: 0 });
+		// apart, so none of their faces merge
+		chunk.set(LocalBlockPos::new(2u8, 10, 4), BlockData::new(6));
+		chunk.set(LocalBlockPos::new(12u8, 10, 4), BlockData::new(6));
+		let meshes = generate_chunk_mesh(
+			&chunk,
+			&ChunkLight::compute(&chunk),
+			&ChunkBorders::default(),
+			&TextureRefs::new(HashMap::new()),
+		);
+		let order = &meshes.translucent_order;
+		assert_eq!(order.quads.len(), 12);
+		// centers are where the quads are in the world, not in the chunk
+		assert!(order
+			.quads
+			.iter()
+			.all(|(center, _)| (18.0..=29.0).contains(&center[0])));
+
+		let distance_to = |indices: &[u32], camera: [f32; 3]| {
+			let (center, _) = order.quads.iter().find(|(_, i)| i == indices).unwrap();
+			(0..3).map(|i| (center[i] - camera[i]).powi(2)).sum::<f32>()
+		};
+		for camera in [[0.0, 10.5, 4.5], [40.0, 12.0, 4.5]] {
+			let indices = order.sorted_indices(camera);
+			assert_eq!(indices.len(), 12 * 6);
+			let distances: Vec<f32> = indices
+				.chunks(6)
+				.map(|quad| distance_to(quad, camera))
+				.collect();
+			assert!(distances.windows(2).all(|pair| pair[0] >= pair[1]));
+		}
+	}
+
+	#[test]
+	fn block_state_turns_textures() {
+		let mut chunk = ChunkData::new(ChunkPos { x: 0, z: 0 });
+		let log_id = registry().id_of("log_block").unwrap();
+		let log_type = BlockType::from_id(log_id);
+		let pos = LocalBlockPos::new(4u8, 10, 4);
+		let borders = ChunkBorders::default();
+		let face = |chunk: &ChunkData, dir| face_texture(chunk, &borders, pos, dir).unwrap();
+
+		// standing up, the top texture is on top and nothing is turned
+		chunk.set(pos, BlockData::new(log_id));
+		assert_eq!(
+			face(&chunk, Direction::PosY),
+			(
+				TextureAtlasKey::BlockFace(log_id, 0, Direction::PosY),
+				false
+			)
+		);
+		for dir in [Direction::PosX, Direction::NegZ] {
+			assert_eq!(
+				face(&chunk, dir),
+				(TextureAtlasKey::BlockFace(log_id, 0, dir), false)
+			);
+		}
+
+		// lying along x, the ends face along x and the sides are turned to match
+		let state = log_type.with_property(0, "axis", "x").unwrap();
+		chunk.set(
+			pos,
+			BlockData {
+				type_id: log_id,
+				state,
+			},
+		);
+		assert_eq!(
+			face(&chunk, Direction::NegX),
+			(
+				TextureAtlasKey::BlockFace(log_id, 0, Direction::NegY),
+				false
+			)
+		);
+		let (key, rotated) = face(&chunk, Direction::PosY);
+		assert!(rotated);
+		assert!(
+			matches!(key, TextureAtlasKey::BlockFace(_, 0, dir) if dir != Direction::PosY && dir != Direction::NegY)
+		);
+		assert!(face(&chunk, Direction::PosZ).1);
+
+		// lying along z, sides whose texture already runs along z aren't turned
+		let state = log_type.with_property(0, "axis", "z").unwrap();
+		chunk.set(
+			pos,
+			BlockData {
+				type_id: log_id,
+				state,
+			},
+		);
+		assert_eq!(
+			face(&chunk, Direction::PosZ),
+			(
+				TextureAtlasKey::BlockFace(log_id, 0, Direction::PosY),
+				false
+			)
+		);
+		assert!(face(&chunk, Direction::PosX).1);
+		assert!(!face(&chunk, Direction::NegY).1);
+
+		// turned faces aren't merged with upright ones that use the same texture
+		let state = log_type.with_property(0, "axis", "x").unwrap();
+		chunk.set(
+			pos,
+			BlockData {
+				type_id: log_id,
+				state,
+			},
+		);
+		chunk.set(LocalBlockPos::new(5u8, 10, 4), BlockData::new(log_id));
+		let quads = generate_quads(&chunk, &ChunkLight::compute(&chunk), &borders, true);
+		let side_quads = quads
+			.values()
+			.flatten()
+			.filter(|quad| quad.dir == Direction::PosZ)
+			.count();
+		assert_eq!(side_quads, 2);
+	}
+
+	#[test]
+	fn faces_are_lit_by_neighbouring_chunks() {
+		let mut chunk = ChunkData::new(ChunkPos { x: 0, z: 0 });
+		let wall = LocalBlockPos::new(15u8, 10, 5);
+		chunk.set(wall, BlockData::new(2));
+		let light = ChunkLight::dark();
+		let mut neighbor = chunk.clone();
+		neighbor.pos = ChunkPos { x: 1, z: 0 };
+		neighbor.set(LocalBlockPos::new(2u8, 10, 5), BlockData::new(5));
+
+		let unlit = face_shading(
+			&chunk,
+			&light,
+			&ChunkBorders::default(),
+			wall,
+			Direction::PosX,
+		);
+		assert!(unlit.light.iter().all(|levels| levels[1] == 0));
+
+		let mut borders = ChunkBorders::default();
+		borders.set_side(Direction::PosX, &neighbor, &ChunkLight::compute(&neighbor));
+		let lit = face_shading(&chunk, &light, &borders, wall, Direction::PosX);
+		assert!(lit.light.iter().all(|levels| levels[1] > 0));
+		// the block right in front of the face is 2 blocks from the lamp
+		let brightest = lit.light.iter().map(|levels| levels[1]).max().unwrap();
+		assert!(brightest < (MAX_LIGHT - 2) * 4);
+	}
+}
@@ -1,3 +1,4 @@
+mod environment;
 pub mod mesher;
 pub mod objects;
 mod passes;
@@ -9,13 +10,17 @@ use std::sync::Arc;
 use pollster::FutureExt;
 use winit::window::Window;
 
-use crate::game::Game;
+use crate::game::{Game, RENDER_DISTANCE};
 use crate::render::passes::LineRenderingPipeline;
-use passes::WorldRenderingPipeline;
+use crate::settings::ClientSettings;
+use environment::EnvironmentUniform;
+use passes::{SkyRenderingPipeline, WorldRenderingPipeline};
 use perspective::Perspective;
-use perspective::OPENGL_TO_WGPU_MATRIX;
+use perspective::{Frustum, OPENGL_TO_WGPU_MATRIX};
 use texture::depth_buffer::DepthTexture;
 
+pub use texture::{TextureBackend, TextureFilter, TextureRefs};
+
 pub struct Renderer {
 	/// winit window needs to be an Arc because both this, the application, and the surface constructor (async) needs it
 	pub window: Arc<Window>,
@@ -33,13 +38,18 @@ pub struct Renderer {
 	pub perspective: Perspective,
 	/// Buffer for camera data to go in
 	camera_buffer: wgpu::Buffer,
+	/// Buffer for sky, sun and camera data (see `EnvironmentUniform`)
+	environment_buffer: wgpu::Buffer,
+	/// How many chunks were drawn and culled in the last frame
+	pub chunk_stats: ChunkDrawStats,
 	// pipelines
+	sky_rendering_pipeline: SkyRenderingPipeline,
 	world_rendering_pipeline: WorldRenderingPipeline,
 	line_rendering_pipeline: LineRenderingPipeline,
 }
 
 impl Renderer {
-	pub fn new(window: Arc<Window>) -> Result<Renderer, ()> {
+	pub fn new(window: Arc<Window>, settings: &ClientSettings) -> Result<Renderer, ()> {
 		let size = window.inner_size();
 
 		let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -110,11 +120,22 @@ impl Renderer {
 			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
 			mapped_at_creation: false,
 		});
+		let environment_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Environment Buffer"),
+			size: size_of::<EnvironmentUniform>() as u64,
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+		let sky_rendering_pipeline =
+			SkyRenderingPipeline::new(&device, &config, environment_buffer.as_entire_binding())?;
 		let world_rendering_pipeline = WorldRenderingPipeline::new(
 			&device,
 			&queue,
 			&config,
 			camera_buffer.as_entire_binding(),
+			environment_buffer.as_entire_binding(),
+			settings.texture_backend,
+			settings.texture_filter,
 		)?;
 		let line_rendering_pipeline =
 			LineRenderingPipeline::new(&device, &config, camera_buffer.as_entire_binding())?;
@@ -135,6 +156,9 @@ impl Renderer {
 				1000.0,
 			),
 			camera_buffer,
+			environment_buffer,
+			chunk_stats: ChunkDrawStats::default(),
+			sky_rendering_pipeline,
 			world_rendering_pipeline,
 			line_rendering_pipeline,
 		})
@@ -159,19 +183,38 @@ impl Renderer {
 		self.surface.configure(&self.device, &self.surface_config);
 	}
 
+	/// Where each block texture is, for building meshes
+	pub fn block_texture_refs(&self) -> TextureRefs {
+		self.world_rendering_pipeline.block_textures.refs()
+	}
+
 	/// Render the in game scene
 	pub fn render_game(&mut self, game: &Game) -> Result<(), wgpu::SurfaceError> {
 		// updating camera buffer
-		let view_proj_matrix: [[f32; 4]; 4] = (OPENGL_TO_WGPU_MATRIX
+		let view_proj = OPENGL_TO_WGPU_MATRIX
 			* self.perspective.proj_matrix()
-			* game.world_data.player.view_matrix())
-		.into();
+			* game.world_data.player.view_matrix();
+		let frustum = Frustum::from_view_proj(&view_proj);
+		let view_proj_matrix: [[f32; 4]; 4] = view_proj.into();
 		self.queue.write_buffer(
 			&self.camera_buffer,
 			0,
 			bytemuck::cast_slice(&view_proj_matrix),
 		);
 
+		// updating environment buffer
+		let environment = EnvironmentUniform::new(
+			game.world_data.clock.now(),
+			&view_proj,
+			game.world_data.player.pos,
+			RENDER_DISTANCE,
+		);
+		self.queue.write_buffer(
+			&self.environment_buffer,
+			0,
+			bytemuck::bytes_of(&environment),
+		);
+
 		let output = self.surface.get_current_texture()?;
 		let output_view = output
 			.texture
@@ -182,12 +225,24 @@ impl Renderer {
 				label: Some("Render Encoder"),
 			});
 
-		self.world_rendering_pipeline.execute_render_pass(
+		self.sky_rendering_pipeline
+			.execute_render_pass(&mut encoder, &output_view);
+		self.chunk_stats = self.world_rendering_pipeline.execute_render_pass(
 			&mut encoder,
 			&output_view,
 			&self.depth_buffer.texture_view,
 			&game.world_data,
+			&frustum,
 		);
+		self.world_rendering_pipeline
+			.execute_translucent_render_pass(
+				&mut encoder,
+				&output_view,
+				&self.depth_buffer.texture_view,
+				&game.world_data,
+				&frustum,
+				game.world_data.player.pos,
+			);
 		self.line_rendering_pipeline.execute_render_pass(
 			&mut encoder,
 			&output_view,
@@ -204,3 +259,10 @@ impl Renderer {
 		Ok(())
 	}
 }
+
+/// Counts of chunks that were drawn and skipped for being out of view in a frame
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ChunkDrawStats {
+	pub drawn: u32,
+	pub culled: u32,
+}
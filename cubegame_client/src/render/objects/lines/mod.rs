@@ -77,7 +77,7 @@ impl Lines {
 					});
 				let vertex_buffer = renderer.device.create_buffer_init(&BufferInitDescriptor {
 					label: Some("Lines vertex buffer"),
-					contents: bytemuck::cast_slice(&verts),
+					contents: bytemuck::cast_slice(verts),
 					usage: BufferUsages::VERTEX,
 				});
 
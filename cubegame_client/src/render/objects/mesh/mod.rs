@@ -1,4 +1,3 @@
-use crate::render::texture::atlas::TextureAtlasKey;
 use crate::render::Renderer;
 pub use vert::MeshVert;
 use wgpu::{
@@ -15,7 +14,6 @@ enum MeshRenderState {
 		verts: Vec<MeshVert>,
 		indices: Vec<u32>,
 		pos: [f32; 3],
-		texture: TextureAtlasKey,
 	},
 }
 
@@ -35,12 +33,7 @@ pub struct Mesh {
 	render_state: MeshRenderState,
 }
 impl Mesh {
-	pub fn new(
-		verts: Vec<MeshVert>,
-		indices: Vec<u32>,
-		pos: [f32; 3],
-		texture: TextureAtlasKey,
-	) -> Mesh {
+	pub fn new(verts: Vec<MeshVert>, indices: Vec<u32>, pos: [f32; 3]) -> Mesh {
 		Mesh {
 			n_verts: verts.len() as u32,
 			n_tris: indices.len() as u32 / 3,
@@ -48,7 +41,6 @@ impl Mesh {
 				verts,
 				indices,
 				pos,
-				texture,
 			},
 		}
 	}
@@ -63,13 +55,17 @@ impl Mesh {
 	}
 
 	/// Creates buffers and bind group for this mesh if it hasn't been loaded already
+	///
+	/// Empty meshes are never loaded, there is nothing to draw
 	pub fn load_buffers(&mut self, renderer: &Renderer) {
+		if self.n_verts == 0 {
+			return;
+		}
 		match &self.render_state {
 			MeshRenderState::Unloaded {
 				verts,
 				indices,
 				pos,
-				texture,
 			} => {
 				// position offset of this whole mesh
 				let pos_buffer = renderer.device.create_buffer_init(&BufferInitDescriptor {
@@ -77,48 +73,25 @@ impl Mesh {
 					contents: bytemuck::cast_slice(pos),
 					usage: BufferUsages::UNIFORM,
 				});
-				let atlas_pos_rect = renderer
-					.world_rendering_pipeline
-					.block_texture_atlas
-					.get_pos_of(*texture)
-					.unwrap_or_else(|| {
-						renderer
-							.world_rendering_pipeline
-							.block_texture_atlas
-							.get_pos_of(TextureAtlasKey::Null)
-							.unwrap()
-					});
-				// position of texture in the texture atlas
-				let atlas_pos_buffer = renderer.device.create_buffer_init(&BufferInitDescriptor {
-					label: Some("Mesh texture atlas position buffer"),
-					contents: bytemuck::cast_slice(atlas_pos_rect),
-					usage: BufferUsages::UNIFORM,
-				});
 				let bind_group = renderer
 					.device
 					.create_bind_group(&wgpu::BindGroupDescriptor {
 						layout: &renderer.world_rendering_pipeline.local_bind_group_layout,
-						entries: &[
-							wgpu::BindGroupEntry {
-								binding: 0,
-								resource: pos_buffer.as_entire_binding(),
-							},
-							wgpu::BindGroupEntry {
-								binding: 1,
-								resource: atlas_pos_buffer.as_entire_binding(),
-							},
-						],
+						entries: &[wgpu::BindGroupEntry {
+							binding: 0,
+							resource: pos_buffer.as_entire_binding(),
+						}],
 						label: Some("Mesh local bind group"),
 					});
 				let vertex_buffer = renderer.device.create_buffer_init(&BufferInitDescriptor {
 					label: Some("Mesh vertex buffer"),
-					contents: bytemuck::cast_slice(&verts),
+					contents: bytemuck::cast_slice(verts),
 					usage: BufferUsages::VERTEX,
 				});
 				let index_buffer = renderer.device.create_buffer_init(&BufferInitDescriptor {
 					label: Some("Mesh index buffer"),
-					contents: bytemuck::cast_slice(&indices),
-					usage: BufferUsages::INDEX,
+					contents: bytemuck::cast_slice(indices),
+					usage: BufferUsages::INDEX | BufferUsages::COPY_DST,
 				});
 
 				let render_objs = MeshRenderObjects {
@@ -132,6 +105,20 @@ impl Mesh {
 		}
 	}
 
+	/// Replaces the mesh's indices with the same tris in a different order
+	pub fn reorder_indices(&mut self, indices: Vec<u32>, renderer: &Renderer) {
+		match &mut self.render_state {
+			MeshRenderState::Loaded(objs) => {
+				renderer
+					.queue
+					.write_buffer(&objs.index_buffer, 0, bytemuck::cast_slice(&indices));
+			}
+			MeshRenderState::Unloaded {
+				indices: unloaded, ..
+			} => *unloaded = indices,
+		}
+	}
+
 	pub fn empty() -> Mesh {
 		Mesh {
 			n_verts: 0,
@@ -140,7 +127,6 @@ impl Mesh {
 				verts: Vec::new(),
 				indices: Vec::new(),
 				pos: [0.0, 0.0, 0.0],
-				texture: TextureAtlasKey::Null,
 			},
 		}
 	}
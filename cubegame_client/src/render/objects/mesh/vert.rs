@@ -6,6 +6,14 @@ use wgpu::{BufferAddress, VertexBufferLayout};
 pub struct MeshVert {
 	pub pos: [f32; 3],
 	pub tex_coord: [f32; 2],
+	/// Position of the texture on its layer: [x pos, y pos, x scale, y scale]
+	pub atlas_rect: [f32; 4],
+	/// Layer of the block texture array the texture is on
+	pub texture_layer: u32,
+	/// Brightness from ambient occlusion, 1 is unoccluded
+	pub ao: f32,
+	/// Sky light and block light, from 0 to 1
+	pub light: [f32; 2],
 }
 impl MeshVert {
 	pub fn buffer_layout() -> VertexBufferLayout<'static> {
@@ -26,6 +34,30 @@ impl MeshVert {
 					shader_location: 1,
 					format: wgpu::VertexFormat::Float32x2,
 				},
+				wgpu::VertexAttribute {
+					// atlas_rect
+					offset: size_of::<[f32; 5]>() as BufferAddress,
+					shader_location: 2,
+					format: wgpu::VertexFormat::Float32x4,
+				},
+				wgpu::VertexAttribute {
+					// texture_layer
+					offset: size_of::<[f32; 9]>() as BufferAddress,
+					shader_location: 3,
+					format: wgpu::VertexFormat::Uint32,
+				},
+				wgpu::VertexAttribute {
+					// ao
+					offset: size_of::<[f32; 10]>() as BufferAddress,
+					shader_location: 4,
+					format: wgpu::VertexFormat::Float32,
+				},
+				wgpu::VertexAttribute {
+					// light
+					offset: size_of::<[f32; 11]>() as BufferAddress,
+					shader_location: 5,
+					format: wgpu::VertexFormat::Float32x2,
+				},
 			],
 		}
 	}
@@ -143,7 +143,7 @@ impl LineRenderingPipeline {
 		let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
 			label: Some("Line rendering pass"),
 			color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-				view: &surface_texture_view,
+				view: surface_texture_view,
 				resolve_target: None,
 				ops: wgpu::Operations {
 					load: wgpu::LoadOp::Load,
@@ -177,5 +177,13 @@ impl LineRenderingPipeline {
 				render_pass.draw(0..(chunk.border_lines.n_lines * 2), 0..1);
 			}
 		}
+
+		if let Some((_, outline)) = world_data.target_outline.as_ref() {
+			if let Some(render_objs) = outline.get_render_objs() {
+				render_pass.set_vertex_buffer(0, render_objs.vertex_buffer.slice(..));
+				render_pass.set_bind_group(1, &render_objs.bind_group, &[]);
+				render_pass.draw(0..(outline.n_lines * 2), 0..1);
+			}
+		}
 	}
 }
@@ -1,5 +1,7 @@
 mod line_rendering;
+mod sky_rendering;
 mod world_rendering;
 
 pub use line_rendering::LineRenderingPipeline;
+pub use sky_rendering::SkyRenderingPipeline;
 pub use world_rendering::WorldRenderingPipeline;
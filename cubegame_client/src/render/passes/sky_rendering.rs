@@ -0,0 +1,117 @@
+/// Render pipeline for rendering the sky, with the sun and the moon
+///
+/// Draws a single triangle covering the screen, no vertex buffers needed
+///
+/// Bind groups and bindings:
+/// 	0: "global" set once per frame
+/// 		0 - Environment (sky colours, sun direction, camera): `EnvironmentUniform`
+pub struct SkyRenderingPipeline {
+	pipeline: wgpu::RenderPipeline,
+	global_bind_group: wgpu::BindGroup,
+}
+impl SkyRenderingPipeline {
+	pub fn new(
+		device: &wgpu::Device,
+		surface_config: &wgpu::SurfaceConfiguration,
+		environment_bind_resource: wgpu::BindingResource,
+	) -> Result<Self, ()> {
+		let global_bind_group_layout =
+			device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+				entries: &[wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				}],
+				label: Some("Sky rendering global bind group layout"),
+			});
+		let global_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+			layout: &global_bind_group_layout,
+			entries: &[wgpu::BindGroupEntry {
+				binding: 0,
+				resource: environment_bind_resource,
+			}],
+			label: Some("Sky rendering global bind group"),
+		});
+		let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("Sky rendering pipeline layout"),
+			bind_group_layouts: &[&global_bind_group_layout],
+			push_constant_ranges: &[],
+		});
+		let shader = device.create_shader_module(wgpu::include_wgsl!("sky_shader.wgsl"));
+		let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("Sky rendering pipeline"),
+			layout: Some(&layout),
+			vertex: wgpu::VertexState {
+				module: &shader,
+				entry_point: Some("vs_main"),
+				buffers: &[],
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+			},
+			fragment: Some(wgpu::FragmentState {
+				module: &shader,
+				entry_point: Some("fs_main"),
+				targets: &[Some(wgpu::ColorTargetState {
+					format: surface_config.format,
+					blend: Some(wgpu::BlendState::REPLACE),
+					write_mask: wgpu::ColorWrites::ALL,
+				})],
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+			}),
+			primitive: wgpu::PrimitiveState {
+				topology: wgpu::PrimitiveTopology::TriangleList,
+				strip_index_format: None,
+				front_face: wgpu::FrontFace::Ccw,
+				cull_mode: None,
+				polygon_mode: wgpu::PolygonMode::Fill,
+				unclipped_depth: false,
+				conservative: false,
+			},
+			// the sky is behind everything, so it doesn't need depth
+			depth_stencil: None,
+			multisample: wgpu::MultisampleState {
+				count: 1,
+				mask: !0,
+				alpha_to_coverage_enabled: false,
+			},
+			multiview: None,
+			cache: None,
+		});
+
+		Ok(SkyRenderingPipeline {
+			pipeline,
+			global_bind_group,
+		})
+	}
+
+	/// Executes a render pass on the given command encoder
+	///
+	/// Draws over the whole color attachment, so it goes before every other pass
+	pub fn execute_render_pass(
+		&self,
+		encoder: &mut wgpu::CommandEncoder,
+		surface_texture_view: &wgpu::TextureView,
+	) {
+		let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+			label: Some("Sky rendering pass"),
+			color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+				view: surface_texture_view,
+				resolve_target: None,
+				ops: wgpu::Operations {
+					load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+					store: wgpu::StoreOp::Store,
+				},
+			})],
+			depth_stencil_attachment: None,
+			occlusion_query_set: None,
+			timestamp_writes: None,
+		});
+		render_pass.set_pipeline(&self.pipeline);
+		render_pass.set_bind_group(0, &self.global_bind_group, &[]);
+		render_pass.draw(0..3, 0..1);
+	}
+}
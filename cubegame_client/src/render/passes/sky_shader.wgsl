@@ -0,0 +1,68 @@
+
+struct Environment {
+    inv_view_proj: mat4x4<f32>,
+    camera_pos: vec4<f32>,
+    sun_dir: vec4<f32>,
+    horizon_color: vec4<f32>,
+    zenith_color: vec4<f32>,
+    fog_color: vec4<f32>,
+    sky_light: f32,
+    fog_start: f32,
+    fog_end: f32,
+};
+@group(0) @binding(0)
+var<uniform> env: Environment;
+
+struct VertexOutput {
+    @builtin(position) clip_pos: vec4<f32>,
+    @location(0) screen_pos: vec2<f32>,
+};
+
+// one triangle covering the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let pos = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u)) * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_pos = vec4<f32>(pos, 0.0, 1.0);
+    out.screen_pos = pos;
+    return out;
+}
+
+// half width of the sun and moon, as the tangent of the angle they cover
+const SUN_SIZE: f32 = 0.08;
+const MOON_SIZE: f32 = 0.05;
+const SUN_COLOR: vec3<f32> = vec3<f32>(1.0, 0.95, 0.7);
+const MOON_COLOR: vec3<f32> = vec3<f32>(0.75, 0.78, 0.85);
+
+// whether the ray hits a square billboard facing the camera in the given direction
+fn hits_billboard(ray: vec3<f32>, dir: vec3<f32>, size: f32) -> bool {
+    let facing = dot(ray, dir);
+    if facing <= 0.0 {
+        return false;
+    }
+    // the sun moves around the z axis, so the billboard is kept lined up with it
+    let u = vec3<f32>(0.0, 0.0, 1.0);
+    let v = cross(dir, u);
+    let local = vec2<f32>(dot(ray, u), dot(ray, v)) / facing;
+    return max(abs(local.x), abs(local.y)) < size;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // direction of the view ray through this pixel
+    let far = env.inv_view_proj * vec4<f32>(in.screen_pos, 1.0, 1.0);
+    let ray = normalize(far.xyz / far.w - env.camera_pos.xyz);
+
+    let height = clamp(ray.y, 0.0, 1.0);
+    var color = mix(env.horizon_color.rgb, env.zenith_color.rgb, sqrt(height));
+
+    let sun_dir = normalize(env.sun_dir.xyz);
+    if hits_billboard(ray, sun_dir, SUN_SIZE) {
+        color = SUN_COLOR;
+    } else if hits_billboard(ray, -sun_dir, MOON_SIZE) {
+        color = MOON_COLOR;
+    }
+
+    return vec4<f32>(color, 1.0);
+}
@@ -1,16 +1,22 @@
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
-use cubegame_lib::blocks::{BlockTextureLayout, BLOCK_TYPES};
-use cubegame_lib::Direction;
+use cubegame_lib::blocks::{registry, BlockTextureLayout};
+use cubegame_lib::{ChunkPos, Direction, CHUNK_WIDTH, WORLD_HEIGHT};
 use image::{ImageReader, RgbaImage};
+use nalgebra::Vector3;
 
 use crate::render::objects::mesh::vert::MeshVert;
+use crate::render::objects::Mesh;
 use crate::{
 	game::world::WorldData,
-	render::texture::{
-		atlas::{TextureAtlas, TextureAtlasKey},
-		depth_buffer::DepthTexture,
+	render::{
+		perspective::Frustum,
+		texture::{
+			atlas::TextureAtlasKey, depth_buffer::DepthTexture, BlockTextures, TextureBackend,
+			TextureFilter,
+		},
+		ChunkDrawStats,
 	},
 };
 
@@ -19,18 +25,23 @@ use crate::{
 /// Bind groups and bindings:
 /// 	0: "global" set once per frame
 /// 		0 - Camera (view/projection) matrix: 4x4 float matrix
-/// 		1 - Texture atlas texture view
-/// 		2 - Texture atlas sampler
+/// 		1 - Block textures texture view (2d array)
+/// 		2 - Block textures sampler
+/// 		3 - Environment (sky light and such): `EnvironmentUniform`
 /// 	1: "local" set once per mesh/object
 /// 		0 - Mesh position (aka vert offset): float vector3
-/// 		1 - Texture atlas position: [x pos, y pos, x scale, y scale]
+///
+/// Texture layers and positions are part of each vert (see `MeshVert`)
+///
+/// Opaque meshes are drawn first, then translucent ones are blended over them from back to front
 pub struct WorldRenderingPipeline {
 	pipeline: wgpu::RenderPipeline,
+	translucent_pipeline: wgpu::RenderPipeline,
 	global_bind_group: wgpu::BindGroup,
 	/// Layout of the local bind group for each mesh
 	pub local_bind_group_layout: wgpu::BindGroupLayout,
-	/// Atlas containing all the packed block textures
-	pub block_texture_atlas: TextureAtlas,
+	/// Every block texture, loaded by whichever backend was picked
+	pub block_textures: BlockTextures,
 }
 impl WorldRenderingPipeline {
 	pub fn new(
@@ -38,8 +49,17 @@ impl WorldRenderingPipeline {
 		queue: &wgpu::Queue,
 		surface_config: &wgpu::SurfaceConfiguration,
 		camera_bind_resource: wgpu::BindingResource,
+		environment_bind_resource: wgpu::BindingResource,
+		texture_backend: TextureBackend,
+		texture_filter: TextureFilter,
 	) -> Result<Self, ()> {
-		let block_texture_atlas = TextureAtlas::generate(read_block_textures()?, &device, &queue)?;
+		let block_textures = BlockTextures::load(
+			texture_backend,
+			texture_filter,
+			read_block_textures(),
+			device,
+			queue,
+		)?;
 
 		let global_bind_group_layout =
 			device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -59,15 +79,25 @@ impl WorldRenderingPipeline {
 						visibility: wgpu::ShaderStages::FRAGMENT,
 						ty: wgpu::BindingType::Texture {
 							multisampled: false,
-							view_dimension: wgpu::TextureViewDimension::D2,
-							sample_type: wgpu::TextureSampleType::Float { filterable: false },
+							view_dimension: wgpu::TextureViewDimension::D2Array,
+							sample_type: wgpu::TextureSampleType::Float { filterable: true },
 						},
 						count: None,
 					},
 					wgpu::BindGroupLayoutEntry {
 						binding: 2,
 						visibility: wgpu::ShaderStages::FRAGMENT,
-						ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+						ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+						count: None,
+					},
+					wgpu::BindGroupLayoutEntry {
+						binding: 3,
+						visibility: wgpu::ShaderStages::FRAGMENT,
+						ty: wgpu::BindingType::Buffer {
+							ty: wgpu::BufferBindingType::Uniform,
+							has_dynamic_offset: false,
+							min_binding_size: None,
+						},
 						count: None,
 					},
 				],
@@ -83,40 +113,32 @@ impl WorldRenderingPipeline {
 				wgpu::BindGroupEntry {
 					binding: 1,
 					resource: wgpu::BindingResource::TextureView(
-						&block_texture_atlas.texture.texture_view,
+						&block_textures.texture.texture_view,
 					),
 				},
 				wgpu::BindGroupEntry {
 					binding: 2,
-					resource: wgpu::BindingResource::Sampler(&block_texture_atlas.texture.sampler),
+					resource: wgpu::BindingResource::Sampler(&block_textures.texture.sampler),
+				},
+				wgpu::BindGroupEntry {
+					binding: 3,
+					resource: environment_bind_resource,
 				},
 			],
 			label: Some("World rendering global bind group"),
 		});
 		let local_bind_group_layout =
 			device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-				entries: &[
-					wgpu::BindGroupLayoutEntry {
-						binding: 0,
-						visibility: wgpu::ShaderStages::VERTEX,
-						ty: wgpu::BindingType::Buffer {
-							ty: wgpu::BufferBindingType::Uniform,
-							has_dynamic_offset: false,
-							min_binding_size: None,
-						},
-						count: None,
-					},
-					wgpu::BindGroupLayoutEntry {
-						binding: 1,
-						visibility: wgpu::ShaderStages::FRAGMENT,
-						ty: wgpu::BindingType::Buffer {
-							ty: wgpu::BufferBindingType::Uniform,
-							has_dynamic_offset: false,
-							min_binding_size: None,
-						},
-						count: None,
+				entries: &[wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					visibility: wgpu::ShaderStages::VERTEX,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
 					},
-				],
+					count: None,
+				}],
 				label: Some("World rendering local bind group layout"),
 			});
 		let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -125,80 +147,48 @@ impl WorldRenderingPipeline {
 			push_constant_ranges: &[],
 		});
 		let shader = device.create_shader_module(wgpu::include_wgsl!("world_shader.wgsl"));
-		let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-			label: Some("World rendering pipeline"),
-			layout: Some(&layout),
-			vertex: wgpu::VertexState {
-				module: &shader,
-				entry_point: Some("vs_main"),
-				buffers: &[
-					MeshVert::buffer_layout(), // vert buffer
-				],
-				compilation_options: wgpu::PipelineCompilationOptions::default(),
-			},
-			fragment: Some(wgpu::FragmentState {
-				module: &shader,
-				entry_point: Some("fs_main"),
-				targets: &[Some(wgpu::ColorTargetState {
-					format: surface_config.format,
-					blend: Some(wgpu::BlendState::REPLACE),
-					write_mask: wgpu::ColorWrites::ALL,
-				})],
-				compilation_options: wgpu::PipelineCompilationOptions::default(),
-			}),
-			primitive: wgpu::PrimitiveState {
-				topology: wgpu::PrimitiveTopology::TriangleList,
-				strip_index_format: None,
-				front_face: wgpu::FrontFace::Ccw, // front face is counter-clockwise
-				cull_mode: Some(wgpu::Face::Back), // back cull
-				polygon_mode: wgpu::PolygonMode::Fill,
-				// Requires Features::DEPTH_CLIP_CONTROL
-				unclipped_depth: false,
-				// Requires Features::CONSERVATIVE_RASTERIZATION
-				conservative: false,
-			},
-			depth_stencil: Some(wgpu::DepthStencilState {
-				format: DepthTexture::FORMAT,
-				depth_write_enabled: true,
-				depth_compare: wgpu::CompareFunction::Less,
-				stencil: wgpu::StencilState::default(),
-				bias: wgpu::DepthBiasState::default(),
-			}),
-			multisample: wgpu::MultisampleState {
-				// idek what this stuff does
-				count: 1,
-				mask: !0,
-				alpha_to_coverage_enabled: false,
-			},
-			multiview: None,
-			cache: None,
-		});
+		let pipeline = create_pipeline(
+			device,
+			&layout,
+			&shader,
+			surface_config.format,
+			BlockPass::Opaque,
+		);
+		let translucent_pipeline = create_pipeline(
+			device,
+			&layout,
+			&shader,
+			surface_config.format,
+			BlockPass::Translucent,
+		);
 
 		Ok(WorldRenderingPipeline {
 			pipeline,
+			translucent_pipeline,
 			global_bind_group,
 			local_bind_group_layout,
-			block_texture_atlas,
+			block_textures,
 		})
 	}
 
-	/// Executes a render pass on the given command encoder
+	/// Executes a render pass for opaque blocks on the given command encoder
 	///
-	/// Clears to white, also clears depth texture
+	/// Loads previous color attachment (the sky), clears depth texture. Chunks outside of the frustum are skipped
 	pub fn execute_render_pass(
 		&self,
 		encoder: &mut wgpu::CommandEncoder,
 		surface_texture_view: &wgpu::TextureView,
 		depth_texture_view: &wgpu::TextureView,
 		world_data: &WorldData,
-	) {
+		frustum: &Frustum,
+	) -> ChunkDrawStats {
 		let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
 			label: Some("World rendering pass"),
 			color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-				view: &surface_texture_view,
+				view: surface_texture_view,
 				resolve_target: None,
 				ops: wgpu::Operations {
-					load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+					load: wgpu::LoadOp::Load,
 					store: wgpu::StoreOp::Store,
 				},
 			})],
@@ -219,96 +209,285 @@ impl WorldRenderingPipeline {
 		render_pass.set_bind_group(0, &self.global_bind_group, &[]);
 
 		// drawing chunks
-		for (_pos, chunk) in world_data.chunks.iter() {
-			for mesh in chunk.meshes.iter() {
-				let Some(mesh_render_objs) = mesh.get_render_objs() else {
-					continue;
-				};
+		let mut stats = ChunkDrawStats::default();
+		for (pos, chunk) in world_data.chunks.iter() {
+			let mesh = &chunk.meshes.opaque;
+			if mesh.get_render_objs().is_none() {
+				continue;
+			}
 
-				// setting local bind group
-				render_pass.set_bind_group(1, &mesh_render_objs.bind_group, &[]);
+			// skipping chunks the camera can't see
+			if !is_chunk_visible(*pos, frustum) {
+				stats.culled += 1;
+				continue;
+			}
+			stats.drawn += 1;
+			draw_mesh(&mut render_pass, mesh);
+		}
+		stats
+	}
 
-				// setting vert and tri buffers
-				render_pass.set_vertex_buffer(0, mesh_render_objs.vertex_buffer.slice(..));
-				render_pass.set_index_buffer(
-					mesh_render_objs.index_buffer.slice(..),
-					wgpu::IndexFormat::Uint32,
-				);
+	/// Executes a render pass for translucent blocks on the given command encoder, after the opaque
+	/// pass
+	///
+	/// Loads previous color attachment and depth texture. Chunks are drawn from the furthest to the
+	/// closest to `camera_pos`, so the ones behind show through the ones in front. The quads within
+	/// each chunk are kept in the same order by `Game::prep_meshes`
+	pub fn execute_translucent_render_pass(
+		&self,
+		encoder: &mut wgpu::CommandEncoder,
+		surface_texture_view: &wgpu::TextureView,
+		depth_texture_view: &wgpu::TextureView,
+		world_data: &WorldData,
+		frustum: &Frustum,
+		camera_pos: [f32; 3],
+	) {
+		let mut chunks: Vec<(f32, &Mesh)> = world_data
+			.chunks
+			.iter()
+			.filter(|(pos, chunk)| {
+				chunk.meshes.translucent.get_render_objs().is_some()
+					&& is_chunk_visible(**pos, frustum)
+			})
+			.map(|(pos, chunk)| {
+				let center = [
+					(pos.x as f32 + 0.5) * CHUNK_WIDTH as f32,
+					(pos.z as f32 + 0.5) * CHUNK_WIDTH as f32,
+				];
+				let dist =
+					(center[0] - camera_pos[0]).powi(2) + (center[1] - camera_pos[2]).powi(2);
+				(dist, &chunk.meshes.translucent)
+			})
+			.collect();
+		if chunks.is_empty() {
+			return;
+		}
+		chunks.sort_unstable_by(|(a, _), (b, _)| b.total_cmp(a));
 
-				// draw
-				render_pass.draw_indexed(0..(mesh.n_tris * 3), 0, 0..1);
-			}
+		let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+			label: Some("Translucent world rendering pass"),
+			color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+				view: surface_texture_view,
+				resolve_target: None,
+				ops: wgpu::Operations {
+					load: wgpu::LoadOp::Load,
+					store: wgpu::StoreOp::Store,
+				},
+			})],
+			depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+				view: depth_texture_view,
+				depth_ops: Some(wgpu::Operations {
+					load: wgpu::LoadOp::Load,
+					store: wgpu::StoreOp::Store,
+				}),
+				stencil_ops: None,
+			}),
+			occlusion_query_set: None,
+			timestamp_writes: None,
+		});
+		render_pass.set_pipeline(&self.translucent_pipeline);
+		render_pass.set_bind_group(0, &self.global_bind_group, &[]);
+		for (_, mesh) in chunks {
+			draw_mesh(&mut render_pass, mesh);
 		}
 	}
 }
 
+/// Which kind of blocks a world pipeline draws
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BlockPass {
+	/// Opaque and cutout blocks, with fully transparent pixels discarded
+	Opaque,
+	/// Translucent blocks, blended with what's behind them without hiding it in the depth buffer
+	Translucent,
+}
+
+fn create_pipeline(
+	device: &wgpu::Device,
+	layout: &wgpu::PipelineLayout,
+	shader: &wgpu::ShaderModule,
+	format: wgpu::TextureFormat,
+	pass: BlockPass,
+) -> wgpu::RenderPipeline {
+	let translucent = pass == BlockPass::Translucent;
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some(if translucent {
+			"Translucent world rendering pipeline"
+		} else {
+			"World rendering pipeline"
+		}),
+		layout: Some(layout),
+		vertex: wgpu::VertexState {
+			module: shader,
+			entry_point: Some("vs_main"),
+			buffers: &[
+				MeshVert::buffer_layout(), // vert buffer
+			],
+			compilation_options: wgpu::PipelineCompilationOptions::default(),
+		},
+		fragment: Some(wgpu::FragmentState {
+			module: shader,
+			entry_point: Some(if translucent {
+				"fs_translucent"
+			} else {
+				"fs_main"
+			}),
+			targets: &[Some(wgpu::ColorTargetState {
+				format,
+				blend: Some(if translucent {
+					wgpu::BlendState::ALPHA_BLENDING
+				} else {
+					wgpu::BlendState::REPLACE
+				}),
+				write_mask: wgpu::ColorWrites::ALL,
+			})],
+			compilation_options: wgpu::PipelineCompilationOptions::default(),
+		}),
+		primitive: wgpu::PrimitiveState {
+			topology: wgpu::PrimitiveTopology::TriangleList,
+			strip_index_format: None,
+			front_face: wgpu::FrontFace::Ccw, // front face is counter-clockwise
+			cull_mode: Some(wgpu::Face::Back), // back cull
+			polygon_mode: wgpu::PolygonMode::Fill,
+			// Requires Features::DEPTH_CLIP_CONTROL
+			unclipped_depth: false,
+			// Requires Features::CONSERVATIVE_RASTERIZATION
+			conservative: false,
+		},
+		depth_stencil: Some(wgpu::DepthStencilState {
+			format: DepthTexture::FORMAT,
+			// translucent faces don't hide the ones behind them
+			depth_write_enabled: !translucent,
+			depth_compare: wgpu::CompareFunction::Less,
+			stencil: wgpu::StencilState::default(),
+			bias: wgpu::DepthBiasState::default(),
+		}),
+		multisample: wgpu::MultisampleState {
+			// idek what this stuff does
+			count: 1,
+			mask: !0,
+			alpha_to_coverage_enabled: false,
+		},
+		multiview: None,
+		cache: None,
+	})
+}
+
+/// Whether any part of a chunk is inside the frustum
+fn is_chunk_visible(pos: ChunkPos, frustum: &Frustum) -> bool {
+	let min = Vector3::new(
+		(pos.x * CHUNK_WIDTH as i32) as f32,
+		0.0,
+		(pos.z * CHUNK_WIDTH as i32) as f32,
+	);
+	let max = min + Vector3::new(CHUNK_WIDTH as f32, WORLD_HEIGHT as f32, CHUNK_WIDTH as f32);
+	frustum.intersects_aabb(min, max)
+}
+
+/// Draws a loaded mesh with its local bind group
+fn draw_mesh(render_pass: &mut wgpu::RenderPass, mesh: &Mesh) {
+	let Some(mesh_render_objs) = mesh.get_render_objs() else {
+		return;
+	};
+
+	// setting local bind group
+	render_pass.set_bind_group(1, &mesh_render_objs.bind_group, &[]);
+
+	// setting vert and tri buffers
+	render_pass.set_vertex_buffer(0, mesh_render_objs.vertex_buffer.slice(..));
+	render_pass.set_index_buffer(
+		mesh_render_objs.index_buffer.slice(..),
+		wgpu::IndexFormat::Uint32,
+	);
+
+	// draw
+	render_pass.draw_indexed(0..(mesh.n_tris * 3), 0, 0..1);
+}
+
 /// helper function that reads block textures for every block type from file
 ///
-/// returns a vector of keys that belong to an image
-pub fn read_block_textures() -> Result<Vec<(Vec<TextureAtlasKey>, RgbaImage)>, ()> {
+/// returns a vector of keys that belong to an image. Textures that can't be read are left out, so
+/// their keys fall back to the null texture
+pub fn read_block_textures() -> Vec<(Vec<TextureAtlasKey>, RgbaImage)> {
+	let texture_dir = Path::new("./assets/block_textures");
+
 	// hashmap of every image path and the keys that need it.
 	// This exists to remove redundant image loads, if there are multiple block types that reference the same file
 	let mut filepaths: HashMap<PathBuf, Vec<TextureAtlasKey>> = HashMap::new();
 
 	let mut record_filepath = |path: PathBuf, keys: Vec<TextureAtlasKey>| {
-		if filepaths.contains_key(&path) {
-			filepaths.get_mut(&path).unwrap().extend(keys);
-		} else {
-			filepaths.insert(path, keys);
-		}
+		filepaths.entry(path).or_default().extend(keys);
 	};
 
-	for block_type in BLOCK_TYPES.iter() {
-		match block_type.texture_layout {
-			BlockTextureLayout::Uniform(filename) => {
-				record_filepath(
-					Path::new("./assets/block_textures").join(filename),
-					vec![TextureAtlasKey::Block(block_type.id)],
-				);
-			}
-			BlockTextureLayout::TopSideBottom {
-				top: top_filename,
-				sides: side_filename,
-				bottom: bottom_filename,
-			} => {
-				record_filepath(
-					Path::new("./assets/block_textures").join(top_filename),
-					vec![TextureAtlasKey::BlockFace(block_type.id, Direction::PosY)],
-				);
-				record_filepath(
-					Path::new("./assets/block_textures").join(side_filename),
-					vec![
-						TextureAtlasKey::BlockFace(block_type.id, Direction::PosX),
-						TextureAtlasKey::BlockFace(block_type.id, Direction::NegX),
-						TextureAtlasKey::BlockFace(block_type.id, Direction::PosZ),
-						TextureAtlasKey::BlockFace(block_type.id, Direction::NegZ),
-					],
-				);
-				record_filepath(
-					Path::new("./assets/block_textures").join(bottom_filename),
-					vec![TextureAtlasKey::BlockFace(block_type.id, Direction::NegY)],
-				);
+	for block_type in registry().iter() {
+		let layouts = std::iter::once(&block_type.texture_layout).chain(
+			block_type
+				.texture_variants
+				.iter()
+				.map(|v| &v.texture_layout),
+		);
+		for (variant, layout) in layouts.enumerate() {
+			let id = block_type.id;
+			let variant = variant as u8;
+			match layout {
+				BlockTextureLayout::Uniform(filename) => {
+					record_filepath(
+						texture_dir.join(filename),
+						vec![TextureAtlasKey::Block(id, variant)],
+					);
+				}
+				BlockTextureLayout::TopSideBottom {
+					top: top_filename,
+					sides: side_filename,
+					bottom: bottom_filename,
+				} => {
+					record_filepath(
+						texture_dir.join(top_filename),
+						vec![TextureAtlasKey::BlockFace(id, variant, Direction::PosY)],
+					);
+					record_filepath(
+						texture_dir.join(side_filename),
+						vec![
+							TextureAtlasKey::BlockFace(id, variant, Direction::PosX),
+							TextureAtlasKey::BlockFace(id, variant, Direction::NegX),
+							TextureAtlasKey::BlockFace(id, variant, Direction::PosZ),
+							TextureAtlasKey::BlockFace(id, variant, Direction::NegZ),
+						],
+					);
+					record_filepath(
+						texture_dir.join(bottom_filename),
+						vec![TextureAtlasKey::BlockFace(id, variant, Direction::NegY)],
+					);
+				}
+				BlockTextureLayout::None => {}
 			}
-			BlockTextureLayout::None => {}
 		}
 	}
 
 	// reading the images
 	let mut out = Vec::new();
 	for (path, keys) in filepaths.into_iter() {
-		let img = match ImageReader::open(&path) {
-			Ok(img_reader) => img_reader.decode().unwrap().to_rgba8(),
+		let img = match ImageReader::open(&path).map(|reader| reader.decode()) {
+			Ok(Ok(img)) => img.to_rgba8(),
+			Ok(Err(e)) => {
+				log::warn!(
+					"Failed to decode block texture \"{}\", using the null texture: {}",
+					path.display(),
+					e
+				);
+				continue;
+			}
 			Err(e) => {
-				log::error!(
-					"Failed to read block texture from \"{}\": {}",
+				log::warn!(
+					"Failed to read block texture from \"{}\", using the null texture: {}",
 					path.display(),
 					e
 				);
-				return Err(());
+				continue;
 			}
 		};
 		out.push((keys, img));
 	}
 
-	return Ok(out);
+	return out;
 }
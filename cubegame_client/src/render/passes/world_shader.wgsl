@@ -11,12 +11,25 @@ var<uniform> mesh_pos: vec3<f32>;
 
 struct VertexInput {
     @location(0) pos: vec3<f32>,
-    @location(1) tex_coord: vec2<f32>
+    @location(1) tex_coord: vec2<f32>,
+    // texture altas position [x pos, y pos, x scale, y scale]
+    @location(2) atlas_rect: vec4<f32>,
+    // layer of the block texture array
+    @location(3) texture_layer: u32,
+    // brightness from ambient occlusion
+    @location(4) ao: f32,
+    // sky light and block light, from 0 to 1
+    @location(5) light: vec2<f32>,
 }
 
 struct VertexOutput {
     @builtin(position) clip_pos: vec4<f32>,
-    @location(0) tex_coord: vec2<f32>
+    @location(0) tex_coord: vec2<f32>,
+    @location(1) @interpolate(flat) atlas_rect: vec4<f32>,
+    @location(2) @interpolate(flat) texture_layer: u32,
+    @location(3) ao: f32,
+    @location(4) light: vec2<f32>,
+    @location(5) world_pos: vec3<f32>,
 };
 
 @vertex
@@ -25,26 +38,81 @@ fn vs_main(
 ) -> VertexOutput {
 
     var out: VertexOutput;
-    out.clip_pos = camera.view_proj * vec4<f32>(vert.pos + mesh_pos, 1.0);
+    out.world_pos = vert.pos + mesh_pos;
+    out.clip_pos = camera.view_proj * vec4<f32>(out.world_pos, 1.0);
     out.tex_coord = vert.tex_coord;
+    out.atlas_rect = vert.atlas_rect;
+    out.texture_layer = vert.texture_layer;
+    out.ao = vert.ao;
+    out.light = vert.light;
     return out;
 }
 
 @group(0) @binding(1)
-var atlas_texture: texture_2d<f32>;
+var block_texture: texture_2d_array<f32>;
 @group(0) @binding(2)
-var atlas_sampler: sampler;
+var block_sampler: sampler;
 
-// texture altas position [x pos, y pos, x scale, y scale]
-@group(1) @binding(1)
-var<uniform> atlas_pos: vec4<f32>;
+struct Environment {
+    inv_view_proj: mat4x4<f32>,
+    camera_pos: vec4<f32>,
+    sun_dir: vec4<f32>,
+    horizon_color: vec4<f32>,
+    zenith_color: vec4<f32>,
+    fog_color: vec4<f32>,
+    sky_light: f32,
+    fog_start: f32,
+    fog_end: f32,
+};
+@group(0) @binding(3)
+var<uniform> env: Environment;
+
+// brightness of completely unlit blocks
+const MIN_BRIGHTNESS: f32 = 0.03;
+
+// each light level is a bit darker than the one above it
+fn light_brightness(level: f32) -> f32 {
+    return max(pow(0.8, (1.0 - level) * 15.0), MIN_BRIGHTNESS);
+}
 
+// textures of cutout blocks (like leaves) have gaps below this alpha
+const ALPHA_CUTOFF: f32 = 0.5;
+
+// opaque and cutout blocks
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let size = textureDimensions(atlas_texture);
+    let color = shade(in);
+    if color.a < ALPHA_CUTOFF {
+        discard;
+    }
+    return vec4<f32>(color.rgb, 1.0);
+}
+
+// translucent blocks, blended with what's behind them
+@fragment
+fn fs_translucent(in: VertexOutput) -> @location(0) vec4<f32> {
+    return shade(in);
+}
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    // merged faces have texture coords going past 1, the texture repeats once per block
+    let tile_coord = fract(in.tex_coord);
+
+    // translating texture coord to its place on the layer
+    let coord = tile_coord * in.atlas_rect.zw + in.atlas_rect.xy;
+
+    // fract jumps at the edge of every block, so the mip level is picked from the unwrapped coords
+    let ddx = dpdx(in.tex_coord) * in.atlas_rect.zw;
+    let ddy = dpdy(in.tex_coord) * in.atlas_rect.zw;
+    let color = textureSampleGrad(block_texture, block_sampler, coord, in.texture_layer, ddx, ddy);
+
+    // sky light fades at night, block light doesn't
+    let light = light_brightness(max(in.light.x * env.sky_light, in.light.y));
 
-    // translating texture coord to atlas
-    let coord = vec2<f32>(in.tex_coord.x * atlas_pos[2] + atlas_pos[0], in.tex_coord.y * atlas_pos[3] + atlas_pos[1]);
+    // fading out towards the edge of the loaded chunks
+    let distance = length(in.world_pos.xz - env.camera_pos.xz);
+    let fog = smoothstep(env.fog_start, env.fog_end, distance);
+    let lit = color.rgb * in.ao * light;
 
-    return textureSample(atlas_texture, atlas_sampler, coord);
+    return vec4<f32>(mix(lit, env.fog_color.rgb, fog), color.a);
 }
@@ -1,4 +1,4 @@
-use nalgebra::{geometry::Perspective3, Matrix4};
+use nalgebra::{geometry::Perspective3, Matrix4, Vector3, Vector4};
 
 /// Translates from opengl coord system to wgpu
 pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
@@ -31,3 +31,124 @@ impl Perspective {
 			.to_homogeneous()
 	}
 }
+
+/// Volume that can be seen by a camera, as 6 planes (left, right, bottom, top, near, far)
+///
+/// Each plane is [normal x, normal y, normal z, distance], with normals pointing inwards
+pub struct Frustum {
+	planes: [Vector4<f32>; 6],
+}
+impl Frustum {
+	/// Extracts the frustum planes from a view projection matrix (Gribb-Hartmann method)
+	///
+	/// The matrix needs to map to wgpu's clip space, where z goes from 0 to 1
+	pub fn from_view_proj(m: &Matrix4<f32>) -> Frustum {
+		let row = |i: usize| m.row(i).transpose();
+		let planes = [
+			row(3) + row(0),
+			row(3) - row(0),
+			row(3) + row(1),
+			row(3) - row(1),
+			row(2),
+			row(3) - row(2),
+		]
+		.map(|plane| plane / plane.xyz().norm());
+		Frustum { planes }
+	}
+
+	/// Checks whether an axis aligned box is at least partly inside the frustum
+	///
+	/// Might give false positives for boxes near the frustum's corners, never false negatives
+	pub fn intersects_aabb(&self, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+		self.planes.iter().all(|plane| {
+			// corner of the box furthest along the plane's normal
+			let corner = Vector3::new(
+				if plane.x >= 0.0 { max.x } else { min.x },
+				if plane.y >= 0.0 { max.y } else { min.y },
+				if plane.z >= 0.0 { max.z } else { min.z },
+			);
+			plane.xyz().dot(&corner) + plane.w >= 0.0
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use nalgebra::Point3;
+
+	/// Camera at the origin looking down -z, like a player with no rotation
+	fn test_matrix() -> Matrix4<f32> {
+		let perspective = Perspective::new(16.0 / 9.0, 70.0, 0.1, 100.0);
+		OPENGL_TO_WGPU_MATRIX * perspective.proj_matrix()
+	}
+
+	fn point_visible(frustum: &Frustum, x: f32, y: f32, z: f32) -> bool {
+		let p = Vector3::new(x, y, z);
+		frustum.intersects_aabb(p, p)
+	}
+
+	#[test]
+	fn planes_match_clip_space() {
+		let m = test_matrix();
+		let frustum = Frustum::from_view_proj(&m);
+		for plane in frustum.planes.iter() {
+			assert!((plane.xyz().norm() - 1.0).abs() < 1e-5);
+		}
+
+		// points are in the frustum exactly when they end up in wgpu's clip volume
+		let mut checked_inside = 0;
+		for x in -20..=20 {
+			for y in -20..=20 {
+				for z in -22..=2 {
+					let p = Point3::new(x as f32 * 2.5, y as f32 * 2.5, z as f32 * 5.0 + 0.05);
+					let clip: Vector4<f32> = m * p.to_homogeneous();
+					let inside = clip.x.abs() <= clip.w
+						&& clip.y.abs() <= clip.w
+						&& clip.z >= 0.0 && clip.z <= clip.w;
+					// points right on a plane could go either way with rounding
+					let margin = [
+						clip.w - clip.x.abs(),
+						clip.w - clip.y.abs(),
+						clip.z,
+						clip.w - clip.z,
+					]
+					.into_iter()
+					.fold(f32::MAX, f32::min)
+					.abs();
+					if margin < 1e-3 {
+						continue;
+					}
+					assert_eq!(point_visible(&frustum, p.x, p.y, p.z), inside, "{}", p);
+					checked_inside += inside as u32;
+				}
+			}
+		}
+		assert!(checked_inside > 100);
+	}
+
+	#[test]
+	fn boxes_are_culled_outside_the_frustum() {
+		let frustum = Frustum::from_view_proj(&test_matrix());
+		let chunk = |x: f32, y: f32, z: f32| {
+			let min = Vector3::new(x, y, z);
+			frustum.intersects_aabb(min, min + Vector3::new(16.0, 16.0, 16.0))
+		};
+
+		// in front
+		assert!(chunk(-8.0, -8.0, -30.0));
+		// containing the camera
+		assert!(chunk(-8.0, -8.0, -8.0));
+		// near the edge of the view
+		assert!(chunk(-60.0, -8.0, -60.0));
+		// behind
+		assert!(!chunk(-8.0, -8.0, 10.0));
+		// past the far plane
+		assert!(!chunk(-8.0, -8.0, -130.0));
+		// off to the sides, above and below
+		assert!(!chunk(-100.0, -8.0, -30.0));
+		assert!(!chunk(80.0, -8.0, -30.0));
+		assert!(!chunk(-8.0, 50.0, -30.0));
+		assert!(!chunk(-8.0, -70.0, -30.0));
+	}
+}
@@ -0,0 +1,68 @@
+use super::atlas::TextureAtlasKey;
+use super::{generate_mips, BlockTextures, LoadedTexture, TextureFilter, TextureRef, TextureRefs};
+use cubegame_lib::blocks::NULL_BLOCK_ID;
+use image::{imageops, RgbaImage};
+use std::collections::HashMap;
+
+/// Loads every texture into its own layer of a texture array, with mipmaps
+///
+/// Textures that aren't as big as the biggest one get scaled up to its size
+pub fn generate(
+	images: Vec<(Vec<TextureAtlasKey>, RgbaImage)>,
+	filter: TextureFilter,
+	device: &wgpu::Device,
+	queue: &wgpu::Queue,
+) -> Result<BlockTextures, ()> {
+	let max_layers = device.limits().max_texture_array_layers as usize;
+	if images.is_empty() || images.len() > max_layers {
+		log::error!(
+			"Can't load {} textures into a texture array (at most {} layers)",
+			images.len(),
+			max_layers
+		);
+		return Err(());
+	}
+	let width = images.iter().map(|(_, img)| img.width()).max().unwrap();
+	let height = images.iter().map(|(_, img)| img.height()).max().unwrap();
+
+	let mut refs = HashMap::new();
+	let mut layers = Vec::with_capacity(images.len());
+	for (layer, (keys, img)) in images.into_iter().enumerate() {
+		let texture_ref = TextureRef {
+			layer: layer as u32,
+			rect: [0.0, 0.0, 1.0, 1.0],
+		};
+		for key in keys {
+			refs.insert(key, texture_ref);
+
+			// adding special null key to null block
+			if key == TextureAtlasKey::Block(NULL_BLOCK_ID, 0) {
+				refs.insert(TextureAtlasKey::Null, texture_ref);
+			}
+		}
+
+		let img = if img.dimensions() == (width, height) {
+			img
+		} else {
+			imageops::resize(&img, width, height, imageops::FilterType::Nearest)
+		};
+		layers.push(generate_mips(img));
+	}
+	log::debug!("Put {} textures into texture array", layers.len());
+
+	// loading
+	let texture = LoadedTexture::load_array(
+		&layers,
+		"Block texture array",
+		wgpu::AddressMode::Repeat,
+		filter,
+		device,
+		queue,
+		&BlockTextures::bind_group_layout(device),
+	);
+
+	Ok(BlockTextures {
+		texture,
+		refs: TextureRefs::new(refs),
+	})
+}
@@ -1,144 +1,190 @@
 // put this in its own file cus can, sue me
 
-use super::LoadedTexture;
+use super::{
+	generate_mips_limited, BlockTextures, LoadedTexture, TextureFilter, TextureRef, TextureRefs,
+};
 use crunch::*;
 use cubegame_lib::blocks::NULL_BLOCK_ID;
 use cubegame_lib::{BlockTypeId, Direction};
 use image::{imageops, RgbaImage};
 use std::collections::HashMap;
-use std::hash::{DefaultHasher, Hash, Hasher};
-use wgpu::BindGroupLayoutDescriptor;
-
-/// Wrapper struct for making and reading from a texture atlas
-pub struct TextureAtlas {
-	/// Texture containing all the sprites
-	pub texture: LoadedTexture,
-	/// Maps each key to a position on the atlas: [x pos, y pos, x scale, y scale]
-	map: HashMap<TextureAtlasKey, [f32; 4]>,
+
+/// Number of mip levels for textures up to `size` texels wide: the full chain, down to the biggest
+/// texture being a single texel (smaller levels would blend textures with their neighbours)
+fn mip_levels(size: u32) -> u32 {
+	size.max(1).ilog2() + 1
 }
-impl TextureAtlas {
-	pub fn generate(
-		images: Vec<(Vec<TextureAtlasKey>, RgbaImage)>,
-		device: &wgpu::Device,
-		queue: &wgpu::Queue,
-	) -> Result<TextureAtlas, ()> {
-		let max_size = device.limits().max_texture_dimension_2d as usize;
-
-		let get_hash_of_keys = |keys: &Vec<TextureAtlasKey>| -> u64 {
-			let mut hasher = DefaultHasher::new();
-			keys.hash(&mut hasher);
-			hasher.finish()
-		};
-
-		// packing
-		let items = images.iter().map(|(keys, img)| -> Item<u64> {
-			Item::new(
-				get_hash_of_keys(keys),
-				img.width() as usize,
-				img.height() as usize,
-				Rotation::None,
-			)
-		});
-		let result: PackedItems<u64> = match pack_into_po2(max_size, items) {
-			Ok(res) => res,
-			Err(_) => {
-				log::error!("Failed to pack textures into texture atlas");
-				return Err(());
-			}
-		};
-		log::debug!("Packed {} textures into atlas", images.len());
-
-		// creating image and position map
-		let mut rect_map = HashMap::new();
-		let mut atlas = RgbaImage::new(result.w as u32, result.h as u32);
-		for item in result.items.into_iter() {
-			// finding keys for this item by hash
-			let keys_hash = item.data;
-			let (keys, img) = images
-				.iter()
-				.find(|(keys, _img)| keys_hash == get_hash_of_keys(keys))
-				.unwrap();
-
-			// overlay image onto atlas
-			imageops::overlay(&mut atlas, img, item.rect.x as i64, item.rect.y as i64);
-
-			let rect = [
-				item.rect.x as f32 / atlas.width() as f32,
-				item.rect.y as f32 / atlas.height() as f32,
-				item.rect.w as f32 / atlas.width() as f32,
-				item.rect.h as f32 / atlas.height() as f32,
-			];
-			for key in keys {
-				rect_map.insert(*key, rect);
-
-				// adding special null key to null block
-				if let TextureAtlasKey::Block(type_id) = key {
-					if *type_id == NULL_BLOCK_ID {
-						rect_map.insert(
-							TextureAtlasKey::Null,
-							[
-								item.rect.x as f32 / atlas.width() as f32,
-								item.rect.y as f32 / atlas.height() as f32,
-								item.rect.w as f32 / atlas.width() as f32,
-								item.rect.h as f32 / atlas.height() as f32,
-							],
-						);
-					}
+
+/// Texels of wrapped-around texture put around each texture, so filtering and smaller mip levels
+/// don't pick up colours from the textures next to it
+///
+/// Each mip level halves it, so it's big enough for the smallest level to still have a texel of
+/// padding. Padded textures are also sized and placed at multiples of it, so they line up with
+/// texels on every level
+fn padding(mip_levels: u32) -> u32 {
+	1 << (mip_levels - 1)
+}
+
+/// Packs every texture into a single image, loaded as a texture array with one layer
+pub fn generate(
+	images: Vec<(Vec<TextureAtlasKey>, RgbaImage)>,
+	filter: TextureFilter,
+	device: &wgpu::Device,
+	queue: &wgpu::Queue,
+) -> Result<BlockTextures, ()> {
+	let max_size = device.limits().max_texture_dimension_2d as usize;
+	let biggest = images
+		.iter()
+		.map(|(_keys, img)| img.width().max(img.height()))
+		.max()
+		.unwrap_or(1);
+	let mip_levels = mip_levels(biggest);
+	let padding = padding(mip_levels);
+
+	// mips are made for each texture on its own, so they never blend with their neighbours
+	let padded: Vec<Vec<RgbaImage>> = images
+		.iter()
+		.map(|(_keys, img)| generate_mips_limited(pad(img, padding), mip_levels))
+		.collect();
+
+	// packing
+	let items = padded.iter().enumerate().map(|(i, mips)| -> Item<usize> {
+		Item::new(
+			i,
+			mips[0].width() as usize,
+			mips[0].height() as usize,
+			Rotation::None,
+		)
+	});
+	let result: PackedItems<usize> = match pack_into_po2(max_size, items) {
+		Ok(res) => res,
+		Err(_) => {
+			log::error!("Failed to pack textures into texture atlas");
+			return Err(());
+		}
+	};
+	log::debug!("Packed {} textures into atlas", images.len());
+
+	// creating every mip level of the atlas and the position map
+	let (width, height) = (result.w as u32, result.h as u32);
+	let mut levels: Vec<RgbaImage> = (0..mip_levels)
+		.map(|level| RgbaImage::new((width >> level).max(1), (height >> level).max(1)))
+		.collect();
+	let mut rect_map = HashMap::new();
+	for item in result.items.into_iter() {
+		let (keys, img) = &images[item.data];
+		let (x, y) = (item.rect.x as u32, item.rect.y as u32);
+		debug_assert!(x % padding == 0 && y % padding == 0);
+
+		// overlay every mip level of the image onto the same level of the atlas
+		for (level, (atlas_level, mip)) in levels.iter_mut().zip(&padded[item.data]).enumerate() {
+			imageops::overlay(atlas_level, mip, (x >> level) as i64, (y >> level) as i64);
+		}
+
+		// the rect only covers the texture, not its padding
+		let rect = [
+			(x + padding) as f32 / width as f32,
+			(y + padding) as f32 / height as f32,
+			img.width() as f32 / width as f32,
+			img.height() as f32 / height as f32,
+		];
+		for key in keys {
+			rect_map.insert(*key, TextureRef { layer: 0, rect });
+
+			// adding special null key to null block
+			if let TextureAtlasKey::Block(type_id, 0) = key {
+				if *type_id == NULL_BLOCK_ID {
+					rect_map.insert(TextureAtlasKey::Null, TextureRef { layer: 0, rect });
 				}
 			}
 		}
-
-		#[cfg(debug_assertions)]
-		atlas.save("texture_atlas.png").unwrap();
-
-		// loading
-		let texture = LoadedTexture::load_from_img(
-			atlas,
-			"Texture atlas",
-			device,
-			queue,
-			&device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-				label: Some("Texture atlas bind group layout"),
-				entries: &[
-					wgpu::BindGroupLayoutEntry {
-						binding: 0,
-						visibility: wgpu::ShaderStages::FRAGMENT,
-						ty: wgpu::BindingType::Texture {
-							multisampled: false,
-							view_dimension: wgpu::TextureViewDimension::D2,
-							sample_type: wgpu::TextureSampleType::Float { filterable: false },
-						},
-						count: None,
-					},
-					wgpu::BindGroupLayoutEntry {
-						binding: 1,
-						visibility: wgpu::ShaderStages::FRAGMENT,
-						ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-						count: None,
-					},
-				],
-			}),
-		);
-
-		Ok(TextureAtlas {
-			texture,
-			map: rect_map,
-		})
 	}
 
-	pub fn get_pos_of(&self, key: TextureAtlasKey) -> Option<&[f32; 4]> {
-		self.map.get(&key)
-	}
+	#[cfg(debug_assertions)]
+	levels[0].save("texture_atlas.png").unwrap();
+
+	// loading
+	let texture = LoadedTexture::load_array(
+		&[levels],
+		"Texture atlas",
+		wgpu::AddressMode::ClampToEdge,
+		filter,
+		device,
+		queue,
+		&BlockTextures::bind_group_layout(device),
+	);
+
+	Ok(BlockTextures {
+		texture,
+		refs: TextureRefs::new(rect_map),
+	})
+}
+
+/// Surrounds a texture with `padding` texels of itself repeating, rounding the size up to a
+/// multiple of `padding`
+fn pad(img: &RgbaImage, padding: u32) -> RgbaImage {
+	let width = (img.width() + padding * 2).next_multiple_of(padding);
+	let height = (img.height() + padding * 2).next_multiple_of(padding);
+	RgbaImage::from_fn(width, height, |x, y| {
+		let src_x = (x as i64 - padding as i64).rem_euclid(img.width() as i64);
+		let src_y = (y as i64 - padding as i64).rem_euclid(img.height() as i64);
+		*img.get_pixel(src_x as u32, src_y as u32)
+	})
 }
 
+/// Identifies a texture in the atlas
+///
+/// Blocks are keyed by their type and texture variant (see `BlockType::texture_layout_in`)
 #[derive(Clone, Copy, Hash, Eq, PartialEq, Debug)]
 pub enum TextureAtlasKey {
 	/// Sampling a uniform block
-	Block(BlockTypeId),
+	Block(BlockTypeId, u8),
 	/// Sampling a specific face of a non-uniform block
-	BlockFace(BlockTypeId, Direction),
+	BlockFace(BlockTypeId, u8, Direction),
 	/*/// Sampling the side of a non-uniform block
 	BlockSides(BlockTypeId),*/
 	/// Null (fallback) texture
 	Null,
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::Rgba;
+
+	#[test]
+	fn padding_repeats_the_texture_and_is_aligned() {
+		let img = RgbaImage::from_fn(5, 3, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+		let padding = padding(mip_levels(5));
+		let padded = pad(&img, padding);
+		assert_eq!(padded.width() % padding, 0);
+		assert_eq!(padded.height() % padding, 0);
+		assert!(padded.width() >= img.width() + padding * 2);
+
+		for y in 0..padded.height() {
+			for x in 0..padded.width() {
+				let expected = img.get_pixel(
+					(x + img.width() * padding - padding) % img.width(),
+					(y + img.height() * padding - padding) % img.height(),
+				);
+				assert_eq!(padded.get_pixel(x, y), expected, "at {}, {}", x, y);
+			}
+		}
+	}
+
+	#[test]
+	fn mip_chain_goes_down_to_a_texel_with_padding_left() {
+		let img = RgbaImage::from_pixel(16, 16, Rgba([200, 100, 50, 255]));
+		let levels = mip_levels(16);
+		assert_eq!(levels, 5);
+		let padding = padding(levels);
+		let mips = generate_mips_limited(pad(&img, padding), levels);
+		assert_eq!(mips.len() as u32, levels);
+
+		// the texture is a single texel on the last level, with a texel of padding on each side
+		let last = mips.last().unwrap();
+		assert_eq!((last.width(), last.height()), (3, 3));
+		assert_eq!(padding >> (levels - 1), 1);
+		assert_eq!(img.width() >> (levels - 1), 1);
+	}
+}
@@ -1,7 +1,13 @@
+pub mod array;
 pub mod atlas;
 pub mod depth_buffer;
 
-use image::RgbaImage;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use atlas::TextureAtlasKey;
+use image::{imageops, RgbaImage};
 use wgpu::{
 	BindGroupDescriptor, BindGroupLayout, Device, Queue, Sampler, Texture, TextureDescriptor,
 	TextureView,
@@ -16,24 +22,30 @@ pub struct LoadedTexture {
 	pub bind_group: wgpu::BindGroup,
 }
 impl LoadedTexture {
-	pub fn load_from_img(
-		img: RgbaImage,
+	/// Loads a 2d array texture, each layer is given as its mip chain (full size image first)
+	///
+	/// All layers must have the same size and number of mip levels
+	pub fn load_array(
+		layers: &[Vec<RgbaImage>],
 		name: &str,
+		address_mode: wgpu::AddressMode,
+		filter: TextureFilter,
 		device: &Device,
 		queue: &Queue,
 		bind_group_layout: &BindGroupLayout,
 	) -> LoadedTexture {
-		let dimensions = img.dimensions();
+		let dimensions = layers[0][0].dimensions();
+		let mip_level_count = layers[0].len() as u32;
 
 		let texture_size = wgpu::Extent3d {
 			width: dimensions.0,
 			height: dimensions.1,
-			depth_or_array_layers: 1,
+			depth_or_array_layers: layers.len() as u32,
 		};
 		let texture = device.create_texture(&TextureDescriptor {
 			label: Some(name),
 			size: texture_size,
-			mip_level_count: 1,
+			mip_level_count,
 			sample_count: 1,
 			dimension: wgpu::TextureDimension::D2,
 			format: wgpu::TextureFormat::Rgba8UnormSrgb, // <-- format for srgb ig
@@ -41,33 +53,40 @@ impl LoadedTexture {
 			view_formats: &[],
 		});
 
-		// writing image data to texture
-		queue.write_texture(
-			wgpu::ImageCopyTexture {
-				texture: &texture,
-				mip_level: 0,
-				origin: wgpu::Origin3d::ZERO,
-				aspect: wgpu::TextureAspect::All,
-			},
-			&img,
-			wgpu::ImageDataLayout {
-				offset: 0,
-				bytes_per_row: Some(4 * dimensions.0),
-				rows_per_image: Some(dimensions.1),
-			},
-			texture_size,
-		);
+		// writing image data to texture, one mip level of one layer at a time
+		for (layer, mips) in layers.iter().enumerate() {
+			for (mip_level, img) in mips.iter().enumerate() {
+				queue.write_texture(
+					wgpu::ImageCopyTexture {
+						texture: &texture,
+						mip_level: mip_level as u32,
+						origin: wgpu::Origin3d {
+							x: 0,
+							y: 0,
+							z: layer as u32,
+						},
+						aspect: wgpu::TextureAspect::All,
+					},
+					img,
+					wgpu::ImageDataLayout {
+						offset: 0,
+						bytes_per_row: Some(4 * img.width()),
+						rows_per_image: Some(img.height()),
+					},
+					wgpu::Extent3d {
+						width: img.width(),
+						height: img.height(),
+						depth_or_array_layers: 1,
+					},
+				);
+			}
+		}
 
-		let texture_view = texture.create_view(&Default::default());
-		let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-			address_mode_u: wgpu::AddressMode::ClampToEdge,
-			address_mode_v: wgpu::AddressMode::ClampToEdge,
-			address_mode_w: wgpu::AddressMode::ClampToEdge,
-			mag_filter: wgpu::FilterMode::Nearest,
-			min_filter: wgpu::FilterMode::Nearest,
-			mipmap_filter: wgpu::FilterMode::Nearest,
+		let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
+			dimension: Some(wgpu::TextureViewDimension::D2Array),
 			..Default::default()
 		});
+		let sampler = device.create_sampler(&filter.sampler_descriptor(address_mode));
 		let bind_group = device.create_bind_group(&BindGroupDescriptor {
 			layout: bind_group_layout,
 			entries: &[
@@ -84,10 +103,13 @@ impl LoadedTexture {
 		});
 
 		log::debug!(
-			"Loaded texture for \"{}\" ({}x{})",
+			"Loaded texture for \"{}\" ({}x{}, {} layers, {} mip levels, {:?} filtering)",
 			name,
 			dimensions.0,
-			dimensions.1
+			dimensions.1,
+			layers.len(),
+			mip_level_count,
+			filter
 		);
 		LoadedTexture {
 			texture,
@@ -97,3 +119,180 @@ impl LoadedTexture {
 		}
 	}
 }
+
+/// Makes every mip level of an image, from full size down to 1x1
+pub fn generate_mips(img: RgbaImage) -> Vec<RgbaImage> {
+	generate_mips_limited(img, u32::MAX)
+}
+
+/// Makes up to `max_levels` mip levels of an image (including the full size one), stopping early at
+/// 1x1
+pub fn generate_mips_limited(img: RgbaImage, max_levels: u32) -> Vec<RgbaImage> {
+	let mut mips = vec![img];
+	loop {
+		let last = mips.last().unwrap();
+		if (last.width() == 1 && last.height() == 1) || mips.len() as u32 >= max_levels {
+			return mips;
+		}
+		let width = (last.width() / 2).max(1);
+		let height = (last.height() / 2).max(1);
+		let next = imageops::resize(last, width, height, imageops::FilterType::Triangle);
+		mips.push(next);
+	}
+}
+
+/// Ways of putting every block texture into a single texture
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TextureBackend {
+	/// Textures packed next to each other in a single image
+	#[default]
+	Atlas,
+	/// Each texture is its own layer of an array texture, with mipmaps
+	Array,
+}
+impl FromStr for TextureBackend {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"atlas" => Ok(TextureBackend::Atlas),
+			"array" => Ok(TextureBackend::Array),
+			_ => Err(()),
+		}
+	}
+}
+
+/// How block textures are filtered when sampled
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TextureFilter {
+	/// Nearest texel from the nearest mip level, blocky but shimmers in the distance
+	#[default]
+	Nearest,
+	/// Blends between texels and mip levels when minified, still blocky up close
+	Linear,
+	/// Like linear, but keeps textures sharp at grazing angles
+	///
+	/// wgpu only allows anisotropy with linear filtering everywhere, so textures are smoothed up
+	/// close as well
+	Anisotropic,
+}
+impl TextureFilter {
+	/// Highest anisotropy level asked for, drivers may clamp it lower
+	const MAX_ANISOTROPY: u16 = 16;
+
+	fn sampler_descriptor(
+		self,
+		address_mode: wgpu::AddressMode,
+	) -> wgpu::SamplerDescriptor<'static> {
+		use wgpu::FilterMode::{Linear, Nearest};
+		let (mag_filter, min_filter, anisotropy_clamp) = match self {
+			TextureFilter::Nearest => (Nearest, Nearest, 1),
+			TextureFilter::Linear => (Nearest, Linear, 1),
+			TextureFilter::Anisotropic => (Linear, Linear, Self::MAX_ANISOTROPY),
+		};
+		wgpu::SamplerDescriptor {
+			label: None,
+			address_mode_u: address_mode,
+			address_mode_v: address_mode,
+			address_mode_w: address_mode,
+			mag_filter,
+			min_filter,
+			mipmap_filter: min_filter,
+			anisotropy_clamp,
+			..Default::default()
+		}
+	}
+}
+impl FromStr for TextureFilter {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"nearest" => Ok(TextureFilter::Nearest),
+			"linear" => Ok(TextureFilter::Linear),
+			"anisotropic" => Ok(TextureFilter::Anisotropic),
+			_ => Err(()),
+		}
+	}
+}
+
+/// Where a texture is: which layer of the texture, and where on that layer
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextureRef {
+	pub layer: u32,
+	/// [x pos, y pos, x scale, y scale]
+	pub rect: [f32; 4],
+}
+
+/// Maps each key to where its texture is
+///
+/// Cheap to clone, so it can be handed to the threads building meshes
+#[derive(Clone, Debug)]
+pub struct TextureRefs(Arc<HashMap<TextureAtlasKey, TextureRef>>);
+impl TextureRefs {
+	pub fn new(map: HashMap<TextureAtlasKey, TextureRef>) -> TextureRefs {
+		TextureRefs(Arc::new(map))
+	}
+
+	/// Gets where a texture is, falling back to the null texture if it wasn't loaded
+	pub fn get(&self, key: TextureAtlasKey) -> TextureRef {
+		self.0
+			.get(&key)
+			.or_else(|| self.0.get(&TextureAtlasKey::Null))
+			.copied()
+			.unwrap_or(TextureRef {
+				layer: 0,
+				rect: [0.0, 0.0, 1.0, 1.0],
+			})
+	}
+}
+
+/// Every block texture, loaded into a single array texture by one of the backends
+pub struct BlockTextures {
+	pub texture: LoadedTexture,
+	refs: TextureRefs,
+}
+impl BlockTextures {
+	pub fn load(
+		backend: TextureBackend,
+		filter: TextureFilter,
+		images: Vec<(Vec<TextureAtlasKey>, RgbaImage)>,
+		device: &Device,
+		queue: &Queue,
+	) -> Result<BlockTextures, ()> {
+		match backend {
+			TextureBackend::Atlas => atlas::generate(images, filter, device, queue),
+			TextureBackend::Array => array::generate(images, filter, device, queue),
+		}
+	}
+
+	/// Gets where every texture is
+	pub fn refs(&self) -> TextureRefs {
+		self.refs.clone()
+	}
+
+	/// Layout of the bind group made with the texture (binding 0) and its sampler (binding 1)
+	fn bind_group_layout(device: &Device) -> BindGroupLayout {
+		device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Block textures bind group layout"),
+			entries: &[
+				wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Texture {
+						multisampled: false,
+						view_dimension: wgpu::TextureViewDimension::D2Array,
+						sample_type: wgpu::TextureSampleType::Float { filterable: true },
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 1,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+					count: None,
+				},
+			],
+		})
+	}
+}
@@ -0,0 +1,44 @@
+use std::str::FromStr;
+
+use crate::render::{TextureBackend, TextureFilter};
+
+/// Client options that are picked at start-up
+#[derive(Debug, Clone, Default)]
+pub struct ClientSettings {
+	/// How block textures are loaded, from the `CUBEGAME_TEXTURE_BACKEND` environment variable
+	/// ("atlas" or "array")
+	pub texture_backend: TextureBackend,
+	/// How block textures are filtered, from the `CUBEGAME_TEXTURE_FILTER` environment variable
+	/// ("nearest", "linear" or "anisotropic")
+	pub texture_filter: TextureFilter,
+}
+impl ClientSettings {
+	/// Reads settings from environment variables, using defaults for any that are missing or
+	/// invalid
+	pub fn from_env() -> ClientSettings {
+		let defaults = ClientSettings::default();
+		ClientSettings {
+			texture_backend: read_env("CUBEGAME_TEXTURE_BACKEND", defaults.texture_backend),
+			texture_filter: read_env("CUBEGAME_TEXTURE_FILTER", defaults.texture_filter),
+		}
+	}
+}
+
+/// Parses an environment variable, falling back to a default value
+fn read_env<T: FromStr + std::fmt::Debug>(name: &str, default: T) -> T {
+	let Ok(value) = std::env::var(name) else {
+		return default;
+	};
+	match value.parse() {
+		Ok(value) => value,
+		Err(_) => {
+			log::warn!(
+				"Invalid value \"{}\" for {}, using {:?}",
+				value,
+				name,
+				default
+			);
+			default
+		}
+	}
+}
@@ -0,0 +1,181 @@
+//! Memory use and access time of `ChunkData`, against a chunk stored the way it was before it was
+//! split into sections (one palette for the whole chunk and a 2 byte index for every block), and
+//! against every block stored inline with no palette at all
+//!
+//! Memory use is printed before the benchmarks run
+
+use std::collections::HashMap;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use cubegame_lib::{
+	worldgen, BlockData, ChunkData, ChunkPos, LocalBlockPos, WorldGenesisData, BLOCKS_PER_CHUNK,
+};
+
+/// A chunk with a single palette and an index for every block
+struct FlatChunk {
+	palette: Vec<BlockData>,
+	palette_indices: HashMap<BlockData, u16>,
+	blocks: Box<[u16; BLOCKS_PER_CHUNK]>,
+}
+impl FlatChunk {
+	fn from_chunk(chunk: &ChunkData) -> FlatChunk {
+		let mut flat = FlatChunk {
+			palette: Vec::new(),
+			palette_indices: HashMap::new(),
+			blocks: vec![0; BLOCKS_PER_CHUNK]
+				.into_boxed_slice()
+				.try_into()
+				.unwrap(),
+		};
+		for (pos, block) in chunk.iter() {
+			flat.set(pos, block);
+		}
+		flat
+	}
+
+	fn get(&self, pos: LocalBlockPos) -> BlockData {
+		self.palette[self.blocks[pos.to_index()] as usize]
+	}
+
+	fn set(&mut self, pos: LocalBlockPos, block: BlockData) {
+		let index = *self.palette_indices.entry(block).or_insert_with(|| {
+			self.palette.push(block);
+			(self.palette.len() - 1) as u16
+		});
+		self.blocks[pos.to_index()] = index;
+	}
+
+	fn memory_usage(&self) -> usize {
+		size_of::<FlatChunk>()
+			+ self.palette.capacity() * size_of::<BlockData>()
+			+ self.palette_indices.capacity() * (size_of::<BlockData>() + size_of::<u16>())
+			+ size_of::<[u16; BLOCKS_PER_CHUNK]>()
+	}
+}
+
+/// A chunk with every block stored as is
+struct InlineChunk {
+	blocks: Box<[BlockData; BLOCKS_PER_CHUNK]>,
+}
+impl InlineChunk {
+	fn from_chunk(chunk: &ChunkData) -> InlineChunk {
+		let mut inline = InlineChunk {
+			blocks: vec![BlockData::default(); BLOCKS_PER_CHUNK]
+				.into_boxed_slice()
+				.try_into()
+				.unwrap(),
+		};
+		for (pos, block) in chunk.iter() {
+			inline.set(pos, block);
+		}
+		inline
+	}
+
+	fn get(&self, pos: LocalBlockPos) -> BlockData {
+		self.blocks[pos.to_index()]
+	}
+
+	fn set(&mut self, pos: LocalBlockPos, block: BlockData) {
+		self.blocks[pos.to_index()] = block;
+	}
+
+	fn memory_usage(&self) -> usize {
+		size_of::<InlineChunk>() + size_of::<[BlockData; BLOCKS_PER_CHUNK]>()
+	}
+}
+
+fn chunk_data(c: &mut Criterion) {
+	let genesis = WorldGenesisData { seed: 1234 };
+	let chunk = worldgen::generate_chunk(&genesis, ChunkPos { x: 3, z: -2 });
+	let flat = FlatChunk::from_chunk(&chunk);
+	let inline = InlineChunk::from_chunk(&chunk);
+	let empty = ChunkData::new(chunk.pos);
+	println!(
+		"memory use of a generated chunk: {} bytes sectioned, {} bytes flat, {} bytes inline",
+		chunk.memory_usage(),
+		flat.memory_usage(),
+		inline.memory_usage()
+	);
+	println!(
+		"memory use of an empty chunk: {} bytes sectioned, {} bytes flat, {} bytes inline",
+		empty.memory_usage(),
+		FlatChunk::from_chunk(&empty).memory_usage(),
+		InlineChunk::from_chunk(&empty).memory_usage()
+	);
+
+	// reading every block in the same order as meshing and lighting do
+	let positions: Vec<_> = (0..BLOCKS_PER_CHUNK)
+		.map(LocalBlockPos::from_index)
+		.collect();
+	let mut group = c.benchmark_group("read every block");
+	group.bench_function("sectioned", |b| {
+		b.iter(|| {
+			for pos in positions.iter() {
+				black_box(chunk.get(*pos));
+			}
+		})
+	});
+	group.bench_function("flat", |b| {
+		b.iter(|| {
+			for pos in positions.iter() {
+				black_box(flat.get(*pos));
+			}
+		})
+	});
+	group.bench_function("inline", |b| {
+		b.iter(|| {
+			for pos in positions.iter() {
+				black_box(inline.get(*pos));
+			}
+		})
+	});
+	group.finish();
+
+	// digging out a layer of the ground
+	let stone = chunk.get(LocalBlockPos::new(0u8, 0, 0));
+	let layer: Vec<_> = (0..16u8)
+		.flat_map(|x| (0..16u8).map(move |z| LocalBlockPos::new(x, 5, z)))
+		.collect();
+	let mut group = c.benchmark_group("set a layer");
+	group.bench_function("sectioned", |b| {
+		let mut chunk = chunk.clone();
+		b.iter(|| {
+			for pos in layer.iter() {
+				chunk.set(*pos, BlockData::default());
+			}
+			for pos in layer.iter() {
+				chunk.set(*pos, stone);
+			}
+		})
+	});
+	group.bench_function("flat", |b| {
+		let mut flat = FlatChunk::from_chunk(&chunk);
+		b.iter(|| {
+			for pos in layer.iter() {
+				flat.set(*pos, BlockData::default());
+			}
+			for pos in layer.iter() {
+				flat.set(*pos, stone);
+			}
+		})
+	});
+	group.bench_function("inline", |b| {
+		let mut inline = InlineChunk::from_chunk(&chunk);
+		b.iter(|| {
+			for pos in layer.iter() {
+				inline.set(*pos, BlockData::default());
+			}
+			for pos in layer.iter() {
+				inline.set(*pos, stone);
+			}
+		})
+	});
+	group.finish();
+
+	c.bench_function("generate chunk", |b| {
+		b.iter(|| worldgen::generate_chunk(&genesis, black_box(chunk.pos)))
+	});
+}
+
+criterion_group!(benches, chunk_data);
+criterion_main!(benches);
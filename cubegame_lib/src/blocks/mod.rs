@@ -1,77 +1,210 @@
-use super::BlockTypeId;
-use lazy_static::lazy_static;
+mod registry;
 
-#[derive(Debug, Copy, Clone)]
+use super::{BlockState, BlockTypeId, Direction};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+pub use registry::{BlockRegistry, RegistryError};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BlockType {
 	pub id: BlockTypeId,
-	pub name: &'static str,
+	pub name: String,
 	pub texture_layout: BlockTextureLayout,
+	/// Light level given off by the block, 0 if it doesn't glow
+	#[serde(default)]
+	pub light_emission: u8,
+	/// How much can be seen through the block
+	#[serde(default)]
+	pub opacity: BlockOpacity,
+	/// Properties that make up the block's state, like which way it faces
+	#[serde(default)]
+	pub properties: Vec<BlockProperty>,
+	/// Textures used instead of `texture_layout` in some states, the first one that matches is used
+	#[serde(default)]
+	pub texture_variants: Vec<TextureVariant>,
+	/// Property (with values "x", "y" and "z") giving the axis that the top and bottom of the block
+	/// face along, like logs. Without it the top always faces up
+	#[serde(default)]
+	pub axis_property: Option<String>,
 }
 impl BlockType {
 	pub fn is_air(&self) -> bool {
 		self.id == AIR_BLOCK_ID
 	}
 
+	/// Whether light passes through the block
+	pub fn is_transparent(&self) -> bool {
+		self.opacity != BlockOpacity::Opaque
+	}
+
+	/// Whether the face of this block next to `neighbor` is hidden by it
+	pub fn face_hidden_by(&self, neighbor: &BlockType) -> bool {
+		match neighbor.opacity {
+			BlockOpacity::Opaque => true,
+			// the insides of a translucent material aren't seen, like a wall of glass
+			BlockOpacity::Translucent => neighbor.id == self.id,
+			// gaps in cutout blocks show the faces behind them, even of the same block
+			BlockOpacity::Cutout | BlockOpacity::Invisible => false,
+		}
+	}
+
+	/// Looks up a block type in the installed registry, unknown ids get the null block
 	pub fn from_id(id: BlockTypeId) -> &'static BlockType {
-		for t in BLOCK_TYPES.iter() {
-			if t.id == id {
-				return t;
+		registry().get(id)
+	}
+
+	/// Number of different states the block can be in
+	///
+	/// Registries reject block types with more states than fit in a `BlockState`, so this only
+	/// overflows for block types that were never registered
+	pub fn state_count(&self) -> usize {
+		self.properties.iter().map(|p| p.values.len()).product()
+	}
+
+	/// Value of a property in a state, `None` if the block doesn't have that property
+	///
+	/// States are packed by giving each property a digit, with as many values as the property has,
+	/// the first property being the lowest digit
+	pub fn property_value(&self, state: BlockState, property: &str) -> Option<&str> {
+		let mut state = state as usize;
+		for p in self.properties.iter() {
+			let value = state % p.values.len();
+			if p.name == property {
+				return Some(&p.values[value]);
+			}
+			state /= p.values.len();
+		}
+		None
+	}
+
+	/// State with a property changed to a different value, `None` if the block doesn't have that
+	/// property or value
+	pub fn with_property(
+		&self,
+		state: BlockState,
+		property: &str,
+		value: &str,
+	) -> Option<BlockState> {
+		let mut stride = 1;
+		for p in self.properties.iter() {
+			let len = p.values.len();
+			if p.name == property {
+				let new = p.values.iter().position(|v| v == value)?;
+				let old = (state as usize / stride) % len;
+				return Some((state as usize + (new * stride) - (old * stride)) as BlockState);
+			}
+			stride *= len;
+		}
+		None
+	}
+
+	/// Texture layout used in a state, with its variant (0 for `texture_layout`, otherwise the index
+	/// in `texture_variants` plus 1)
+	pub fn texture_layout_in(&self, state: BlockState) -> (u8, &BlockTextureLayout) {
+		for (i, variant) in self.texture_variants.iter().enumerate() {
+			let matches = variant
+				.when
+				.iter()
+				.all(|(property, value)| self.property_value(state, property) == Some(value));
+			if matches {
+				return (i as u8 + 1, &variant.texture_layout);
 			}
 		}
-		return &BLOCK_TYPES[NULL_BLOCK_ID as usize];
+		(0, &self.texture_layout)
 	}
+
+	/// Direction that the top of the block faces in a state, see `axis_property`
+	pub fn up_direction(&self, state: BlockState) -> Direction {
+		let axis = self
+			.axis_property
+			.as_ref()
+			.and_then(|property| self.property_value(state, property));
+		match axis {
+			Some("x") => Direction::PosX,
+			Some("z") => Direction::PosZ,
+			_ => Direction::PosY,
+		}
+	}
+}
+
+/// A named part of a block's state that can have a few different values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockProperty {
+	pub name: String,
+	/// Every value the property can have, the first one is the default
+	pub values: Vec<String>,
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Textures for a block in the states where its properties have certain values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextureVariant {
+	/// Value that each of these properties needs to have
+	pub when: BTreeMap<String, String>,
+	pub texture_layout: BlockTextureLayout,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BlockTextureLayout {
 	/// All faces have the same texture
-	Uniform(&'static str),
+	Uniform(String),
 	/// Different textures for the top, the sides, and the bottom
 	TopSideBottom {
-		top: &'static str,
-		sides: &'static str,
-		bottom: &'static str,
+		top: String,
+		sides: String,
+		bottom: String,
 	},
 	/// Has no textured faces (air)
+	#[serde(rename = "NoTexture")]
 	None,
 }
+impl BlockTextureLayout {
+	/// Names of every texture file used
+	pub fn filenames(&self) -> Vec<&str> {
+		match self {
+			BlockTextureLayout::Uniform(filename) => vec![filename],
+			BlockTextureLayout::TopSideBottom { top, sides, bottom } => vec![top, sides, bottom],
+			BlockTextureLayout::None => Vec::new(),
+		}
+	}
+}
+
+/// How much can be seen through a block, which decides how its faces are culled and drawn
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockOpacity {
+	/// Nothing behind the block can be seen
+	#[default]
+	Opaque,
+	/// Parts of the texture are fully see through, the rest is opaque (leaves)
+	Cutout,
+	/// The whole block is partly see through and is blended with what's behind it (glass)
+	Translucent,
+	/// The block isn't drawn at all (air)
+	Invisible,
+}
 
 pub static AIR_BLOCK_ID: BlockTypeId = 1;
 pub static NULL_BLOCK_ID: BlockTypeId = 0;
 
-// TODO add better registering functionality
-// TODO add randomized textures
-lazy_static! {
-	/// Static list off all block types, needs to be initialized
-	pub static ref BLOCK_TYPES: Vec<BlockType> = vec![
-		BlockType {
-			id: 0,
-			name: "null_block",
-			texture_layout: BlockTextureLayout::Uniform("null_block.png"),
-		},
-		BlockType {
-			id: 1,
-			name: "air",
-			texture_layout: BlockTextureLayout::None,
-		},
-		BlockType {
-			id: 2,
-			name: "stone_block",
-			texture_layout: BlockTextureLayout::Uniform("stone_block.png"),
-		},
-		BlockType {
-			id: 3,
-			name: "dirt_block",
-			texture_layout: BlockTextureLayout::Uniform("dirt_block.png"),
-		},
-		BlockType {
-			id: 4,
-			name: "grass_block",
-			texture_layout: BlockTextureLayout::TopSideBottom {
-				top: "grass_block_top.png",
-				sides: "grass_block_side.png",
-				bottom: "dirt_block.png",
-			},
-		},
-	];
-}
\ No newline at end of file
+/// Registry that block types are looked up in, set once for the whole run
+static REGISTRY: OnceLock<BlockRegistry> = OnceLock::new();
+
+/// Registry of every block type, as installed by `install_registry`
+///
+/// If none was installed before the first lookup, the built in one gets installed
+pub fn registry() -> &'static BlockRegistry {
+	REGISTRY.get_or_init(BlockRegistry::builtin)
+}
+
+/// Makes `registry` the one block types are looked up in, e.g. the one received from a server
+///
+/// This can only happen once, before any block types are looked up. Installing an identical
+/// registry again is fine, so an integrated server and its client can both install the same one
+pub fn install_registry(registry: BlockRegistry) -> Result<(), ()> {
+	match REGISTRY.set(registry) {
+		Ok(()) => Ok(()),
+		Err(registry) if REGISTRY.get() == Some(&registry) => Ok(()),
+		Err(_) => Err(()),
+	}
+}
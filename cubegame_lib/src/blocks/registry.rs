@@ -0,0 +1,511 @@
+use super::{BlockType, AIR_BLOCK_ID, NULL_BLOCK_ID};
+use crate::{BlockData, BlockState, BlockTypeId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+/// Block types that come with the game, see `assets/blocks.ron`
+const BUILTIN_BLOCKS: &str = include_str!("../../../assets/blocks.ron");
+
+/// Every block type that exists, looked up by id or by name
+///
+/// Sent from the server to clients as a list of block types, so they agree on what each id means
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<BlockType>", into = "Vec<BlockType>")]
+pub struct BlockRegistry {
+	/// Block types indexed by id, `None` for unused ids
+	types: Vec<Option<BlockType>>,
+	ids_by_name: HashMap<String, BlockTypeId>,
+}
+impl BlockRegistry {
+	/// Checks a list of block types and puts them in a registry
+	///
+	/// Ids and names need to be unique, the null block and air need to exist, each block type's
+	/// properties need to make sense, and texture names can't point outside the texture directory
+	pub fn new(block_types: Vec<BlockType>) -> Result<BlockRegistry, RegistryError> {
+		let mut types: Vec<Option<BlockType>> = Vec::new();
+		let mut ids_by_name = HashMap::new();
+		for block_type in block_types {
+			check_properties(&block_type).map_err(|reason| {
+				RegistryError::InvalidProperties(block_type.name.clone(), reason)
+			})?;
+			let layouts = std::iter::once(&block_type.texture_layout).chain(
+				block_type
+					.texture_variants
+					.iter()
+					.map(|v| &v.texture_layout),
+			);
+			if let Some(filename) = layouts
+				.flat_map(|layout| layout.filenames())
+				.find(|filename| !is_valid_texture_filename(filename))
+			{
+				return Err(RegistryError::InvalidTexture(
+					block_type.name.clone(),
+					filename.to_string(),
+				));
+			}
+			let index = block_type.id as usize;
+			if types.len() <= index {
+				types.resize(index + 1, None);
+			}
+			if types[index].is_some() {
+				return Err(RegistryError::DuplicateId(block_type.id));
+			}
+			if ids_by_name
+				.insert(block_type.name.clone(), block_type.id)
+				.is_some()
+			{
+				return Err(RegistryError::DuplicateName(block_type.name));
+			}
+			types[index] = Some(block_type);
+		}
+		for reserved in [NULL_BLOCK_ID, AIR_BLOCK_ID] {
+			if types.get(reserved as usize).is_none_or(Option::is_none) {
+				return Err(RegistryError::MissingReservedId(reserved));
+			}
+		}
+		Ok(BlockRegistry { types, ids_by_name })
+	}
+
+	/// Parses a registry from RON, in the format of `assets/blocks.ron`
+	pub fn parse(text: &str) -> Result<BlockRegistry, RegistryError> {
+		let block_types: Vec<BlockType> =
+			ron::from_str(text).map_err(|e| RegistryError::Parse(e.to_string()))?;
+		BlockRegistry::new(block_types)
+	}
+
+	/// Reads a registry from a RON file, in the format of `assets/blocks.ron`
+	pub fn load(path: &Path) -> Result<BlockRegistry, RegistryError> {
+		let text = std::fs::read_to_string(path)
+			.map_err(|e| RegistryError::Read(path.to_path_buf(), e.to_string()))?;
+		BlockRegistry::parse(&text)
+	}
+
+	/// Registry of the block types that come with the game
+	pub fn builtin() -> BlockRegistry {
+		BlockRegistry::parse(BUILTIN_BLOCKS).expect("Built in block registry is invalid")
+	}
+
+	/// Gets a block type, unknown ids get the null block
+	pub fn get(&self, id: BlockTypeId) -> &BlockType {
+		match self.types.get(id as usize) {
+			Some(Some(block_type)) => block_type,
+			_ => self.types[NULL_BLOCK_ID as usize].as_ref().unwrap(),
+		}
+	}
+
+	/// Gets the id of the block type with this name
+	pub fn id_of(&self, name: &str) -> Option<BlockTypeId> {
+		self.ids_by_name.get(name).copied()
+	}
+
+	/// Whether a block is of a registered type, in one of that type's states
+	pub fn is_valid(&self, block: BlockData) -> bool {
+		match self.types.get(block.type_id as usize) {
+			Some(Some(block_type)) => (block.state as usize) < block_type.state_count(),
+			_ => false,
+		}
+	}
+
+	/// Iterates over every block type, in order of id
+	pub fn iter(&self) -> impl Iterator<Item = &BlockType> {
+		self.types.iter().flatten()
+	}
+
+	/// Checks that every texture the block types use is in `dir`
+	pub fn check_textures(&self, dir: &Path) -> Result<(), RegistryError> {
+		for block_type in self.iter() {
+			let layouts = std::iter::once(&block_type.texture_layout).chain(
+				block_type
+					.texture_variants
+					.iter()
+					.map(|v| &v.texture_layout),
+			);
+			for filename in layouts.flat_map(|layout| layout.filenames()) {
+				let path = dir.join(filename);
+				if !path.is_file() {
+					return Err(RegistryError::MissingTexture(block_type.name.clone(), path));
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Checks that a texture name is a plain file name, which can't reach outside the texture directory
+fn is_valid_texture_filename(filename: &str) -> bool {
+	// colons too, for windows drive prefixes
+	!filename.is_empty() && !filename.contains(['/', '\\', ':']) && !filename.contains("..")
+}
+
+/// Checks that a block type's properties, and everything referring to them, are valid
+fn check_properties(block_type: &BlockType) -> Result<(), String> {
+	for (i, property) in block_type.properties.iter().enumerate() {
+		if property.values.is_empty() {
+			return Err(format!("property \"{}\" has no values", property.name));
+		}
+		if block_type.properties[..i]
+			.iter()
+			.any(|p| p.name == property.name)
+		{
+			return Err(format!("property \"{}\" is declared twice", property.name));
+		}
+		for (j, value) in property.values.iter().enumerate() {
+			if property.values[..j].contains(value) {
+				return Err(format!(
+					"property \"{}\" has the value \"{}\" twice",
+					property.name, value
+				));
+			}
+		}
+	}
+	// checked, since enough properties would overflow `state_count`
+	let state_count = block_type
+		.properties
+		.iter()
+		.try_fold(1usize, |count, p| count.checked_mul(p.values.len()));
+	match state_count {
+		Some(count) if count <= BlockState::MAX as usize + 1 => {}
+		Some(count) => return Err(format!("too many states ({})", count)),
+		None => return Err("too many states".to_string()),
+	}
+
+	let values_of = |name: &str| {
+		block_type
+			.properties
+			.iter()
+			.find(|p| p.name == name)
+			.map(|p| &p.values)
+			.ok_or(format!("unknown property \"{}\"", name))
+	};
+	if let Some(axis) = &block_type.axis_property {
+		let values = values_of(axis)?;
+		if let Some(value) = values
+			.iter()
+			.find(|v| !["x", "y", "z"].contains(&v.as_str()))
+		{
+			return Err(format!("\"{}\" is not an axis", value));
+		}
+	}
+	if block_type.texture_variants.len() >= u8::MAX as usize {
+		return Err("too many texture variants".to_string());
+	}
+	for variant in block_type.texture_variants.iter() {
+		for (property, value) in variant.when.iter() {
+			if !values_of(property)?.contains(value) {
+				return Err(format!(
+					"property \"{}\" has no value \"{}\"",
+					property, value
+				));
+			}
+		}
+	}
+	Ok(())
+}
+
+impl TryFrom<Vec<BlockType>> for BlockRegistry {
+	type Error = RegistryError;
+
+	fn try_from(block_types: Vec<BlockType>) -> Result<Self, Self::Error> {
+		BlockRegistry::new(block_types)
+	}
+}
+impl From<BlockRegistry> for Vec<BlockType> {
+	fn from(registry: BlockRegistry) -> Self {
+		registry.types.into_iter().flatten().collect()
+	}
+}
+
+/// Reasons a block registry can be invalid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+	/// The file couldn't be read
+	Read(PathBuf, String),
+	/// The file isn't a valid list of block types
+	Parse(String),
+	/// More than one block type has this id
+	DuplicateId(BlockTypeId),
+	/// More than one block type has this name
+	DuplicateName(String),
+	/// A block type the game relies on (null block or air) is missing
+	MissingReservedId(BlockTypeId),
+	/// A block type's properties are invalid, or something refers to ones that don't exist
+	InvalidProperties(String, String),
+	/// A block type's texture name isn't a plain file name
+	InvalidTexture(String, String),
+	/// A block type's texture file doesn't exist
+	MissingTexture(String, PathBuf),
+}
+impl Display for RegistryError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			RegistryError::Read(path, e) => {
+				write!(f, "failed to read \"{}\": {}", path.display(), e)
+			}
+			RegistryError::Parse(e) => write!(f, "invalid block types: {}", e),
+			RegistryError::DuplicateId(id) => write!(f, "block type id {} is used twice", id),
+			RegistryError::DuplicateName(name) => {
+				write!(f, "block type name \"{}\" is used twice", name)
+			}
+			RegistryError::MissingReservedId(id) => {
+				write!(f, "reserved block type id {} is missing", id)
+			}
+			RegistryError::InvalidProperties(name, reason) => {
+				write!(
+					f,
+					"block type \"{}\" has invalid properties: {}",
+					name, reason
+				)
+			}
+			RegistryError::InvalidTexture(name, filename) => write!(
+				f,
+				"texture \"{}\" of block type \"{}\" isn't a plain file name",
+				filename, name
+			),
+			RegistryError::MissingTexture(name, path) => write!(
+				f,
+				"texture \"{}\" of block type \"{}\" doesn't exist",
+				path.display(),
+				name
+			),
+		}
+	}
+}
+impl std::error::Error for RegistryError {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::blocks::{
+		install_registry, registry, BlockOpacity, BlockProperty, BlockTextureLayout, TextureVariant,
+	};
+	use crate::Direction;
+
+	fn block_type(id: BlockTypeId, name: &str) -> BlockType {
+		BlockType {
+			id,
+			name: name.to_string(),
+			texture_layout: BlockTextureLayout::None,
+			light_emission: 0,
+			opacity: BlockOpacity::Opaque,
+			properties: Vec::new(),
+			texture_variants: Vec::new(),
+			axis_property: None,
+		}
+	}
+
+	#[test]
+	fn builtin_registry_is_valid() {
+		let registry = BlockRegistry::builtin();
+		let textures = Path::new(env!("CARGO_MANIFEST_DIR")).join("../assets/block_textures");
+		assert_eq!(registry.check_textures(&textures), Ok(()));
+
+		assert_eq!(registry.id_of("air"), Some(AIR_BLOCK_ID));
+		let lamp = registry.get(registry.id_of("lamp_block").unwrap());
+		assert_eq!(lamp.name, "lamp_block");
+		assert_eq!(lamp.light_emission, crate::light::MAX_LIGHT);
+		// unknown ids fall back to the null block
+		assert_eq!(registry.get(200).id, NULL_BLOCK_ID);
+	}
+
+	#[test]
+	fn invalid_registries_are_rejected() {
+		let reserved = || vec![block_type(0, "null_block"), block_type(1, "air")];
+
+		let mut types = reserved();
+		types.push(block_type(1, "stone_block"));
+		assert_eq!(
+			BlockRegistry::new(types),
+			Err(RegistryError::DuplicateId(1))
+		);
+
+		let mut types = reserved();
+		types.push(block_type(2, "air"));
+		assert_eq!(
+			BlockRegistry::new(types),
+			Err(RegistryError::DuplicateName("air".to_string()))
+		);
+
+		assert_eq!(
+			BlockRegistry::new(vec![block_type(0, "null_block")]),
+			Err(RegistryError::MissingReservedId(AIR_BLOCK_ID))
+		);
+
+		let mut types = reserved();
+		types.push(BlockType {
+			texture_layout: BlockTextureLayout::Uniform("missing.png".to_string()),
+			..block_type(5, "stone_block")
+		});
+		let registry = BlockRegistry::new(types).unwrap();
+		assert!(matches!(
+			registry.check_textures(Path::new(env!("CARGO_MANIFEST_DIR"))),
+			Err(RegistryError::MissingTexture(name, _)) if name == "stone_block"
+		));
+
+		for filename in [
+			"../secret.png",
+			"textures/stone.png",
+			"C:stone.png",
+			"..",
+			"",
+		] {
+			let mut types = reserved();
+			types.push(BlockType {
+				texture_layout: BlockTextureLayout::Uniform(filename.to_string()),
+				..block_type(5, "stone_block")
+			});
+			assert_eq!(
+				BlockRegistry::new(types),
+				Err(RegistryError::InvalidTexture(
+					"stone_block".to_string(),
+					filename.to_string()
+				))
+			);
+		}
+
+		assert!(matches!(
+			BlockRegistry::parse("[(id: 0)]"),
+			Err(RegistryError::Parse(_))
+		));
+
+		let invalid_properties = [
+			BlockType {
+				properties: vec![property("open", &[])],
+				..block_type(5, "door_block")
+			},
+			BlockType {
+				properties: vec![property("open", &["false", "false"])],
+				..block_type(5, "door_block")
+			},
+			BlockType {
+				properties: vec![property("axis", &["x", "up"])],
+				axis_property: Some("axis".to_string()),
+				..block_type(5, "door_block")
+			},
+			BlockType {
+				texture_variants: vec![TextureVariant {
+					when: [("open".to_string(), "true".to_string())].into(),
+					texture_layout: BlockTextureLayout::None,
+				}],
+				..block_type(5, "door_block")
+			},
+			// enough states to overflow counting them
+			BlockType {
+				properties: (0..16)
+					.map(|i| {
+						let values: Vec<String> = (0..256).map(|v| v.to_string()).collect();
+						BlockProperty {
+							name: format!("property_{}", i),
+							values,
+						}
+					})
+					.collect(),
+				..block_type(5, "door_block")
+			},
+		];
+		for invalid in invalid_properties {
+			let mut types = reserved();
+			types.push(invalid);
+			assert!(matches!(
+				BlockRegistry::new(types),
+				Err(RegistryError::InvalidProperties(name, _)) if name == "door_block"
+			));
+		}
+	}
+
+	fn property(name: &str, values: &[&str]) -> BlockProperty {
+		BlockProperty {
+			name: name.to_string(),
+			values: values.iter().map(|v| v.to_string()).collect(),
+		}
+	}
+
+	#[test]
+	fn block_states_hold_every_property() {
+		let door = BlockType {
+			properties: vec![
+				property("open", &["false", "true"]),
+				property("axis", &["y", "x", "z"]),
+			],
+			texture_variants: vec![TextureVariant {
+				when: [("open".to_string(), "true".to_string())].into(),
+				texture_layout: BlockTextureLayout::Uniform("open.png".to_string()),
+			}],
+			axis_property: Some("axis".to_string()),
+			..block_type(5, "door_block")
+		};
+		assert_eq!(door.state_count(), 6);
+
+		// the default state has the first value of every property
+		assert_eq!(door.property_value(0, "open"), Some("false"));
+		assert_eq!(door.property_value(0, "axis"), Some("y"));
+		assert_eq!(door.property_value(0, "colour"), None);
+		assert_eq!(door.texture_layout_in(0), (0, &BlockTextureLayout::None));
+		assert_eq!(door.up_direction(0), Direction::PosY);
+
+		let state = door.with_property(0, "axis", "z").unwrap();
+		let state = door.with_property(state, "open", "true").unwrap();
+		assert_eq!(door.property_value(state, "open"), Some("true"));
+		assert_eq!(door.property_value(state, "axis"), Some("z"));
+		assert_eq!(door.texture_layout_in(state).0, 1);
+		assert_eq!(door.up_direction(state), Direction::PosZ);
+
+		// changing one property leaves the others alone
+		let state = door.with_property(state, "open", "false").unwrap();
+		assert_eq!(door.property_value(state, "axis"), Some("z"));
+		assert_eq!(door.with_property(state, "open", "ajar"), None);
+		assert_eq!(door.with_property(state, "colour", "red"), None);
+
+		// every state is different
+		let mut states: Vec<_> = (0..door.state_count() as BlockState)
+			.map(|s| {
+				(
+					door.property_value(s, "open"),
+					door.property_value(s, "axis"),
+				)
+			})
+			.collect();
+		states.sort();
+		states.dedup();
+		assert_eq!(states.len(), door.state_count());
+	}
+
+	#[test]
+	fn only_one_registry_can_be_installed() {
+		// the built in registry is the one every other test looks block types up in
+		assert_eq!(install_registry(BlockRegistry::builtin()), Ok(()));
+		assert_eq!(registry(), &BlockRegistry::builtin());
+
+		let mut types: Vec<BlockType> = BlockRegistry::builtin().iter().cloned().collect();
+		types.push(block_type(500, "custom_block"));
+		assert_eq!(
+			install_registry(BlockRegistry::new(types).unwrap()),
+			Err(())
+		);
+		assert_eq!(registry().id_of("custom_block"), None);
+	}
+
+	#[test]
+	fn registry_survives_being_sent() {
+		let mut types = vec![block_type(0, "null_block"), block_type(1, "air")];
+		// gaps between ids are kept
+		types.push(block_type(9, "stone_block"));
+		let registry = BlockRegistry::new(types).unwrap();
+
+		let bytes = rmp_serde::to_vec(&registry).unwrap();
+		let received: BlockRegistry = rmp_serde::from_slice(&bytes).unwrap();
+		assert_eq!(received, registry);
+		assert_eq!(received.get(9).name, "stone_block");
+		assert_eq!(received.get(5).id, NULL_BLOCK_ID);
+
+		// duplicates are caught when receiving too
+		let duplicated = vec![
+			block_type(0, "null_block"),
+			block_type(1, "air"),
+			block_type(1, "a"),
+		];
+		let bytes = rmp_serde::to_vec(&duplicated).unwrap();
+		assert!(rmp_serde::from_slice::<BlockRegistry>(&bytes).is_err());
+	}
+}
@@ -1,23 +1,96 @@
+use crate::blocks::BlockRegistry;
+use crate::time::WorldTime;
 use crate::*;
 use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// Version of the client/server protocol, needs to be bumped whenever the message formats change
+///
+/// The formats of `ServerMessage::Hello`, `ServerResponse::Welcome`, `ServerResponse::Err` and
+/// `ErrorMessage::IncompatibleProtocolVersion` must never change, so that mismatched clients and
+/// servers can always tell each other apart
+pub const PROTOCOL_VERSION: u32 = 8;
+
+/// Optional features that a client or server can advertise during the handshake
+pub mod capabilities {
+	/// Creating, listing, loading and unloading worlds
+	pub const WORLD_MANAGEMENT: &str = "world_management";
+	/// Receiving `ServerEvent`s about changes to loaded chunks
+	pub const BLOCK_EVENTS: &str = "block_events";
+	/// Receiving `ServerEvent::TimeSync`s to keep the world time in step with the server
+	pub const WORLD_TIME: &str = "world_time";
+	/// Requesting the server's block types with `ServerMessage::GetBlockRegistry`
+	pub const BLOCK_REGISTRY: &str = "block_registry";
+}
+
+/// Chosen by the client to match responses to its requests
+pub type RequestId = u32;
 
 /// Common trait for all cubegame communications, provides uniform methods for encoding and decoding messages into bytes
 pub trait Communication<'de>: Serialize + Deserialize<'de> {
-	fn encode(&self) -> Vec<u8> {
-		rmp_serde::encode::to_vec(&self).unwrap()
+	fn encode(&self) -> Result<Vec<u8>, CommunicationError> {
+		rmp_serde::encode::to_vec(&self).map_err(CommunicationError::Encode)
 	}
-	fn decode(data: &'de [u8]) -> Self {
-		rmp_serde::decode::from_slice(&data).unwrap()
+	fn decode(data: &'de [u8]) -> Result<Self, CommunicationError> {
+		rmp_serde::decode::from_slice(data).map_err(CommunicationError::Decode)
 	}
 }
 
+/// Anything that can go wrong while exchanging communications
+#[derive(Debug)]
+pub enum CommunicationError {
+	/// A message could not be encoded
+	Encode(rmp_serde::encode::Error),
+	/// Received bytes are not a valid message
+	Decode(rmp_serde::decode::Error),
+	/// Received something valid, but not what was expected at this point
+	UnexpectedMessage(String),
+	/// The underlying connection failed or was closed
+	Transport(String),
+}
+impl Display for CommunicationError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			CommunicationError::Encode(e) => write!(f, "failed to encode message: {}", e),
+			CommunicationError::Decode(e) => write!(f, "failed to decode message: {}", e),
+			CommunicationError::UnexpectedMessage(msg) => write!(f, "unexpected message: {}", msg),
+			CommunicationError::Transport(e) => write!(f, "connection error: {}", e),
+		}
+	}
+}
+impl std::error::Error for CommunicationError {}
+
 // message content formats
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ServerMessage {
-	/// Request chunk data at this position
+	/// First message of every connection
+	Hello {
+		protocol_version: u32,
+		player_name: String,
+		capabilities: Vec<String>,
+	},
+	/// Create a new world with this name
+	CreateWorld(String, WorldGenesisData),
+	/// Request the names of every world on the server
+	ListWorlds,
+	/// Start playing in the world with this name
+	LoadWorld(String),
+	/// Stop playing in the current world
+	UnloadWorld,
+	/// Request chunk data at this position, and start receiving events about changes to it
 	LoadChunk(ChunkPos),
+	/// Stop receiving events about changes to this chunk
+	UnloadChunk(ChunkPos),
 	/// Change this block at this position in the loaded world
 	BlockUpdate(ChunkPos, LocalBlockPos, BlockData),
+	/// Request every block type the server knows about, so both sides agree on block ids
+	GetBlockRegistry,
+	/// Wraps another message, its response is wrapped in `ServerResponse::Response` with the same
+	/// id, so that clients can have many requests in flight at once
+	Request {
+		id: RequestId,
+		message: Box<ServerMessage>,
+	},
 }
 impl Communication<'_> for ServerMessage {}
 
@@ -27,13 +100,157 @@ pub enum ServerResponse {
 	Ack,
 	/// Error
 	Err(ErrorMessage),
+	/// Response to Hello, if the protocol versions match
+	Welcome {
+		protocol_version: u32,
+		capabilities: Vec<String>,
+	},
+	/// Response to ListWorlds request
+	WorldList(Vec<String>),
+	/// Response to LoadWorld request, with the data needed to generate the world's terrain and the
+	/// world's current time
+	WorldLoaded(WorldGenesisData, WorldTime),
 	/// Response to LoadChunk request
 	LoadChunkOK(ChunkDeltaData),
+	/// Response to GetBlockRegistry request
+	BlockRegistry(BlockRegistry),
+	/// Not a response, pushed by the server whenever something happens that the client should know about
+	Event(ServerEvent),
+	/// Response to a `ServerMessage::Request` with this id
+	Response {
+		id: RequestId,
+		response: Box<ServerResponse>,
+	},
 }
 impl Communication<'_> for ServerResponse {}
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Things that happen on the server that clients get notified about
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServerEvent {
+	/// Another client changed a block in a loaded chunk
+	BlockChanged(ChunkPos, LocalBlockPos, BlockData),
+	/// The world's current time, sent every so often so clients don't drift away from it
+	TimeSync(WorldTime),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ErrorMessage {
+	/// Response to Hello, if the client and server versions are not compatible (the connection is
+	/// closed after this)
+	IncompatibleProtocolVersion {
+		server: u32,
+		client: u32,
+	},
+	/// Messages were sent before the Hello handshake
+	HandshakeRequired,
+	/// Request needs a world, but none has been loaded
 	NoLoadedWorld,
 	WorldDoesNotExist,
+	WorldAlreadyExists,
+	/// World names can only contain letters, numbers, spaces, underscores and dashes
+	InvalidWorldName,
+	/// Block updates need a registered block type, in one of its states
+	InvalidBlock,
+	/// Server failed to handle the request (e.g. couldn't read the world save)
+	Internal,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::Rng;
+
+	fn sample_messages() -> Vec<ServerMessage> {
+		let chunk = ChunkPos { x: -3, z: 12 };
+		vec![
+			ServerMessage::Hello {
+				protocol_version: PROTOCOL_VERSION,
+				player_name: "player".to_string(),
+				capabilities: vec![capabilities::WORLD_MANAGEMENT.to_string()],
+			},
+			ServerMessage::CreateWorld("world".to_string(), WorldGenesisData { seed: 42 }),
+			ServerMessage::ListWorlds,
+			ServerMessage::LoadWorld("world".to_string()),
+			ServerMessage::UnloadWorld,
+			ServerMessage::LoadChunk(chunk),
+			ServerMessage::UnloadChunk(chunk),
+			ServerMessage::BlockUpdate(chunk, LocalBlockPos::new(1u8, 2, 3), BlockData::default()),
+			ServerMessage::BlockUpdate(
+				chunk,
+				LocalBlockPos::new(4u8, 5, 6),
+				BlockData {
+					type_id: 8,
+					state: 2,
+				},
+			),
+			ServerMessage::GetBlockRegistry,
+			ServerMessage::Request {
+				id: 7,
+				message: Box::new(ServerMessage::LoadChunk(chunk)),
+			},
+		]
+	}
+
+	#[test]
+	fn messages_round_trip() {
+		for msg in sample_messages() {
+			let bytes = msg.encode().unwrap();
+			let decoded = ServerMessage::decode(&bytes).unwrap();
+			assert_eq!(format!("{:?}", msg), format!("{:?}", decoded));
+		}
+	}
+
+	#[test]
+	fn decoding_random_bytes_does_not_panic() {
+		let mut rng = Rng(0x2545F4914F6CDD1D);
+		for _ in 0..20_000 {
+			let len = (rng.next() % 64) as usize;
+			let bytes = rng.bytes(len);
+			let _ = ServerMessage::decode(&bytes);
+			let _ = ServerResponse::decode(&bytes);
+		}
+	}
+
+	#[test]
+	fn decoding_corrupted_messages_does_not_panic() {
+		let mut rng = Rng(0x9E3779B97F4A7C15);
+		for msg in sample_messages() {
+			let valid = msg.encode().unwrap();
+			for _ in 0..2_000 {
+				let mut bytes = valid.clone();
+				// flipping some bytes
+				for _ in 0..(1 + rng.next() % 3) {
+					let i = (rng.next() as usize) % bytes.len();
+					bytes[i] = rng.next() as u8;
+				}
+				// and sometimes cutting it short
+				if rng.next().is_multiple_of(4) {
+					bytes.truncate((rng.next() as usize) % bytes.len());
+				}
+				let _ = ServerMessage::decode(&bytes);
+			}
+		}
+	}
+
+	#[test]
+	fn blocks_without_state_decode_with_default_state() {
+		// blocks used to be encoded as just their type id
+		let bytes = rmp_serde::to_vec(&[5u8]).unwrap();
+		let block: BlockData = rmp_serde::from_slice(&bytes).unwrap();
+		assert_eq!(block, BlockData::new(5));
+	}
+
+	#[test]
+	fn decoding_garbage_is_an_error() {
+		assert!(matches!(
+			ServerMessage::decode(&[]),
+			Err(CommunicationError::Decode(_))
+		));
+		assert!(matches!(
+			ServerMessage::decode(&[0xc1]),
+			Err(CommunicationError::Decode(_))
+		));
+		let response = ServerResponse::Ack.encode().unwrap();
+		assert!(ServerMessage::decode(&response).is_err());
+	}
 }
@@ -1,19 +1,32 @@
 pub mod blocks;
 pub mod communication;
+pub mod light;
+pub mod raycast;
+mod section;
+#[cfg(test)]
+mod test_util;
+pub mod time;
 pub mod worldgen;
 
 use crate::blocks::AIR_BLOCK_ID;
+use crate::section::ChunkSection;
 use bitmask_enum::bitmask;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt::Formatter;
 
+pub use section::{BLOCKS_PER_SECTION, SECTION_HEIGHT};
+
 // constants
 pub const WORLD_HEIGHT: usize = 256;
 pub const CHUNK_WIDTH: usize = 16;
 pub const BLOCKS_PER_CHUNK: usize = CHUNK_WIDTH * CHUNK_WIDTH * WORLD_HEIGHT;
+pub const SECTIONS_PER_CHUNK: usize = WORLD_HEIGHT / SECTION_HEIGHT;
 
 // types
-pub type BlockTypeId = u8;
+pub type BlockTypeId = u16;
+/// State of a block, its meaning depends on the block type's properties (see `BlockType`)
+pub type BlockState = u16;
 
 /// Represents possibly multiple directions (or none)
 #[bitmask(u8)]
@@ -37,6 +50,28 @@ pub enum Direction {
 	PosZ,
 	NegZ,
 }
+impl Direction {
+	pub const ALL: [Direction; 6] = [
+		Direction::PosX,
+		Direction::NegX,
+		Direction::PosY,
+		Direction::NegY,
+		Direction::PosZ,
+		Direction::NegZ,
+	];
+
+	/// Change in x, y and z from moving one block this way
+	pub fn offset(&self) -> [i32; 3] {
+		match self {
+			Direction::PosX => [1, 0, 0],
+			Direction::NegX => [-1, 0, 0],
+			Direction::PosY => [0, 1, 0],
+			Direction::NegY => [0, -1, 0],
+			Direction::PosZ => [0, 0, 1],
+			Direction::NegZ => [0, 0, -1],
+		}
+	}
+}
 impl From<Directions> for Direction {
 	/// WARNING: assumes there is one and only one direction set
 	fn from(value: Directions) -> Self {
@@ -68,6 +103,88 @@ impl std::fmt::Display for ChunkPos {
 	}
 }
 
+/// Position of a block in the world
+///
+/// Can be above or below the world, where there are no chunks to put blocks in
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub struct BlockPos {
+	pub x: i32,
+	pub y: i32,
+	pub z: i32,
+}
+impl BlockPos {
+	pub fn new(x: i32, y: i32, z: i32) -> BlockPos {
+		BlockPos { x, y, z }
+	}
+
+	/// Gets the block that a point in the world is inside of
+	pub fn from_world_pos(pos: [f32; 3]) -> BlockPos {
+		BlockPos {
+			x: pos[0].floor() as i32,
+			y: pos[1].floor() as i32,
+			z: pos[2].floor() as i32,
+		}
+	}
+
+	pub fn from_local(chunk: ChunkPos, pos: LocalBlockPos) -> BlockPos {
+		BlockPos {
+			x: chunk.x * CHUNK_WIDTH as i32 + pos.x() as i32,
+			y: pos.y() as i32,
+			z: chunk.z * CHUNK_WIDTH as i32 + pos.z() as i32,
+		}
+	}
+
+	/// Gets the chunk this block is in, or would be in if it was in the world
+	pub fn chunk(&self) -> ChunkPos {
+		ChunkPos {
+			x: self.x.div_euclid(CHUNK_WIDTH as i32),
+			z: self.z.div_euclid(CHUNK_WIDTH as i32),
+		}
+	}
+
+	/// Gets the chunk this block is in and where it is in it, `None` above and below the world
+	pub fn to_local(&self) -> Option<(ChunkPos, LocalBlockPos)> {
+		if !(0..WORLD_HEIGHT as i32).contains(&self.y) {
+			return None;
+		}
+		let pos = LocalBlockPos::new(
+			self.x.rem_euclid(CHUNK_WIDTH as i32) as u8,
+			self.y as u8,
+			self.z.rem_euclid(CHUNK_WIDTH as i32) as u8,
+		);
+		Some((self.chunk(), pos))
+	}
+
+	/// Returns the position of the block adjacent in a certain direction, which can be in another
+	/// chunk
+	pub fn neighbor(&self, dir: Direction) -> BlockPos {
+		let [x, y, z] = dir.offset();
+		BlockPos {
+			x: self.x + x,
+			y: self.y + y,
+			z: self.z + z,
+		}
+	}
+
+	/// Iterates over the 6 blocks touching this one, with the direction each is in
+	pub fn neighbors(&self) -> impl Iterator<Item = (Direction, BlockPos)> {
+		let pos = *self;
+		Direction::ALL
+			.into_iter()
+			.map(move |dir| (dir, pos.neighbor(dir)))
+	}
+}
+impl From<(ChunkPos, LocalBlockPos)> for BlockPos {
+	fn from((chunk, pos): (ChunkPos, LocalBlockPos)) -> Self {
+		BlockPos::from_local(chunk, pos)
+	}
+}
+impl std::fmt::Display for BlockPos {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_fmt(format_args!("({}, {}, {})", self.x, self.y, self.z))
+	}
+}
+
 #[derive(PartialEq, Copy, Clone, Debug, Eq, Hash, Serialize, Deserialize)]
 /// Local block position within a chunk
 pub struct LocalBlockPos {
@@ -152,15 +269,82 @@ impl LocalBlockPos {
 
 /// Represents all the blocks in a chunk
 ///
+/// Split into `SECTION_HEIGHT` block high sections, each storing its blocks as small indices into
+/// its own palette, so a block takes little space however wide block type ids and states are, and
+/// sections of only air take almost none
+///
+/// The cost is unpacking the indices: reading every block of a generated chunk takes about 2.5x as
+/// long as from one unpacked 2 byte index per block, and about 3x as long as from an inline array
+/// of blocks (see `benches/chunk_data.rs`). Meshing and lighting read every block, so keep that in
+/// mind when adding more passes over whole chunks
+///
 /// blocks are represented negative to positive,  x, z, y
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct ChunkData {
 	pub pos: ChunkPos,
-	pub blocks: [BlockData; BLOCKS_PER_CHUNK],
+	/// Sections from the bottom of the world up
+	sections: [ChunkSection; SECTIONS_PER_CHUNK],
 }
+impl ChunkData {
+	/// Makes a chunk full of air
+	pub fn new(pos: ChunkPos) -> ChunkData {
+		ChunkData {
+			pos,
+			sections: std::array::from_fn(|_| ChunkSection::filled(BlockData::default())),
+		}
+	}
+
+	pub fn get(&self, pos: LocalBlockPos) -> BlockData {
+		let (section, i) = section_index(pos);
+		self.sections[section].get(i)
+	}
+
+	pub fn set(&mut self, pos: LocalBlockPos, block: BlockData) {
+		let (section, i) = section_index(pos);
+		self.sections[section].set(i, block);
+	}
+
+	/// Iterates over every block with its position, in the order of `LocalBlockPos::to_index`
+	pub fn iter(&self) -> impl Iterator<Item = (LocalBlockPos, BlockData)> + '_ {
+		self.sections.iter().enumerate().flat_map(|(s, section)| {
+			(0..BLOCKS_PER_SECTION).map(move |i| {
+				let pos = LocalBlockPos::from_index(s * BLOCKS_PER_SECTION + i);
+				(pos, section.get(i))
+			})
+		})
+	}
+
+	/// Whether the section at `y` (in blocks) is known to be all air, so it can be skipped
+	pub fn is_air_section(&self, y: usize) -> bool {
+		self.sections[y / SECTION_HEIGHT].is_only(BlockData::default())
+	}
+
+	/// Bytes used by the chunk, including what it points to
+	pub fn memory_usage(&self) -> usize {
+		size_of::<ChunkData>()
+			+ self
+				.sections
+				.iter()
+				.map(|section| section.memory_usage() - size_of::<ChunkSection>())
+				.sum::<usize>()
+	}
+}
+
+/// Section a block is in, and its index in that section
+fn section_index(pos: LocalBlockPos) -> (usize, usize) {
+	let i = pos.to_index();
+	(i / BLOCKS_PER_SECTION, i % BLOCKS_PER_SECTION)
+}
+
+/// Index of a block in a chunk delta's palette
+pub type PaletteIndex = u16;
 
 /// Represents the difference of a chunk from its generated state
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Sent and stored with a palette of the different blocks in it, so each changed block only takes
+/// a small index however wide block type ids and states are
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "PalettedChunkDelta", into = "PalettedChunkDelta")]
 pub struct ChunkDeltaData {
 	pub pos: ChunkPos,
 	pub blocks: Vec<(LocalBlockPos, BlockData)>,
@@ -174,25 +358,285 @@ impl ChunkDeltaData {
 	}
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+/// How a `ChunkDeltaData` is serialized
+#[derive(Serialize, Deserialize)]
+struct PalettedChunkDelta {
+	pos: ChunkPos,
+	palette: Vec<BlockData>,
+	blocks: Vec<(LocalBlockPos, PaletteIndex)>,
+}
+impl TryFrom<PalettedChunkDelta> for ChunkDeltaData {
+	type Error = String;
+
+	fn try_from(delta: PalettedChunkDelta) -> Result<Self, Self::Error> {
+		let blocks = delta
+			.blocks
+			.into_iter()
+			.map(|(pos, index)| match delta.palette.get(index as usize) {
+				Some(block) => Ok((pos, *block)),
+				None => Err(format!("palette index {} out of bounds", index)),
+			})
+			.collect::<Result<_, _>>()?;
+		Ok(ChunkDeltaData {
+			pos: delta.pos,
+			blocks,
+		})
+	}
+}
+impl From<ChunkDeltaData> for PalettedChunkDelta {
+	fn from(delta: ChunkDeltaData) -> Self {
+		let mut palette: Vec<BlockData> = Vec::new();
+		let mut palette_indices = HashMap::new();
+		let blocks = delta
+			.blocks
+			.into_iter()
+			.map(|(pos, block)| {
+				let index = *palette_indices.entry(block).or_insert_with(|| {
+					palette.push(block);
+					(palette.len() - 1) as PaletteIndex
+				});
+				(pos, index)
+			})
+			.collect();
+		PalettedChunkDelta {
+			pos: delta.pos,
+			palette,
+			blocks,
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct BlockData {
 	/// Block type ID
 	pub type_id: BlockTypeId,
+	/// Values of the block type's properties, 0 is the first value of each
+	///
+	/// Defaults when missing, blocks saved before states existed don't have it
+	#[serde(default)]
+	pub state: BlockState,
+}
+impl BlockData {
+	/// Block of a type in its default state
+	pub const fn new(type_id: BlockTypeId) -> BlockData {
+		BlockData { type_id, state: 0 }
+	}
 }
 impl Default for BlockData {
 	fn default() -> Self {
-		Self {
-			type_id: AIR_BLOCK_ID,
-		}
+		BlockData::new(AIR_BLOCK_ID)
 	}
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct WorldGenesisData {
 	pub seed: u32,
 }
-impl Default for WorldGenesisData {
-	fn default() -> Self {
-		WorldGenesisData { seed: 0u32 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::Rng;
+
+	#[test]
+	fn block_pos_round_trips_through_chunks() {
+		let mut rng = Rng(0x9E3779B97F4A7C15);
+		for _ in 0..10_000 {
+			let pos = BlockPos::new(
+				rng.range(-100_000..100_000),
+				rng.range(0..WORLD_HEIGHT as i32),
+				rng.range(-100_000..100_000),
+			);
+			let (chunk, local) = pos.to_local().unwrap();
+			assert_eq!(chunk, pos.chunk());
+			assert_eq!(BlockPos::from_local(chunk, local), pos);
+			// the block is inside its chunk
+			let chunk_x = chunk.x * CHUNK_WIDTH as i32;
+			let chunk_z = chunk.z * CHUNK_WIDTH as i32;
+			assert!((chunk_x..(chunk_x + CHUNK_WIDTH as i32)).contains(&pos.x));
+			assert!((chunk_z..(chunk_z + CHUNK_WIDTH as i32)).contains(&pos.z));
+		}
+
+		// around zero, where rounding towards zero would go wrong
+		let local = |x: i32, z: i32| {
+			let (chunk, pos) = BlockPos::new(x, 10, z).to_local().unwrap();
+			((chunk.x, chunk.z), (pos.x(), pos.z()))
+		};
+		assert_eq!(local(0, 0), ((0, 0), (0, 0)));
+		assert_eq!(local(-1, 15), ((-1, 0), (15, 15)));
+		assert_eq!(local(-16, 16), ((-1, 1), (0, 0)));
+		assert_eq!(local(-17, -33), ((-2, -3), (15, 15)));
+
+		// nothing above or below the world
+		assert_eq!(BlockPos::new(0, -1, 0).to_local(), None);
+		assert_eq!(BlockPos::new(0, WORLD_HEIGHT as i32, 0).to_local(), None);
+		assert_eq!(BlockPos::new(-5, -1, 0).chunk(), ChunkPos { x: -1, z: 0 });
+	}
+
+	#[test]
+	fn block_neighbors_cross_chunk_borders() {
+		let mut rng = Rng(0x2545F4914F6CDD1D);
+		for _ in 0..10_000 {
+			// close to chunk borders more often than not
+			let pos = BlockPos::new(
+				rng.range(-40..40),
+				rng.range(0..WORLD_HEIGHT as i32),
+				rng.range(-40..40),
+			);
+			let (chunk, local) = pos.to_local().unwrap();
+			for (dir, neighbor) in pos.neighbors() {
+				let [x, y, z] = dir.offset();
+				assert_eq!(
+					[neighbor.x - pos.x, neighbor.y - pos.y, neighbor.z - pos.z],
+					[x, y, z]
+				);
+				match (local.get_neighbor(dir), neighbor.to_local()) {
+					// inside the chunk, both agree
+					(Some(local_neighbor), Some(n)) => assert_eq!(n, (chunk, local_neighbor)),
+					// otherwise it's in the next chunk over
+					(None, Some((n_chunk, _))) => {
+						assert_ne!(n_chunk, chunk);
+						assert_eq!(
+							[n_chunk.x - chunk.x, n_chunk.z - chunk.z],
+							[x, z],
+							"{} next to {} is in the wrong chunk",
+							neighbor,
+							pos
+						);
+					}
+					// or above or below the world
+					(None, None) => assert!(neighbor.y < 0 || neighbor.y >= WORLD_HEIGHT as i32),
+					(Some(_), None) => panic!("{} should be in the world", neighbor),
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn world_positions_are_in_their_block() {
+		let mut rng = Rng(0x853C49E6748FEA9B);
+		for _ in 0..10_000 {
+			let point = [0, 1, 2].map(|_| rng.range(-10_000_000..10_000_000) as f32 / 100.0);
+			let block = BlockPos::from_world_pos(point);
+			for (coord, block_coord) in point.into_iter().zip([block.x, block.y, block.z]) {
+				assert!(
+					block_coord as f32 <= coord && coord < block_coord as f32 + 1.0,
+					"{:?} is not in {}",
+					point,
+					block
+				);
+			}
+		}
+		assert_eq!(
+			BlockPos::from_world_pos([-0.5, 0.0, -1.0]),
+			BlockPos::new(-1, 0, -1)
+		);
+		assert_eq!(
+			BlockPos::from_world_pos([-16.25, 3.9, 15.99]).chunk(),
+			ChunkPos { x: -2, z: 0 }
+		);
+	}
+
+	#[test]
+	fn chunk_sections_make_room_for_new_blocks() {
+		let mut chunk = ChunkData::new(ChunkPos { x: 0, z: 0 });
+		let pos = LocalBlockPos::new(3u8, 200, 7);
+		assert_eq!(chunk.get(pos), BlockData::default());
+
+		// a different block every time, but only two are ever in the section at once
+		for i in 0..(2 * BLOCKS_PER_SECTION) {
+			let block = BlockData {
+				type_id: (i % 1000) as BlockTypeId,
+				state: (i / 1000) as BlockState,
+			};
+			chunk.set(pos, block);
+			assert_eq!(chunk.get(pos), block);
+		}
+		assert_eq!(
+			chunk.get(LocalBlockPos::new(0u8, 200, 0)),
+			BlockData::default()
+		);
+		assert_eq!(
+			chunk
+				.iter()
+				.filter(|(_, b)| *b != BlockData::default())
+				.count(),
+			1
+		);
+		// the palette was cleaned out as it went instead of growing to fit every block
+		assert!(
+			chunk.memory_usage()
+				< ChunkData::new(chunk.pos).memory_usage() + BLOCKS_PER_SECTION / 2
+		);
+	}
+
+	#[test]
+	fn chunk_sections_match_a_flat_array() {
+		let mut rng = Rng(0x2545F4914F6CDD1D);
+		let mut chunk = ChunkData::new(ChunkPos { x: 0, z: 0 });
+		let mut expected = vec![BlockData::default(); BLOCKS_PER_CHUNK];
+		let mut check = |chunk: &mut ChunkData, rng: &mut Rng, kinds: u64| {
+			for _ in 0..20_000 {
+				// mostly near the bottom, so some sections fill up and others stay empty
+				let i = (rng.next() % (BLOCKS_PER_CHUNK as u64 / 4)) as usize;
+				let block = BlockData {
+					type_id: (rng.next() % kinds) as BlockTypeId,
+					state: (rng.next() % 2) as BlockState,
+				};
+				chunk.set(LocalBlockPos::from_index(i), block);
+				expected[i] = block;
+			}
+			for (pos, block) in chunk.iter() {
+				assert_eq!(
+					block,
+					expected[pos.to_index()],
+					"block at {:?} differs",
+					pos
+				);
+				assert_eq!(chunk.get(pos), block);
+			}
+		};
+		// few kinds of blocks, then enough that sections need a lot of bits, then few again
+		check(&mut chunk, &mut rng, 3);
+		check(&mut chunk, &mut rng, 3000);
+		check(&mut chunk, &mut rng, 2);
+		assert!(chunk.is_air_section(WORLD_HEIGHT - 1));
+		assert!(!chunk.is_air_section(0));
+	}
+
+	#[test]
+	fn chunk_deltas_are_sent_with_a_palette() {
+		let stone = BlockData::new(2);
+		let wide = BlockData {
+			type_id: 1000,
+			state: 3,
+		};
+		let delta = ChunkDeltaData {
+			pos: ChunkPos { x: -4, z: 9 },
+			blocks: (0..100u8)
+				.map(|i| {
+					let block = if i % 2 == 0 { stone } else { wide };
+					(LocalBlockPos::new(i % 16, i, 0), block)
+				})
+				.collect(),
+		};
+		let bytes = rmp_serde::to_vec(&delta).unwrap();
+		assert_eq!(
+			rmp_serde::from_slice::<ChunkDeltaData>(&bytes).unwrap(),
+			delta
+		);
+
+		// each block is a position and a small index, the blocks themselves are only sent once
+		let unpaletted = rmp_serde::to_vec(&(delta.pos, &delta.blocks)).unwrap();
+		assert!(bytes.len() < unpaletted.len());
+
+		// indices outside the palette are rejected
+		let bad = (
+			delta.pos,
+			vec![stone],
+			vec![(LocalBlockPos::new(0u8, 0, 0), 1u16)],
+		);
+		let bytes = rmp_serde::to_vec(&bad).unwrap();
+		assert!(rmp_serde::from_slice::<ChunkDeltaData>(&bytes).is_err());
 	}
 }
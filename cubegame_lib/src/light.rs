@@ -0,0 +1,576 @@
+use crate::blocks::BlockType;
+use crate::{
+	BlockData, BlockPos, ChunkData, ChunkPos, Direction, LocalBlockPos, BLOCKS_PER_CHUNK,
+	CHUNK_WIDTH, WORLD_HEIGHT,
+};
+use std::collections::VecDeque;
+
+/// Brightest a block can be lit
+pub const MAX_LIGHT: u8 = 15;
+
+/// The two kinds of light, which spread separately
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LightChannel {
+	/// Light from the sky, which goes straight down without fading
+	Sky,
+	/// Light given off by blocks
+	Block,
+}
+impl LightChannel {
+	pub const ALL: [LightChannel; 2] = [LightChannel::Sky, LightChannel::Block];
+}
+
+/// Block in the world: the chunk it's in and where it is in it
+type WorldPos = (ChunkPos, LocalBlockPos);
+
+/// Light levels of every block in a chunk
+///
+/// Sky light is stored in the 4 most significant bits of each level, block light in the other 4
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkLight {
+	levels: Box<[u8; BLOCKS_PER_CHUNK]>,
+}
+impl ChunkLight {
+	/// Makes light data with every block fully dark
+	pub fn dark() -> ChunkLight {
+		ChunkLight {
+			levels: vec![0; BLOCKS_PER_CHUNK]
+				.into_boxed_slice()
+				.try_into()
+				.unwrap(),
+		}
+	}
+
+	/// Lights a chunk on its own, as if there was nothing around it
+	///
+	/// Light from and into neighbouring chunks is spread with `spread_across_borders` once the chunk
+	/// is part of a world
+	pub fn compute(data: &ChunkData) -> ChunkLight {
+		let mut light = ChunkLight::dark();
+		let mut sky_queue = VecDeque::new();
+		let mut block_queue = VecDeque::new();
+
+		// sky light goes down every column until it hits a block, heights are the lowest block each
+		// column's sky light reaches (`WORLD_HEIGHT` when the top block stops it)
+		let mut heights = [[WORLD_HEIGHT; CHUNK_WIDTH]; CHUNK_WIDTH];
+		for (x, column) in heights.iter_mut().enumerate() {
+			for (z, height) in column.iter_mut().enumerate() {
+				for y in (0..WORLD_HEIGHT).rev() {
+					let pos = LocalBlockPos::new(x as u8, y as u8, z as u8);
+					if !BlockType::from_id(data.get(pos).type_id).is_transparent() {
+						break;
+					}
+					light.set(pos, LightChannel::Sky, MAX_LIGHT);
+					*height = y;
+				}
+			}
+		}
+		// only the parts of columns next to taller columns have anywhere darker to spread to
+		for x in 0..CHUNK_WIDTH {
+			for z in 0..CHUNK_WIDTH {
+				let pos = LocalBlockPos::new(x as u8, 0, z as u8);
+				let tallest_neighbor = [
+					Direction::PosX,
+					Direction::NegX,
+					Direction::PosZ,
+					Direction::NegZ,
+				]
+				.into_iter()
+				.filter_map(|dir| pos.get_neighbor(dir))
+				.map(|n| heights[n.x() as usize][n.z() as usize])
+				.max()
+				.unwrap_or(0);
+				for y in heights[x][z]..tallest_neighbor {
+					sky_queue.push_back((data.pos, LocalBlockPos::new(x as u8, y as u8, z as u8)));
+				}
+			}
+		}
+
+		for (pos, block) in data.iter() {
+			let emission = BlockType::from_id(block.type_id).light_emission;
+			if emission > 0 {
+				light.set(pos, LightChannel::Block, emission);
+				block_queue.push_back((data.pos, pos));
+			}
+		}
+
+		let mut world = SingleChunk {
+			data,
+			light: &mut light,
+		};
+		spread(&mut world, LightChannel::Sky, sky_queue);
+		spread(&mut world, LightChannel::Block, block_queue);
+		light
+	}
+
+	pub fn get(&self, pos: LocalBlockPos, channel: LightChannel) -> u8 {
+		let level = self.levels[pos.to_index()];
+		match channel {
+			LightChannel::Sky => level >> 4,
+			LightChannel::Block => level & 0b1111,
+		}
+	}
+
+	pub fn set(&mut self, pos: LocalBlockPos, channel: LightChannel, level: u8) {
+		let stored = &mut self.levels[pos.to_index()];
+		*stored = match channel {
+			LightChannel::Sky => (*stored & 0b1111) | (level.min(MAX_LIGHT) << 4),
+			LightChannel::Block => (*stored & 0b11110000) | level.min(MAX_LIGHT),
+		};
+	}
+}
+
+/// Loaded chunks that light can spread through
+pub trait LightWorld {
+	/// Gets a block, `None` if its chunk isn't loaded
+	fn block(&self, chunk: ChunkPos, pos: LocalBlockPos) -> Option<BlockData>;
+	/// Gets a block's light level, 0 if its chunk isn't loaded
+	fn light(&self, chunk: ChunkPos, pos: LocalBlockPos, channel: LightChannel) -> u8;
+	/// Changes a block's light level, only called for blocks in loaded chunks
+	fn set_light(&mut self, chunk: ChunkPos, pos: LocalBlockPos, channel: LightChannel, level: u8);
+}
+
+/// A single chunk, with nothing around it
+struct SingleChunk<'a> {
+	data: &'a ChunkData,
+	light: &'a mut ChunkLight,
+}
+impl LightWorld for SingleChunk<'_> {
+	fn block(&self, chunk: ChunkPos, pos: LocalBlockPos) -> Option<BlockData> {
+		(chunk == self.data.pos).then(|| self.data.get(pos))
+	}
+
+	fn light(&self, chunk: ChunkPos, pos: LocalBlockPos, channel: LightChannel) -> u8 {
+		if chunk == self.data.pos {
+			self.light.get(pos, channel)
+		} else {
+			0
+		}
+	}
+
+	fn set_light(
+		&mut self,
+		_chunk: ChunkPos,
+		pos: LocalBlockPos,
+		channel: LightChannel,
+		level: u8,
+	) {
+		self.light.set(pos, channel, level);
+	}
+}
+
+/// Spreads light between a chunk that was just lit with `ChunkLight::compute` and the loaded chunks
+/// around it, both ways
+pub fn spread_across_borders(world: &mut impl LightWorld, chunk: ChunkPos) {
+	let last = CHUNK_WIDTH as u8 - 1;
+	let mut queue = VecDeque::new();
+	for y in 0..WORLD_HEIGHT {
+		let y = y as u8;
+		for along in 0..CHUNK_WIDTH as u8 {
+			for pos in [
+				LocalBlockPos::new(0, y, along),
+				LocalBlockPos::new(last, y, along),
+				LocalBlockPos::new(along, y, 0),
+				LocalBlockPos::new(along, y, last),
+			] {
+				queue.push_back((chunk, pos));
+				// the block touching it in the neighbouring chunk
+				for dir in [
+					Direction::PosX,
+					Direction::NegX,
+					Direction::PosZ,
+					Direction::NegZ,
+				] {
+					if pos.get_neighbor(dir).is_none() {
+						if let Some(neighbor) = neighbor(chunk, pos, dir) {
+							queue.push_back(neighbor);
+						}
+					}
+				}
+			}
+		}
+	}
+	for channel in LightChannel::ALL {
+		spread(world, channel, queue.clone());
+	}
+}
+
+/// Relights the world around a block that was just changed
+pub fn update_block(world: &mut impl LightWorld, chunk: ChunkPos, pos: LocalBlockPos) {
+	for channel in LightChannel::ALL {
+		let mut relight = VecDeque::new();
+
+		// taking away all the light that might have come through the block
+		let mut removed = vec![(chunk, pos)];
+		let mut removal = VecDeque::from([((chunk, pos), world.light(chunk, pos, channel))]);
+		world.set_light(chunk, pos, channel, 0);
+		while let Some(((c, p), level)) = removal.pop_front() {
+			for dir in Direction::ALL {
+				let Some((n_chunk, n_pos)) = neighbor(c, p, dir) else {
+					continue;
+				};
+				let n_level = world.light(n_chunk, n_pos, channel);
+				if n_level == 0 {
+					continue;
+				}
+				if n_level < level
+					|| (n_level == level && spread_level(channel, level, dir) == level)
+				{
+					// this block's light came from the removed one
+					world.set_light(n_chunk, n_pos, channel, 0);
+					removal.push_back(((n_chunk, n_pos), n_level));
+					removed.push((n_chunk, n_pos));
+				} else {
+					// lit from somewhere else, and can light the removed blocks back up
+					relight.push_back((n_chunk, n_pos));
+				}
+			}
+		}
+
+		// light sources that were taken away shine again
+		for (c, p) in removed {
+			let level = source_level(world, c, p, channel);
+			if level > world.light(c, p, channel) {
+				world.set_light(c, p, channel, level);
+				relight.push_back((c, p));
+			}
+		}
+		// light can come in from every side if the block is see through now
+		for dir in Direction::ALL {
+			if let Some(n) = neighbor(chunk, pos, dir) {
+				relight.push_back(n);
+			}
+		}
+		spread(world, channel, relight);
+	}
+}
+
+/// Light a block gives itself, without any coming from around it
+fn source_level(
+	world: &impl LightWorld,
+	chunk: ChunkPos,
+	pos: LocalBlockPos,
+	channel: LightChannel,
+) -> u8 {
+	let Some(block) = world.block(chunk, pos) else {
+		return 0;
+	};
+	let block_type = BlockType::from_id(block.type_id);
+	match channel {
+		LightChannel::Block => block_type.light_emission,
+		LightChannel::Sky => {
+			if !block_type.is_transparent() {
+				return 0;
+			}
+			let open_above = match pos.get_neighbor(Direction::PosY) {
+				Some(above) => world.light(chunk, above, LightChannel::Sky) == MAX_LIGHT,
+				None => true,
+			};
+			if open_above {
+				MAX_LIGHT
+			} else {
+				0
+			}
+		}
+	}
+}
+
+/// Level that light spreading out of a block in a direction has
+fn spread_level(channel: LightChannel, level: u8, dir: Direction) -> u8 {
+	if channel == LightChannel::Sky && dir == Direction::NegY && level == MAX_LIGHT {
+		// sky light going straight down doesn't fade
+		MAX_LIGHT
+	} else {
+		level.saturating_sub(1)
+	}
+}
+
+/// Spreads light outwards from every queued block, into every see through block it brightens
+fn spread(world: &mut impl LightWorld, channel: LightChannel, mut queue: VecDeque<WorldPos>) {
+	while let Some((chunk, pos)) = queue.pop_front() {
+		let level = world.light(chunk, pos, channel);
+		if level <= 1 {
+			continue;
+		}
+		for dir in Direction::ALL {
+			let Some((n_chunk, n_pos)) = neighbor(chunk, pos, dir) else {
+				continue;
+			};
+			let transparent = world
+				.block(n_chunk, n_pos)
+				.is_some_and(|block| BlockType::from_id(block.type_id).is_transparent());
+			let n_level = spread_level(channel, level, dir);
+			if transparent && world.light(n_chunk, n_pos, channel) < n_level {
+				world.set_light(n_chunk, n_pos, channel, n_level);
+				queue.push_back((n_chunk, n_pos));
+			}
+		}
+	}
+}
+
+/// Gets the block next to a block, which can be in the next chunk over
+///
+/// `None` above and below the world
+fn neighbor(chunk: ChunkPos, pos: LocalBlockPos, dir: Direction) -> Option<WorldPos> {
+	if let Some(neighbor) = pos.get_neighbor(dir) {
+		return Some((chunk, neighbor));
+	}
+	BlockPos::from_local(chunk, pos).neighbor(dir).to_local()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::blocks::AIR_BLOCK_ID;
+	use std::collections::HashMap;
+
+	const STONE: BlockData = BlockData::new(2);
+	const LAMP: BlockData = BlockData::new(5);
+	const AIR: BlockData = BlockData::new(AIR_BLOCK_ID);
+
+	#[derive(Default)]
+	struct TestWorld {
+		chunks: HashMap<ChunkPos, (Box<ChunkData>, ChunkLight)>,
+	}
+	impl TestWorld {
+		/// Adds a chunk with a stone floor below `floor_y`, lighting it like a game would
+		fn load(&mut self, pos: ChunkPos, floor_y: u8, blocks: &[(LocalBlockPos, BlockData)]) {
+			let mut data = Box::new(ChunkData::new(pos));
+			for i in 0..BLOCKS_PER_CHUNK {
+				let block_pos = LocalBlockPos::from_index(i);
+				if block_pos.y() < floor_y {
+					data.set(block_pos, STONE);
+				}
+			}
+			for (block_pos, block) in blocks {
+				data.set(*block_pos, *block);
+			}
+			let light = ChunkLight::compute(&data);
+			self.chunks.insert(pos, (data, light));
+			spread_across_borders(self, pos);
+		}
+
+		fn set_block(&mut self, chunk: ChunkPos, pos: LocalBlockPos, block: BlockData) {
+			self.chunks.get_mut(&chunk).unwrap().0.set(pos, block);
+			update_block(self, chunk, pos);
+		}
+
+		fn get(&self, chunk: ChunkPos, pos: LocalBlockPos, channel: LightChannel) -> u8 {
+			self.light(chunk, pos, channel)
+		}
+	}
+	impl LightWorld for TestWorld {
+		fn block(&self, chunk: ChunkPos, pos: LocalBlockPos) -> Option<BlockData> {
+			self.chunks.get(&chunk).map(|(data, _)| data.get(pos))
+		}
+
+		fn light(&self, chunk: ChunkPos, pos: LocalBlockPos, channel: LightChannel) -> u8 {
+			self.chunks
+				.get(&chunk)
+				.map_or(0, |(_, light)| light.get(pos, channel))
+		}
+
+		fn set_light(
+			&mut self,
+			chunk: ChunkPos,
+			pos: LocalBlockPos,
+			channel: LightChannel,
+			level: u8,
+		) {
+			self.chunks
+				.get_mut(&chunk)
+				.unwrap()
+				.1
+				.set(pos, channel, level);
+		}
+	}
+
+	fn pos(x: u8, y: u8, z: u8) -> LocalBlockPos {
+		LocalBlockPos::new(x, y, z)
+	}
+
+	const A: ChunkPos = ChunkPos { x: 0, z: 0 };
+	const B: ChunkPos = ChunkPos { x: 1, z: 0 };
+
+	#[test]
+	fn light_levels_are_stored_separately() {
+		let mut light = ChunkLight::dark();
+		light.set(pos(3, 4, 5), LightChannel::Sky, 12);
+		light.set(pos(3, 4, 5), LightChannel::Block, 7);
+		assert_eq!(light.get(pos(3, 4, 5), LightChannel::Sky), 12);
+		assert_eq!(light.get(pos(3, 4, 5), LightChannel::Block), 7);
+		light.set(pos(3, 4, 5), LightChannel::Sky, 0);
+		assert_eq!(light.get(pos(3, 4, 5), LightChannel::Block), 7);
+	}
+
+	#[test]
+	fn sky_light_fills_open_columns_and_fades_under_overhangs() {
+		let mut world = TestWorld::default();
+		// a roof covering x 0 to 7 at y 20
+		let roof: Vec<_> = (0..8)
+			.flat_map(|x| (0..16).map(move |z| (pos(x, 20, z), STONE)))
+			.collect();
+		world.load(A, 10, &roof);
+
+		assert_eq!(world.get(A, pos(12, 10, 3), LightChannel::Sky), MAX_LIGHT);
+		assert_eq!(world.get(A, pos(12, 255, 3), LightChannel::Sky), MAX_LIGHT);
+		assert_eq!(world.get(A, pos(4, 9, 3), LightChannel::Sky), 0);
+		// fading with distance from the edge of the roof
+		assert_eq!(
+			world.get(A, pos(7, 15, 3), LightChannel::Sky),
+			MAX_LIGHT - 1
+		);
+		assert_eq!(
+			world.get(A, pos(5, 15, 3), LightChannel::Sky),
+			MAX_LIGHT - 3
+		);
+		assert_eq!(
+			world.get(A, pos(0, 15, 3), LightChannel::Sky),
+			MAX_LIGHT - 8
+		);
+	}
+
+	#[test]
+	fn sky_light_spreads_under_a_block_at_the_top_of_the_world() {
+		let mut world = TestWorld::default();
+		world.load(A, 10, &[(pos(8, 255, 8), STONE)]);
+		assert_eq!(
+			world.get(A, pos(8, 254, 8), LightChannel::Sky),
+			MAX_LIGHT - 1
+		);
+		assert_eq!(
+			world.get(A, pos(8, 10, 8), LightChannel::Sky),
+			MAX_LIGHT - 1
+		);
+	}
+
+	#[test]
+	fn block_light_spreads_across_chunk_borders() {
+		let mut world = TestWorld::default();
+		world.load(A, 10, &[(pos(15, 10, 4), LAMP)]);
+		assert_eq!(world.get(A, pos(15, 10, 4), LightChannel::Block), MAX_LIGHT);
+		assert_eq!(world.get(A, pos(15, 9, 4), LightChannel::Block), 0);
+		assert_eq!(
+			world.get(A, pos(14, 10, 4), LightChannel::Block),
+			MAX_LIGHT - 1
+		);
+		assert_eq!(
+			world.get(A, pos(15, 11, 4), LightChannel::Block),
+			MAX_LIGHT - 1
+		);
+
+		// light comes into a chunk loaded after the lamp's
+		world.load(B, 0, &[]);
+		assert_eq!(
+			world.get(B, pos(0, 10, 4), LightChannel::Block),
+			MAX_LIGHT - 1
+		);
+		assert_eq!(
+			world.get(B, pos(3, 10, 4), LightChannel::Block),
+			MAX_LIGHT - 4
+		);
+		// and goes back into the first one through it
+		assert_eq!(world.get(A, pos(15, 9, 4), LightChannel::Block), 0);
+		assert_eq!(
+			world.get(B, pos(0, 9, 4), LightChannel::Block),
+			MAX_LIGHT - 2
+		);
+
+		// removing the lamp darkens both chunks
+		world.set_block(A, pos(15, 10, 4), STONE);
+		for chunk in [A, B] {
+			for i in 0..BLOCKS_PER_CHUNK {
+				let p = LocalBlockPos::from_index(i);
+				assert_eq!(world.get(chunk, p, LightChannel::Block), 0, "at {:?}", p);
+			}
+		}
+	}
+
+	#[test]
+	fn sky_light_spreads_into_newly_loaded_chunks() {
+		let mut world = TestWorld::default();
+		// a cave in chunk B that is only open towards chunk A
+		let walls: Vec<_> = (0..16)
+			.flat_map(|x| (0..16).map(move |z| (pos(x, 15, z), STONE)))
+			.collect();
+		world.load(B, 10, &walls);
+		assert_eq!(world.get(B, pos(0, 12, 8), LightChannel::Sky), 0);
+
+		world.load(A, 10, &[]);
+		assert_eq!(
+			world.get(B, pos(0, 12, 8), LightChannel::Sky),
+			MAX_LIGHT - 1
+		);
+		assert_eq!(
+			world.get(B, pos(4, 12, 8), LightChannel::Sky),
+			MAX_LIGHT - 5
+		);
+	}
+
+	#[test]
+	fn placing_and_removing_blocks_updates_sky_light() {
+		let mut world = TestWorld::default();
+		world.load(A, 10, &[]);
+		world.load(B, 10, &[]);
+
+		// a single block shades the column under it, only a little
+		world.set_block(A, pos(15, 20, 4), STONE);
+		assert_eq!(
+			world.get(A, pos(15, 19, 4), LightChannel::Sky),
+			MAX_LIGHT - 1
+		);
+		assert_eq!(
+			world.get(A, pos(15, 10, 4), LightChannel::Sky),
+			MAX_LIGHT - 1
+		);
+		assert_eq!(world.get(A, pos(15, 20, 4), LightChannel::Sky), 0);
+		assert_eq!(world.get(B, pos(0, 19, 4), LightChannel::Sky), MAX_LIGHT);
+
+		world.set_block(A, pos(15, 20, 4), AIR);
+		assert_eq!(world.get(A, pos(15, 10, 4), LightChannel::Sky), MAX_LIGHT);
+		assert_eq!(world.get(A, pos(15, 20, 4), LightChannel::Sky), MAX_LIGHT);
+	}
+
+	#[test]
+	fn incremental_updates_match_lighting_from_scratch() {
+		let changes = [
+			(A, pos(15, 12, 3), LAMP),
+			(B, pos(0, 14, 3), STONE),
+			(A, pos(3, 30, 3), STONE),
+			(B, pos(2, 11, 9), LAMP),
+			(A, pos(15, 12, 3), AIR),
+			(A, pos(8, 9, 8), AIR),
+			(A, pos(8, 8, 8), LAMP),
+			(B, pos(0, 14, 3), AIR),
+			(A, pos(3, 30, 3), AIR),
+		];
+		// a roof over both chunks with a hole in it
+		let mut roof: Vec<_> = (0..16)
+			.flat_map(|x| (0..16).map(move |z| (pos(x, 16, z), STONE)))
+			.filter(|(p, _)| (p.x(), p.z()) != (5, 5))
+			.collect();
+		roof.push((pos(3, 12, 3), LAMP));
+
+		let mut world = TestWorld::default();
+		world.load(A, 10, &roof);
+		world.load(B, 10, &roof);
+		for (i, (chunk, block_pos, block)) in changes.iter().enumerate() {
+			world.set_block(*chunk, *block_pos, *block);
+
+			let mut fresh = TestWorld::default();
+			for chunk in [B, A] {
+				let data = &world.chunks[&chunk].0;
+				let blocks: Vec<_> = data.iter().collect();
+				fresh.load(chunk, 0, &blocks);
+			}
+			for chunk in [A, B] {
+				assert!(
+					world.chunks[&chunk].1 == fresh.chunks[&chunk].1,
+					"light differs in {} after change {}",
+					chunk,
+					i
+				);
+			}
+		}
+	}
+}
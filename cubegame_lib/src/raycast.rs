@@ -0,0 +1,314 @@
+use crate::blocks::{BlockOpacity, BlockType};
+use crate::{BlockData, BlockPos, ChunkPos, Direction, LocalBlockPos};
+
+/// A block that a ray hit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+	pub pos: BlockPos,
+	/// Face of the block the ray went in through
+	pub face: Direction,
+	/// Distance along the ray to where it hit the block
+	pub distance: f32,
+}
+
+/// Finds the first visible block along a ray, walking through every block it passes through in
+/// order (DDA)
+///
+/// `block_at` gives the block at a position, `None` if its chunk isn't loaded. The ray stops at
+/// unloaded chunks, and goes through the empty space above and below the world until it's heading
+/// away from it. The block the ray starts in is skipped, so a camera inside a block can still look
+/// out of it. `max_distance` has to be finite
+pub fn raycast<F>(
+	origin: [f32; 3],
+	dir: [f32; 3],
+	max_distance: f32,
+	block_at: F,
+) -> Option<RaycastHit>
+where
+	F: Fn(ChunkPos, LocalBlockPos) -> Option<BlockData>,
+{
+	let len = dir.iter().map(|d| d * d).sum::<f32>().sqrt();
+	if !len.is_normal() || !origin.iter().all(|o| o.is_finite()) || !max_distance.is_finite() {
+		return None;
+	}
+	let dir = dir.map(|d| d / len);
+
+	let start = BlockPos::from_world_pos(origin);
+	let mut pos = [start.x, start.y, start.z];
+	// how far along the ray each axis crosses its next block boundary, and how far apart those are
+	let mut next_crossing = [0.0; 3];
+	let mut crossing_gap = [0.0; 3];
+	for axis in 0..3 {
+		crossing_gap[axis] = (1.0 / dir[axis]).abs();
+		next_crossing[axis] = if dir[axis] > 0.0 {
+			(pos[axis] as f32 + 1.0 - origin[axis]) / dir[axis]
+		} else if dir[axis] < 0.0 {
+			(pos[axis] as f32 - origin[axis]) / dir[axis]
+		} else {
+			f32::INFINITY
+		};
+	}
+
+	loop {
+		// stepping into the next block along whichever axis gets there first
+		let axis = (0..3)
+			.min_by(|a, b| next_crossing[*a].total_cmp(&next_crossing[*b]))
+			.unwrap();
+		let distance = next_crossing[axis];
+		if distance > max_distance {
+			return None;
+		}
+		next_crossing[axis] += crossing_gap[axis];
+		let forwards = dir[axis] > 0.0;
+		pos[axis] += if forwards { 1 } else { -1 };
+
+		let block_pos = BlockPos::new(pos[0], pos[1], pos[2]);
+		let Some((chunk, local)) = block_pos.to_local() else {
+			// above or below the world, only worth going on if the ray comes back into it
+			let heading_away = if pos[1] < 0 {
+				dir[1] <= 0.0
+			} else {
+				dir[1] >= 0.0
+			};
+			if heading_away {
+				return None;
+			}
+			continue;
+		};
+		let block = block_at(chunk, local)?;
+		if BlockType::from_id(block.type_id).opacity != BlockOpacity::Invisible {
+			// the face facing back the way the ray came
+			let face = match (axis, forwards) {
+				(0, true) => Direction::NegX,
+				(0, false) => Direction::PosX,
+				(1, true) => Direction::NegY,
+				(1, false) => Direction::PosY,
+				(_, true) => Direction::NegZ,
+				(_, false) => Direction::PosZ,
+			};
+			return Some(RaycastHit {
+				pos: block_pos,
+				face,
+				distance,
+			});
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{ChunkData, WORLD_HEIGHT};
+	use std::collections::HashMap;
+
+	const STONE: BlockData = BlockData::new(2);
+	const GLASS: BlockData = BlockData::new(6);
+
+	/// Loaded chunks full of air
+	struct TestWorld(HashMap<ChunkPos, ChunkData>);
+	impl TestWorld {
+		fn new(chunks: &[(i32, i32)]) -> TestWorld {
+			TestWorld(
+				chunks
+					.iter()
+					.map(|(x, z)| {
+						let pos = ChunkPos { x: *x, z: *z };
+						(pos, ChunkData::new(pos))
+					})
+					.collect(),
+			)
+		}
+
+		fn set(&mut self, pos: BlockPos, block: BlockData) {
+			let (chunk, local) = pos.to_local().unwrap();
+			self.0.get_mut(&chunk).unwrap().set(local, block);
+		}
+
+		fn raycast(
+			&self,
+			origin: [f32; 3],
+			dir: [f32; 3],
+			max_distance: f32,
+		) -> Option<RaycastHit> {
+			raycast(origin, dir, max_distance, |chunk, pos| {
+				self.0.get(&chunk).map(|data| data.get(pos))
+			})
+		}
+	}
+
+	fn assert_hit(hit: Option<RaycastHit>, pos: BlockPos, face: Direction, distance: f32) {
+		let hit = hit.expect("ray should hit a block");
+		assert_eq!((hit.pos, hit.face), (pos, face));
+		assert!(
+			(hit.distance - distance).abs() < 1e-4,
+			"hit at distance {}, not {}",
+			hit.distance,
+			distance
+		);
+	}
+
+	#[test]
+	fn axis_aligned_rays_hit_the_face_towards_them() {
+		let mut world = TestWorld::new(&[(0, 0)]);
+		let origin = [8.5, 20.5, 8.5];
+		let targets = [
+			(
+				[1.0, 0.0, 0.0],
+				BlockPos::new(12, 20, 8),
+				Direction::NegX,
+				3.5,
+			),
+			(
+				[-1.0, 0.0, 0.0],
+				BlockPos::new(2, 20, 8),
+				Direction::PosX,
+				5.5,
+			),
+			(
+				[0.0, 1.0, 0.0],
+				BlockPos::new(8, 30, 8),
+				Direction::NegY,
+				9.5,
+			),
+			(
+				[0.0, -1.0, 0.0],
+				BlockPos::new(8, 10, 8),
+				Direction::PosY,
+				9.5,
+			),
+			(
+				[0.0, 0.0, 1.0],
+				BlockPos::new(8, 20, 15),
+				Direction::NegZ,
+				6.5,
+			),
+			(
+				[0.0, 0.0, -2.0],
+				BlockPos::new(8, 20, 0),
+				Direction::PosZ,
+				7.5,
+			),
+		];
+		for (_, pos, ..) in targets {
+			world.set(pos, STONE);
+		}
+		for (dir, pos, face, distance) in targets {
+			assert_hit(world.raycast(origin, dir, 20.0), pos, face, distance);
+		}
+
+		// see through blocks are still hit
+		world.set(BlockPos::new(10, 20, 8), GLASS);
+		assert_hit(
+			world.raycast(origin, [1.0, 0.0, 0.0], 20.0),
+			BlockPos::new(10, 20, 8),
+			Direction::NegX,
+			1.5,
+		);
+		// too far away
+		assert_eq!(world.raycast(origin, [0.0, 1.0, 0.0], 9.0), None);
+		// no direction
+		assert_eq!(world.raycast(origin, [0.0, 0.0, 0.0], 20.0), None);
+	}
+
+	#[test]
+	fn diagonal_rays_hit_the_first_block_they_touch() {
+		let mut world = TestWorld::new(&[(0, 0)]);
+		// a wall along x = 10
+		for y in 0..WORLD_HEIGHT as i32 {
+			for z in 0..16 {
+				world.set(BlockPos::new(10, y, z), STONE);
+			}
+		}
+		let origin = [2.25, 20.5, 3.75];
+		let dir = [2.0, 1.0, 0.5];
+		// reaches x = 10 at origin + 3.875 * dir
+		let len = (2.0f32 * 2.0 + 1.0 + 0.25).sqrt();
+		assert_hit(
+			world.raycast(origin, dir, 50.0),
+			BlockPos::new(10, 24, 5),
+			Direction::NegX,
+			3.875 * len,
+		);
+
+		// blocks the ray only passes close to aren't hit
+		let mut world = TestWorld::new(&[(0, 0)]);
+		world.set(BlockPos::new(5, 21, 5), STONE);
+		world.set(BlockPos::new(4, 20, 5), STONE);
+		// crosses x = 5 first, then z = 5, then goes in through the bottom at y = 21
+		assert_hit(
+			world.raycast([3.7, 19.5, 3.6], [1.0, 1.0, 1.0], 20.0),
+			BlockPos::new(5, 21, 5),
+			Direction::NegY,
+			1.5 * 3.0f32.sqrt(),
+		);
+	}
+
+	#[test]
+	fn rays_cross_chunk_borders() {
+		let mut world = TestWorld::new(&[(0, 0), (-1, 0), (0, 1), (-1, -1)]);
+		world.set(BlockPos::new(-3, 40, 2), STONE);
+		world.set(BlockPos::new(1, 40, 20), STONE);
+		world.set(BlockPos::new(-5, 40, -5), STONE);
+
+		// into the chunk at negative x
+		assert_hit(
+			world.raycast([1.5, 40.5, 2.5], [-1.0, 0.0, 0.0], 20.0),
+			BlockPos::new(-3, 40, 2),
+			Direction::PosX,
+			3.5,
+		);
+		// into the next chunk along z
+		assert_hit(
+			world.raycast([1.5, 40.5, 2.5], [0.0, 0.0, 1.0], 20.0),
+			BlockPos::new(1, 40, 20),
+			Direction::NegZ,
+			17.5,
+		);
+		// diagonally past the corner between four chunks
+		assert_hit(
+			world.raycast([2.5, 40.5, 2.7], [-1.0, 0.0, -1.0], 20.0),
+			BlockPos::new(-5, 40, -5),
+			Direction::PosZ,
+			6.7 * 2.0f32.sqrt(),
+		);
+		// the ray stops at chunks that aren't loaded
+		assert_eq!(
+			world.raycast([1.5, 40.5, 2.5], [1.0, 0.0, 0.0], 100.0),
+			None
+		);
+	}
+
+	#[test]
+	fn rays_go_through_the_space_above_the_world() {
+		let mut world = TestWorld::new(&[(0, 0)]);
+		world.set(BlockPos::new(4, WORLD_HEIGHT as i32 - 1, 4), STONE);
+		assert_hit(
+			world.raycast(
+				[4.5, WORLD_HEIGHT as f32 + 10.0, 4.5],
+				[0.0, -1.0, 0.0],
+				20.0,
+			),
+			BlockPos::new(4, WORLD_HEIGHT as i32 - 1, 4),
+			Direction::PosY,
+			10.0,
+		);
+		// starting inside a block looks out of it
+		assert_eq!(
+			world.raycast([4.5, WORLD_HEIGHT as f32 - 0.5, 4.5], [0.0, 1.0, 0.0], 20.0),
+			None
+		);
+	}
+
+	#[test]
+	fn rays_that_never_reach_a_block_stop() {
+		// no chunks are loaded, so only leaving the world can end these rays
+		let world = TestWorld::new(&[]);
+		let above = [4.5, WORLD_HEIGHT as f32 + 10.0, 4.5];
+		let below = [4.5, -10.0, 4.5];
+		assert_eq!(world.raycast(above, [1.0, 0.0, 0.0], f32::MAX), None);
+		assert_eq!(world.raycast(above, [1.0, 0.5, 0.0], f32::MAX), None);
+		assert_eq!(world.raycast(below, [0.0, -1.0, 1.0], f32::MAX), None);
+		assert_eq!(world.raycast(above, [1.0, -1.0, 0.0], f32::INFINITY), None);
+		assert_eq!(world.raycast(above, [1.0, -1.0, 0.0], f32::NAN), None);
+	}
+}
@@ -0,0 +1,148 @@
+use crate::{BlockData, CHUNK_WIDTH};
+
+/// Height of a chunk section, in blocks
+pub const SECTION_HEIGHT: usize = 16;
+/// Number of blocks in a chunk section
+pub const BLOCKS_PER_SECTION: usize = CHUNK_WIDTH * CHUNK_WIDTH * SECTION_HEIGHT;
+
+/// A `SECTION_HEIGHT` block high slice of a chunk
+///
+/// Each block is stored as an index into a palette of the different blocks in the section, packed
+/// into as few bits as the palette needs. A section that's all one block (like the air above the
+/// ground) has a single palette entry and no indices at all
+///
+/// Blocks are indexed the same way as in a chunk, x, z, then y
+#[derive(Debug, Clone)]
+pub struct ChunkSection {
+	/// Every different block in the section (and maybe some that were, until it gets full)
+	palette: Vec<BlockData>,
+	/// Bits per index, 0 when the palette only has one block
+	bits: u32,
+	/// Packed palette indices, as many as fit in each word without spanning two words
+	words: Vec<u64>,
+}
+impl ChunkSection {
+	/// Makes a section full of one block
+	pub fn filled(block: BlockData) -> ChunkSection {
+		ChunkSection {
+			palette: vec![block],
+			bits: 0,
+			words: Vec::new(),
+		}
+	}
+
+	/// Gets the block at index `i` in the section
+	pub fn get(&self, i: usize) -> BlockData {
+		if self.bits == 0 {
+			return self.palette[0];
+		}
+		self.palette[self.index(i)]
+	}
+
+	/// Changes the block at index `i` in the section
+	pub fn set(&mut self, i: usize, block: BlockData) {
+		let index = match self.palette.iter().position(|b| *b == block) {
+			Some(index) => index,
+			None => {
+				if self.palette.len() >= 1 << self.bits {
+					// throwing away blocks that are gone first, the section might not need more bits
+					self.compact();
+					if self.palette.len() >= 1 << self.bits {
+						self.repack(self.bits + 1);
+					}
+				}
+				self.palette.push(block);
+				self.palette.len() - 1
+			}
+		};
+		if self.bits > 0 {
+			self.set_index(i, index);
+		}
+	}
+
+	/// Whether every block in the section is `block`
+	///
+	/// Only checks the palette, so sections that used to have other blocks can give false negatives
+	pub fn is_only(&self, block: BlockData) -> bool {
+		self.bits == 0 && self.palette[0] == block
+	}
+
+	/// Bytes used by the section, including what it points to
+	pub fn memory_usage(&self) -> usize {
+		size_of::<ChunkSection>()
+			+ self.palette.capacity() * size_of::<BlockData>()
+			+ self.words.capacity() * size_of::<u64>()
+	}
+
+	fn index(&self, i: usize) -> usize {
+		let per_word = (u64::BITS / self.bits) as usize;
+		let shift = (i % per_word) as u32 * self.bits;
+		((self.words[i / per_word] >> shift) & ((1 << self.bits) - 1)) as usize
+	}
+
+	fn set_index(&mut self, i: usize, index: usize) {
+		let per_word = (u64::BITS / self.bits) as usize;
+		let shift = (i % per_word) as u32 * self.bits;
+		let word = &mut self.words[i / per_word];
+		*word &= !(((1 << self.bits) - 1) << shift);
+		*word |= (index as u64) << shift;
+	}
+
+	/// Removes blocks that are no longer used from the palette, shrinking the indices if they fit
+	/// in fewer bits
+	fn compact(&mut self) {
+		if self.bits == 0 {
+			return;
+		}
+		let mut used = vec![false; self.palette.len()];
+		for i in 0..BLOCKS_PER_SECTION {
+			used[self.index(i)] = true;
+		}
+		let mut new_indices = vec![0; self.palette.len()];
+		let mut palette = Vec::new();
+		for (old, block) in self.palette.iter().enumerate() {
+			if used[old] {
+				new_indices[old] = palette.len();
+				palette.push(*block);
+			}
+		}
+
+		let indices: Vec<usize> = (0..BLOCKS_PER_SECTION)
+			.map(|i| new_indices[self.index(i)])
+			.collect();
+		self.palette = palette;
+		self.write_indices(bits_for(self.palette.len()), &indices);
+	}
+
+	/// Changes the number of bits per index
+	fn repack(&mut self, bits: u32) {
+		let indices: Vec<usize> = if self.bits == 0 {
+			vec![0; BLOCKS_PER_SECTION]
+		} else {
+			(0..BLOCKS_PER_SECTION).map(|i| self.index(i)).collect()
+		};
+		self.write_indices(bits, &indices);
+	}
+
+	fn write_indices(&mut self, bits: u32, indices: &[usize]) {
+		self.bits = bits;
+		if bits == 0 {
+			self.words = Vec::new();
+			return;
+		}
+		let per_word = (u64::BITS / bits) as usize;
+		self.words = vec![0; BLOCKS_PER_SECTION.div_ceil(per_word)];
+		for (i, index) in indices.iter().enumerate() {
+			self.set_index(i, *index);
+		}
+	}
+}
+
+/// Bits needed to index a palette of `len` blocks
+fn bits_for(len: usize) -> u32 {
+	if len <= 1 {
+		0
+	} else {
+		usize::BITS - (len - 1).leading_zeros()
+	}
+}
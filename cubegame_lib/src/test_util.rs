@@ -0,0 +1,21 @@
+//! Helpers shared between the tests of different modules
+
+use std::ops::Range;
+
+/// Small deterministic xorshift rng, so failures can be reproduced
+pub struct Rng(pub u64);
+impl Rng {
+	pub fn next(&mut self) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0
+	}
+	pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+		(0..len).map(|_| self.next() as u8).collect()
+	}
+	/// Random number in `range`
+	pub fn range(&mut self, range: Range<i32>) -> i32 {
+		range.start + (self.next() % (range.end - range.start) as u64) as i32
+	}
+}
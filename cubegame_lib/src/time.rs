@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+use std::time::{Duration, Instant};
+
+/// Number of world ticks in a real second
+pub const TICKS_PER_SECOND: u64 = 20;
+/// Number of ticks in a full day and night (20 minutes)
+pub const DAY_LENGTH: u64 = 24_000;
+/// Daylight still reaching the world in the middle of the night
+pub const NIGHT_DAYLIGHT: f32 = 0.2;
+
+/// Time in a world, in ticks since it was created
+///
+/// Worlds start at sunrise
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct WorldTime(pub u64);
+impl WorldTime {
+	/// How far through the current day it is, from 0 to 1
+	///
+	/// 0 is sunrise, 0.25 is noon, 0.5 is sunset and 0.75 is midnight
+	pub fn time_of_day(&self) -> f32 {
+		(self.0 % DAY_LENGTH) as f32 / DAY_LENGTH as f32
+	}
+
+	/// Direction towards the sun, which rises towards +x and sets towards -x
+	///
+	/// The moon is always in the opposite direction
+	pub fn sun_direction(&self) -> [f32; 3] {
+		let angle = self.time_of_day() * TAU;
+		[angle.cos(), angle.sin(), 0.0]
+	}
+
+	/// How much of the sky's light reaches the world, 1 during the day and lower at night
+	pub fn daylight(&self) -> f32 {
+		// fading around sunrise and sunset, while the sun is close to the horizon
+		let sun_height = self.sun_direction()[1];
+		let t = ((sun_height + 0.1) / 0.3).clamp(0.0, 1.0);
+		let t = t * t * (3.0 - 2.0 * t);
+		NIGHT_DAYLIGHT + (1.0 - NIGHT_DAYLIGHT) * t
+	}
+
+	/// Time after some real time has passed
+	pub fn advanced(&self, elapsed: Duration) -> WorldTime {
+		let ticks = elapsed.as_millis() as u64 * TICKS_PER_SECOND / 1000;
+		WorldTime(self.0 + ticks)
+	}
+}
+
+/// Keeps track of a world's time as real time passes
+#[derive(Debug, Clone)]
+pub struct WorldClock {
+	/// Time when the clock was last set
+	set_to: WorldTime,
+	set_at: Instant,
+}
+impl WorldClock {
+	pub fn new(time: WorldTime) -> WorldClock {
+		WorldClock {
+			set_to: time,
+			set_at: Instant::now(),
+		}
+	}
+
+	/// Current time in the world
+	pub fn now(&self) -> WorldTime {
+		self.set_to.advanced(self.set_at.elapsed())
+	}
+
+	/// Makes the clock continue from a different time
+	pub fn set(&mut self, time: WorldTime) {
+		*self = WorldClock::new(time);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sun_follows_the_time_of_day() {
+		let quarter = DAY_LENGTH / 4;
+		let sunrise = WorldTime(DAY_LENGTH * 3).sun_direction();
+		let noon = WorldTime(quarter).sun_direction();
+		let midnight = WorldTime(quarter * 3).sun_direction();
+		assert!((sunrise[0] - 1.0).abs() < 1e-4 && sunrise[1].abs() < 1e-4);
+		assert!((noon[1] - 1.0).abs() < 1e-4);
+		assert!((midnight[1] + 1.0).abs() < 1e-4);
+
+		assert_eq!(WorldTime(quarter).daylight(), 1.0);
+		assert!(WorldTime(quarter * 3).daylight() < 0.5);
+		assert!(WorldTime(0).daylight() > WorldTime(quarter * 3).daylight());
+	}
+
+	#[test]
+	fn time_advances_with_real_time() {
+		let time = WorldTime(100);
+		assert_eq!(time.advanced(Duration::from_secs(3)), WorldTime(160));
+		assert_eq!(time.advanced(Duration::from_millis(49)), WorldTime(100));
+	}
+}
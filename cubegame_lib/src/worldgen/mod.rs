@@ -1,17 +1,21 @@
 use crate::{
-	BlockData, ChunkData, ChunkPos, LocalBlockPos, WorldGenesisData, BLOCKS_PER_CHUNK, CHUNK_WIDTH,
+	blocks::{registry, NULL_BLOCK_ID},
+	BlockData, ChunkData, ChunkPos, LocalBlockPos, WorldGenesisData, CHUNK_WIDTH,
 };
 use noise::NoiseFn;
 
 /// Generates simple chunk for testing
 pub fn generate_chunk(gen: &WorldGenesisData, pos: ChunkPos) -> Box<ChunkData> {
 	// create empty chunk
-	let mut chunk = Box::new(ChunkData {
-		pos,
-		blocks: [BlockData::default(); BLOCKS_PER_CHUNK],
-	});
+	let mut chunk = Box::new(ChunkData::new(pos));
 
 	let rng = noise::Simplex::new(gen.seed);
+	let block = |name: &str| BlockData::new(registry().id_of(name).unwrap_or(NULL_BLOCK_ID));
+	let (grass, stone, dirt) = (
+		block("grass_block"),
+		block("stone_block"),
+		block("dirt_block"),
+	);
 
 	let offset_x = CHUNK_WIDTH as f64 * pos.x as f64;
 	let offset_z = CHUNK_WIDTH as f64 * pos.z as f64;
@@ -29,16 +33,16 @@ pub fn generate_chunk(gen: &WorldGenesisData, pos: ChunkPos) -> Box<ChunkData> {
 			let floor_y = floor_height as u8;
 			let pos = LocalBlockPos::new(x as u8, floor_y, z as u8);
 			// grass on top
-			chunk.blocks[pos.to_index()] = BlockData { type_id: 4 };
+			chunk.set(pos, grass);
 			// fill with stone
 			for y in 0..floor_y {
 				let pos = LocalBlockPos::new(x as u8, y, z as u8);
-				chunk.blocks[pos.to_index()] = BlockData { type_id: 2 };
+				chunk.set(pos, stone);
 			}
 			// couple layers of dirt underneath grass
 			for y in (floor_y - 3)..floor_y {
 				let pos = LocalBlockPos::new(x as u8, y, z as u8);
-				chunk.blocks[pos.to_index()] = BlockData { type_id: 3 };
+				chunk.set(pos, dirt);
 			}
 		}
 	}
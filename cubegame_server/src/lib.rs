@@ -1,17 +1,384 @@
+pub mod storage;
+pub mod world;
+
 use std::{
-	net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+	collections::HashMap,
+	fs, io,
+	net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+	path::PathBuf,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		mpsc::{self, Receiver, Sender},
+		Arc, Mutex,
+	},
 	thread,
+	time::{Duration, Instant},
 };
 
 use cubegame_lib::{
-	communication::{Communication, ServerMessage, ServerResponse},
-	BlockData, ChunkDeltaData, LocalBlockPos,
+	blocks::{self, BlockRegistry},
+	communication::{
+		capabilities, Communication, CommunicationError, ErrorMessage, ServerEvent, ServerMessage,
+		ServerResponse, PROTOCOL_VERSION,
+	},
+	WorldGenesisData,
+};
+use tungstenite::{
+	accept,
+	protocol::{frame::coding::CloseCode, CloseFrame},
+	Message, WebSocket,
 };
-use tungstenite::{accept, Message};
+use world::{is_valid_world_name, world_dir, World};
+
+/// How often modified regions are written to disk
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+/// Optional protocol features this server supports
+const SERVER_CAPABILITIES: &[&str] = &[
+	capabilities::WORLD_MANAGEMENT,
+	capabilities::BLOCK_EVENTS,
+	capabilities::WORLD_TIME,
+	capabilities::BLOCK_REGISTRY,
+];
+/// How long a connection waits for a message before checking if it has events to send
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// How often clients are sent the time of the world they're in
+const TIME_SYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Unique identifier of a connection
+pub type ConnectionId = u64;
+
+/// State shared between every connection to the server
+pub struct ServerState {
+	/// Directory containing all the worlds
+	save_dir: PathBuf,
+	/// Worlds that are currently loaded by at least one connection, by name
+	loaded_worlds: Mutex<HashMap<String, Arc<World>>>,
+}
+impl ServerState {
+	/// Creates server state, with worlds saved in `save_dir` and the installed block types (the built
+	/// in ones, unless others were installed)
+	pub fn new<P: Into<PathBuf>>(save_dir: P) -> Result<ServerState, ()> {
+		let save_dir = save_dir.into();
+		if let Err(e) = fs::create_dir_all(save_dir.join("worlds")) {
+			log::error!(
+				"Failed to open save directory \"{}\": {}",
+				save_dir.display(),
+				e
+			);
+			return Err(());
+		}
+		Ok(ServerState {
+			save_dir,
+			loaded_worlds: Mutex::new(HashMap::new()),
+		})
+	}
+
+	/// Creates server state, with worlds saved in `save_dir`, after installing the block types in
+	/// `blocks`
+	///
+	/// Fails if different block types are already installed, since they're shared by the whole
+	/// process
+	pub fn with_blocks<P: Into<PathBuf>>(
+		save_dir: P,
+		blocks: BlockRegistry,
+	) -> Result<ServerState, ()> {
+		if blocks::install_registry(blocks).is_err() {
+			log::error!("Different block types are already in use");
+			return Err(());
+		}
+		ServerState::new(save_dir)
+	}
+
+	/// Handles a message from a connection, producing the response to send back
+	pub fn make_response(&self, conn: &mut ConnectionState, msg: &ServerMessage) -> ServerResponse {
+		// wrapped messages get checked once they are unwrapped
+		if conn.player_name.is_none()
+			&& !matches!(
+				msg,
+				ServerMessage::Hello { .. } | ServerMessage::Request { .. }
+			) {
+			return ServerResponse::Err(ErrorMessage::HandshakeRequired);
+		}
+
+		match msg {
+			ServerMessage::Hello {
+				protocol_version,
+				player_name,
+				capabilities,
+			} => {
+				if *protocol_version != PROTOCOL_VERSION {
+					log::warn!(
+						"Refusing player \"{}\" with protocol version {} (server uses {})",
+						player_name,
+						protocol_version,
+						PROTOCOL_VERSION
+					);
+					return ServerResponse::Err(ErrorMessage::IncompatibleProtocolVersion {
+						server: PROTOCOL_VERSION,
+						client: *protocol_version,
+					});
+				}
+				log::info!("Player \"{}\" joined", player_name);
+				conn.player_name = Some(player_name.clone());
+				conn.capabilities = capabilities.clone();
+				ServerResponse::Welcome {
+					protocol_version: PROTOCOL_VERSION,
+					capabilities: SERVER_CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+				}
+			}
+			// requests with ids are handled like any other message, only the response is wrapped
+			ServerMessage::Request { id, message } => ServerResponse::Response {
+				id: *id,
+				response: Box::new(self.make_response(conn, message)),
+			},
+			ServerMessage::CreateWorld(name, genesis) => match self.create_world(name, *genesis) {
+				Ok(()) => ServerResponse::Ack,
+				Err(e) => ServerResponse::Err(e),
+			},
+			ServerMessage::ListWorlds => match self.list_worlds() {
+				Ok(names) => ServerResponse::WorldList(names),
+				Err(e) => ServerResponse::Err(e),
+			},
+			ServerMessage::LoadWorld(name) => {
+				// leaving the current world first
+				self.leave_world(conn);
+				match self.acquire_world(name) {
+					Ok(world) => {
+						let response = ServerResponse::WorldLoaded(world.genesis, world.time());
+						conn.world = Some(world);
+						response
+					}
+					Err(e) => ServerResponse::Err(e),
+				}
+			}
+			ServerMessage::UnloadWorld => {
+				if conn.world.is_none() {
+					return ServerResponse::Err(ErrorMessage::NoLoadedWorld);
+				}
+				self.leave_world(conn);
+				ServerResponse::Ack
+			}
+			ServerMessage::LoadChunk(chunk_pos) => {
+				let Some(world) = &conn.world else {
+					return ServerResponse::Err(ErrorMessage::NoLoadedWorld);
+				};
+				match world.load_chunk(*chunk_pos) {
+					Ok(delta) => {
+						if conn.has_capability(capabilities::BLOCK_EVENTS) {
+							world.subscribe(conn.id, &conn.event_sender, *chunk_pos);
+						}
+						ServerResponse::LoadChunkOK(delta)
+					}
+					Err(e) => {
+						log::error!(
+							"Failed to load chunk {} of world \"{}\": {}",
+							chunk_pos,
+							world.name,
+							e
+						);
+						ServerResponse::Err(ErrorMessage::Internal)
+					}
+				}
+			}
+			ServerMessage::UnloadChunk(chunk_pos) => {
+				let Some(world) = &conn.world else {
+					return ServerResponse::Err(ErrorMessage::NoLoadedWorld);
+				};
+				world.unsubscribe(conn.id, *chunk_pos);
+				ServerResponse::Ack
+			}
+			ServerMessage::GetBlockRegistry => {
+				ServerResponse::BlockRegistry(blocks::registry().clone())
+			}
+			ServerMessage::BlockUpdate(chunk_pos, block_pos, block) => {
+				let Some(world) = &conn.world else {
+					return ServerResponse::Err(ErrorMessage::NoLoadedWorld);
+				};
+				if !blocks::registry().is_valid(*block) {
+					return ServerResponse::Err(ErrorMessage::InvalidBlock);
+				}
+				match world.set_block(*chunk_pos, *block_pos, *block) {
+					Ok(()) => {
+						let event = ServerEvent::BlockChanged(*chunk_pos, *block_pos, *block);
+						world.broadcast(conn.id, *chunk_pos, event);
+						ServerResponse::Ack
+					}
+					Err(e) => {
+						log::error!(
+							"Failed to update block in chunk {} of world \"{}\": {}",
+							chunk_pos,
+							world.name,
+							e
+						);
+						ServerResponse::Err(ErrorMessage::Internal)
+					}
+				}
+			}
+		}
+	}
+
+	/// Queues an event telling a connection's client the time in its world, if it's in one and wants
+	/// to know
+	pub fn sync_time(&self, conn: &ConnectionState) {
+		let Some(world) = &conn.world else {
+			return;
+		};
+		if conn.has_capability(capabilities::WORLD_TIME) {
+			// the receiver is owned by the connection, so this can't fail
+			let _ = conn.event_sender.send(ServerEvent::TimeSync(world.time()));
+		}
+	}
+
+	/// Cleans up after a connection that has closed
+	pub fn close_connection(&self, conn: &mut ConnectionState) {
+		self.leave_world(conn);
+	}
+
+	/// Writes all modified chunks of every loaded world to disk
+	pub fn flush(&self) {
+		for world in self.loaded_worlds.lock().unwrap().values() {
+			if let Err(e) = world.flush() {
+				log::error!("Failed to save world \"{}\": {}", world.name, e);
+			}
+		}
+	}
+
+	fn create_world(&self, name: &str, genesis: WorldGenesisData) -> Result<(), ErrorMessage> {
+		if !is_valid_world_name(name) {
+			return Err(ErrorMessage::InvalidWorldName);
+		}
+		// holding the lock so that the world can't be loaded while it's being created
+		let _loaded_worlds = self.loaded_worlds.lock().unwrap();
+		let dir = world_dir(&self.save_dir, name);
+		if World::exists(&dir) {
+			return Err(ErrorMessage::WorldAlreadyExists);
+		}
+		if let Err(e) = World::create(&dir, name, genesis) {
+			log::error!("Failed to create world \"{}\": {}", name, e);
+			return Err(ErrorMessage::Internal);
+		}
+		log::info!("Created world \"{}\" (seed {})", name, genesis.seed);
+		Ok(())
+	}
+
+	/// Names of all the worlds in the save directory
+	fn list_worlds(&self) -> Result<Vec<String>, ErrorMessage> {
+		let entries = match fs::read_dir(self.save_dir.join("worlds")) {
+			Ok(entries) => entries,
+			Err(e) => {
+				log::error!("Failed to read worlds directory: {}", e);
+				return Err(ErrorMessage::Internal);
+			}
+		};
+		let mut names: Vec<String> = entries
+			.filter_map(|entry| entry.ok())
+			.filter(|entry| World::exists(&entry.path()))
+			.filter_map(|entry| entry.file_name().into_string().ok())
+			.collect();
+		names.sort();
+		Ok(names)
+	}
+
+	/// Gets a handle to a world, loading it from disk if no other connection has
+	fn acquire_world(&self, name: &str) -> Result<Arc<World>, ErrorMessage> {
+		let mut loaded_worlds = self.loaded_worlds.lock().unwrap();
+		if let Some(world) = loaded_worlds.get(name) {
+			return Ok(world.clone());
+		}
+
+		let dir = world_dir(&self.save_dir, name);
+		if !is_valid_world_name(name) || !World::exists(&dir) {
+			return Err(ErrorMessage::WorldDoesNotExist);
+		}
+		match World::open(&dir, name) {
+			Ok(world) => {
+				log::info!("Loaded world \"{}\"", name);
+				let world = Arc::new(world);
+				loaded_worlds.insert(name.to_string(), world.clone());
+				Ok(world)
+			}
+			Err(e) => {
+				log::error!("Failed to load world \"{}\": {}", name, e);
+				Err(ErrorMessage::Internal)
+			}
+		}
+	}
+
+	/// Takes a connection out of the world it is playing in, if any
+	fn leave_world(&self, conn: &mut ConnectionState) {
+		if let Some(world) = conn.world.take() {
+			world.remove_subscriber(conn.id);
+			self.release_world(world);
+		}
+	}
+
+	/// Gives up a handle to a world, saving and unloading it if no other connection is using it
+	fn release_world(&self, world: Arc<World>) {
+		let mut loaded_worlds = self.loaded_worlds.lock().unwrap();
+		let name = world.name.clone();
+		drop(world);
+		// only the server's handle left
+		if loaded_worlds
+			.get(&name)
+			.is_some_and(|w| Arc::strong_count(w) == 1)
+		{
+			let world = loaded_worlds.remove(&name).unwrap();
+			if let Err(e) = world.flush() {
+				log::error!("Failed to save world \"{}\": {}", name, e);
+			}
+			log::info!("Unloaded world \"{}\"", name);
+		}
+	}
+}
+
+/// State specific to a single connection
+pub struct ConnectionState {
+	id: ConnectionId,
+	/// Name of the connected player, set once the handshake is done
+	player_name: Option<String>,
+	/// Optional protocol features the client supports
+	capabilities: Vec<String>,
+	/// World this connection is playing in
+	world: Option<Arc<World>>,
+	/// Events that need to be sent to this connection's client
+	event_sender: Sender<ServerEvent>,
+	event_receiver: Receiver<ServerEvent>,
+}
+impl ConnectionState {
+	pub fn new() -> ConnectionState {
+		static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+		let (event_sender, event_receiver) = mpsc::channel();
+		ConnectionState {
+			id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+			player_name: None,
+			capabilities: Vec::new(),
+			world: None,
+			event_sender,
+			event_receiver,
+		}
+	}
+
+	pub fn id(&self) -> ConnectionId {
+		self.id
+	}
+
+	/// Takes the next event waiting to be sent to this connection's client, if there is one
+	pub fn next_event(&self) -> Option<ServerEvent> {
+		self.event_receiver.try_recv().ok()
+	}
 
-pub struct ServerState {}
+	pub fn player_name(&self) -> Option<&str> {
+		self.player_name.as_deref()
+	}
 
-pub fn run_server(port: u16) -> Result<(), ()> {
+	/// Whether the client advertised support for an optional protocol feature
+	pub fn has_capability(&self, capability: &str) -> bool {
+		self.capabilities.iter().any(|c| c == capability)
+	}
+}
+
+pub fn run_server(port: u16, state: Arc<ServerState>) -> Result<(), ()> {
 	log::info!("Launching server on port {}", port);
 
 	let listener = match TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)) {
@@ -21,8 +388,23 @@ pub fn run_server(port: u16) -> Result<(), ()> {
 			return Err(());
 		}
 	};
+	serve(listener, state);
+	Ok(())
+}
+
+/// Accepts and handles connections from a listener, forever
+pub fn serve(listener: TcpListener, state: Arc<ServerState>) {
+	// periodically saving loaded worlds
+	{
+		let state = state.clone();
+		thread::spawn(move || loop {
+			thread::sleep(FLUSH_INTERVAL);
+			state.flush();
+		});
+	}
 
 	for stream in listener.incoming() {
+		let state = state.clone();
 		thread::spawn(move || {
 			let stream = match stream {
 				Ok(s) => s,
@@ -37,46 +419,98 @@ pub fn run_server(port: u16) -> Result<(), ()> {
 					return;
 				}
 			};
-			log::info!("New connection from {:?}", stream.peer_addr().unwrap());
-
-			loop {
-				let received = websocket.read().unwrap();
-
-				match received {
-					Message::Binary(data) => {
-						let msg: ServerMessage = Communication::decode(&data);
-						//log::debug!("{:?}", msg);
-						websocket
-							.send(Message::binary(make_response(&msg).encode()))
-							.unwrap();
-					}
-					Message::Close(_) => {
-						log::info!("Connection closed");
-						break;
-					}
-					_ => {
-						log::warn!("Unexpected message type received: {:?}", received);
-					}
+			log::info!("New connection from {:?}", stream.peer_addr());
+			// reads need to time out every so often to send events
+			if let Err(e) = stream.set_read_timeout(Some(EVENT_POLL_INTERVAL)) {
+				log::error!("Failed to configure connection: {}", e);
+				return;
+			}
+
+			let mut conn = ConnectionState::new();
+			match serve_connection(&mut websocket, &state, &mut conn) {
+				Ok(()) => log::info!("Connection closed"),
+				Err(e) => {
+					log::warn!("Closing connection: {}", e);
+					// telling the client why, if the connection still works
+					let code = match e {
+						CommunicationError::Decode(_)
+						| CommunicationError::UnexpectedMessage(_) => CloseCode::Protocol,
+						_ => CloseCode::Error,
+					};
+					let _ = websocket.close(Some(CloseFrame {
+						code,
+						reason: e.to_string().into(),
+					}));
+					let _ = websocket.flush();
 				}
 			}
+			state.close_connection(&mut conn);
 		});
 	}
-	Ok(())
 }
 
-/// Function to handle messages
-fn make_response(msg: &ServerMessage) -> ServerResponse {
-	match msg {
-		ServerMessage::LoadChunk(chunk_pos) => {
-			let mut delta = ChunkDeltaData::empty(*chunk_pos);
-			delta
-				.blocks
-				.push((LocalBlockPos::new(2, 5, 2), BlockData::default()));
-			ServerResponse::LoadChunkOK(delta)
-		}
-		_ => {
-			log::warn!("Unhandled message, acknowledging: {:?}", msg);
-			ServerResponse::Ack
+/// Responds to messages from a connection until it is closed
+fn serve_connection(
+	websocket: &mut WebSocket<&TcpStream>,
+	state: &ServerState,
+	conn: &mut ConnectionState,
+) -> Result<(), CommunicationError> {
+	let transport_err = |e: tungstenite::Error| CommunicationError::Transport(e.to_string());
+	let mut last_time_sync = Instant::now();
+	loop {
+		if last_time_sync.elapsed() >= TIME_SYNC_INTERVAL {
+			last_time_sync = Instant::now();
+			state.sync_time(conn);
+		}
+		while let Some(event) = conn.next_event() {
+			let event = ServerResponse::Event(event).encode()?;
+			websocket
+				.write(Message::binary(event))
+				.map_err(transport_err)?;
+		}
+		websocket.flush().map_err(transport_err)?;
+
+		let received = match websocket.read() {
+			Ok(msg) => msg,
+			// nothing to read yet
+			Err(tungstenite::Error::Io(e))
+				if matches!(
+					e.kind(),
+					io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+				) =>
+			{
+				continue;
+			}
+			// closing handshake is done
+			Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+			Err(e) => return Err(transport_err(e)),
+		};
+
+		match received {
+			Message::Binary(data) => {
+				let msg = ServerMessage::decode(&data)?;
+				//log::debug!("{:?}", msg);
+				let response = state.make_response(conn, &msg);
+				// flushed at the start of the next loop, so pipelined requests get batched
+				websocket
+					.write(Message::binary(response.encode()?))
+					.map_err(transport_err)?;
+
+				// nothing else can be understood from incompatible clients
+				if let ServerResponse::Err(ErrorMessage::IncompatibleProtocolVersion { .. }) =
+					response
+				{
+					websocket.close(None).map_err(transport_err)?;
+				}
+			}
+			// replies are queued by tungstenite, the connection finishes closing on the next read
+			Message::Close(_) | Message::Ping(_) | Message::Pong(_) => {}
+			_ => {
+				return Err(CommunicationError::UnexpectedMessage(format!(
+					"{:?}",
+					received
+				)));
+			}
 		}
 	}
 }
@@ -0,0 +1,242 @@
+use std::{
+	collections::{HashMap, HashSet},
+	fs,
+	io::{self, Read, Write},
+	path::{Path, PathBuf},
+};
+
+use cubegame_lib::{BlockData, ChunkDeltaData, ChunkPos, LocalBlockPos};
+
+/// Width (and length) of a region, in chunks
+pub const REGION_WIDTH: i32 = 32;
+/// Number of chunks stored in a single region file
+const CHUNKS_PER_REGION: usize = (REGION_WIDTH * REGION_WIDTH) as usize;
+
+/// Identifies region files, followed by the format version
+const REGION_MAGIC: &[u8; 4] = b"CGRG";
+const REGION_FORMAT_VERSION: u32 = 3;
+/// Oldest format version that can still be read
+///
+/// Version 1 blocks had no state, which is read as the default state, and versions before 3 stored
+/// chunk deltas without a palette (see `UnpalettedChunkDelta`)
+const OLDEST_REGION_FORMAT_VERSION: u32 = 1;
+/// First format version where chunk deltas are stored with a palette
+const PALETTED_REGION_FORMAT_VERSION: u32 = 3;
+/// Magic + version + offset table (an offset and a length for each chunk)
+const REGION_HEADER_SIZE: usize = 8 + CHUNKS_PER_REGION * 8;
+
+/// Position of a region, a square group of chunks that are all stored in the same file
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub struct RegionPos {
+	pub x: i32,
+	pub z: i32,
+}
+impl RegionPos {
+	/// Gets the region that contains a chunk
+	pub fn of_chunk(chunk: ChunkPos) -> RegionPos {
+		RegionPos {
+			x: chunk.x.div_euclid(REGION_WIDTH),
+			z: chunk.z.div_euclid(REGION_WIDTH),
+		}
+	}
+
+	/// Index of a chunk in this region's offset table
+	fn chunk_index(chunk: ChunkPos) -> usize {
+		let x = chunk.x.rem_euclid(REGION_WIDTH) as usize;
+		let z = chunk.z.rem_euclid(REGION_WIDTH) as usize;
+		z * REGION_WIDTH as usize + x
+	}
+
+	fn filename(&self) -> String {
+		format!("r.{}.{}.region", self.x, self.z)
+	}
+}
+
+/// All the chunk deltas in a region that are held in memory
+#[derive(Debug, Default)]
+struct Region {
+	chunks: HashMap<ChunkPos, ChunkDeltaData>,
+	/// Whether this region has changed since it was last written to disk
+	dirty: bool,
+}
+
+/// Stores chunk deltas on disk, grouped into region files
+///
+/// Region file layout (all integers little endian):
+/// 	magic "CGRG", format version (u32)
+/// 	offset table: for each chunk in the region, (offset from start of file (u32), length (u32)),
+/// 		a length of zero means the chunk has no data
+/// 	msgpack encoded chunk deltas (with a palette, see `ChunkDeltaData`)
+///
+/// Regions are read lazily the first time one of their chunks is accessed, and are kept in memory
+/// until they are evicted (see `evict_clean_regions`)
+#[derive(Debug)]
+pub struct ChunkStore {
+	/// Directory containing the region files
+	dir: PathBuf,
+	regions: HashMap<RegionPos, Region>,
+}
+impl ChunkStore {
+	/// Opens a chunk store in a directory, creating the directory if it does not exist
+	pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<ChunkStore> {
+		let dir = dir.as_ref().to_path_buf();
+		fs::create_dir_all(&dir)?;
+		Ok(ChunkStore {
+			dir,
+			regions: HashMap::new(),
+		})
+	}
+
+	/// Gets the stored delta of a chunk, empty if the chunk has never been modified
+	pub fn load_chunk(&mut self, pos: ChunkPos) -> io::Result<ChunkDeltaData> {
+		let region = self.get_region(RegionPos::of_chunk(pos))?;
+		Ok(region
+			.chunks
+			.get(&pos)
+			.cloned()
+			.unwrap_or_else(|| ChunkDeltaData::empty(pos)))
+	}
+
+	/// Records a changed block in a chunk's delta
+	pub fn set_block(
+		&mut self,
+		chunk_pos: ChunkPos,
+		block_pos: LocalBlockPos,
+		block: BlockData,
+	) -> io::Result<()> {
+		let region = self.get_region(RegionPos::of_chunk(chunk_pos))?;
+		let delta = region
+			.chunks
+			.entry(chunk_pos)
+			.or_insert_with(|| ChunkDeltaData::empty(chunk_pos));
+		match delta.blocks.iter_mut().find(|(pos, _)| *pos == block_pos) {
+			Some((_, existing)) => *existing = block,
+			None => delta.blocks.push((block_pos, block)),
+		}
+		region.dirty = true;
+		Ok(())
+	}
+
+	/// Writes every region that has changed to disk
+	pub fn flush(&mut self) -> io::Result<()> {
+		for (pos, region) in self.regions.iter_mut() {
+			if !region.dirty {
+				continue;
+			}
+			write_region(&self.dir.join(pos.filename()), region)?;
+			region.dirty = false;
+		}
+		Ok(())
+	}
+
+	/// Drops regions from memory that are not in `in_use` and have no changes waiting to be
+	/// written, they're read again if one of their chunks is needed
+	pub fn evict_clean_regions(&mut self, in_use: &HashSet<RegionPos>) {
+		self.regions
+			.retain(|pos, region| region.dirty || in_use.contains(pos));
+	}
+
+	/// Gets a region from memory, reading it from disk if it hasn't been accessed yet
+	fn get_region(&mut self, pos: RegionPos) -> io::Result<&mut Region> {
+		if !self.regions.contains_key(&pos) {
+			let region = read_region(&self.dir.join(pos.filename()))?;
+			self.regions.insert(pos, region);
+		}
+		Ok(self.regions.get_mut(&pos).unwrap())
+	}
+}
+impl Drop for ChunkStore {
+	fn drop(&mut self) {
+		if let Err(e) = self.flush() {
+			log::error!(
+				"Failed to write regions to \"{}\": {}",
+				self.dir.display(),
+				e
+			);
+		}
+	}
+}
+
+/// How chunk deltas were stored before they had a palette: position and changed blocks
+type UnpalettedChunkDelta = (ChunkPos, Vec<(LocalBlockPos, BlockData)>);
+
+/// Reads a region file, giving an empty region if it does not exist
+fn read_region(path: &Path) -> io::Result<Region> {
+	let mut file = match fs::File::open(path) {
+		Ok(f) => f,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Region::default()),
+		Err(e) => return Err(e),
+	};
+	let mut bytes = Vec::new();
+	file.read_to_end(&mut bytes)?;
+
+	let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+	if bytes.len() < REGION_HEADER_SIZE || &bytes[0..4] != REGION_MAGIC {
+		return Err(invalid("not a region file"));
+	}
+	let version = read_u32(&bytes, 4);
+	if !(OLDEST_REGION_FORMAT_VERSION..=REGION_FORMAT_VERSION).contains(&version) {
+		return Err(invalid("unsupported region format version"));
+	}
+
+	let mut region = Region::default();
+	for i in 0..CHUNKS_PER_REGION {
+		let offset = read_u32(&bytes, 8 + i * 8) as usize;
+		let len = read_u32(&bytes, 8 + i * 8 + 4) as usize;
+		if len == 0 {
+			continue;
+		}
+		let Some(data) = bytes.get(offset..(offset + len)) else {
+			return Err(invalid("chunk data out of bounds"));
+		};
+		let delta = if version < PALETTED_REGION_FORMAT_VERSION {
+			rmp_serde::decode::from_slice::<UnpalettedChunkDelta>(data)
+				.map(|(pos, blocks)| ChunkDeltaData { pos, blocks })
+		} else {
+			rmp_serde::decode::from_slice::<ChunkDeltaData>(data)
+		}
+		.map_err(|e| invalid(&format!("malformed chunk data: {}", e)))?;
+		region.chunks.insert(delta.pos, delta);
+	}
+	Ok(region)
+}
+
+/// Writes a whole region file, replacing it atomically
+fn write_region(path: &Path, region: &Region) -> io::Result<()> {
+	let mut header = vec![0u8; REGION_HEADER_SIZE];
+	header[0..4].copy_from_slice(REGION_MAGIC);
+	header[4..8].copy_from_slice(&REGION_FORMAT_VERSION.to_le_bytes());
+
+	let mut body = Vec::new();
+	for (pos, delta) in region.chunks.iter() {
+		if delta.blocks.is_empty() {
+			continue;
+		}
+		let data = rmp_serde::encode::to_vec(delta)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		let offset = (REGION_HEADER_SIZE + body.len()) as u32;
+		let entry = 8 + RegionPos::chunk_index(*pos) * 8;
+		header[entry..(entry + 4)].copy_from_slice(&offset.to_le_bytes());
+		header[(entry + 4)..(entry + 8)].copy_from_slice(&(data.len() as u32).to_le_bytes());
+		body.extend_from_slice(&data);
+	}
+
+	write_atomically(path, &[&header, &body])
+}
+
+/// Writes a file by writing its parts to a temporary file and renaming that over it, so a crash
+/// mid-write leaves the old file intact
+pub fn write_atomically(path: &Path, parts: &[&[u8]]) -> io::Result<()> {
+	let mut tmp_path = path.as_os_str().to_owned();
+	tmp_path.push(".tmp");
+	let mut file = fs::File::create(&tmp_path)?;
+	for part in parts {
+		file.write_all(part)?;
+	}
+	file.sync_all()?;
+	fs::rename(tmp_path, path)
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+	u32::from_le_bytes(bytes[at..(at + 4)].try_into().unwrap())
+}
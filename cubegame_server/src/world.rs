@@ -0,0 +1,182 @@
+use std::{
+	collections::{HashMap, HashSet},
+	fs, io,
+	path::{Path, PathBuf},
+	sync::{mpsc::Sender, Mutex},
+};
+
+use cubegame_lib::{
+	communication::ServerEvent,
+	time::{WorldClock, WorldTime},
+	BlockData, ChunkDeltaData, ChunkPos, LocalBlockPos, WorldGenesisData,
+};
+
+use crate::{
+	storage::{self, ChunkStore, RegionPos},
+	ConnectionId,
+};
+
+/// Name of the file in a world's directory containing its genesis data
+const WORLD_INFO_FILENAME: &str = "world.dat";
+/// Name of the file in a world's directory containing its time when it was last saved
+const WORLD_TIME_FILENAME: &str = "time.dat";
+
+/// A named world loaded by the server
+#[derive(Debug)]
+pub struct World {
+	pub name: String,
+	/// Data the world was generated with
+	pub genesis: WorldGenesisData,
+	/// Directory the world is saved in
+	dir: PathBuf,
+	/// Time in the world, which only passes while it's loaded
+	clock: WorldClock,
+	/// Modified chunks of this world
+	chunks: Mutex<ChunkStore>,
+	/// Connections that get told about changes to the chunks they have loaded
+	subscribers: Mutex<HashMap<ConnectionId, Subscriber>>,
+}
+impl World {
+	/// Creates a brand new world in `dir`
+	pub fn create(dir: &Path, name: &str, genesis: WorldGenesisData) -> io::Result<World> {
+		fs::create_dir_all(dir)?;
+		let info = rmp_serde::encode::to_vec(&genesis)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		fs::write(dir.join(WORLD_INFO_FILENAME), info)?;
+		World::open(dir, name)
+	}
+
+	/// Opens an existing world from `dir`
+	pub fn open(dir: &Path, name: &str) -> io::Result<World> {
+		let info = fs::read(dir.join(WORLD_INFO_FILENAME))?;
+		let genesis: WorldGenesisData = rmp_serde::decode::from_slice(&info)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		// worlds that have never been saved start at the beginning of time
+		let time = match fs::read(dir.join(WORLD_TIME_FILENAME)) {
+			Ok(time) => rmp_serde::decode::from_slice(&time)
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => WorldTime::default(),
+			Err(e) => return Err(e),
+		};
+		Ok(World {
+			name: name.to_string(),
+			genesis,
+			dir: dir.to_path_buf(),
+			clock: WorldClock::new(time),
+			chunks: Mutex::new(ChunkStore::open(dir.join("regions"))?),
+			subscribers: Mutex::new(HashMap::new()),
+		})
+	}
+
+	/// Checks whether there is a world saved in `dir`
+	pub fn exists(dir: &Path) -> bool {
+		dir.join(WORLD_INFO_FILENAME).is_file()
+	}
+
+	/// Current time in the world
+	pub fn time(&self) -> WorldTime {
+		self.clock.now()
+	}
+
+	/// Gets the stored delta of a chunk
+	pub fn load_chunk(&self, pos: ChunkPos) -> io::Result<ChunkDeltaData> {
+		self.chunks.lock().unwrap().load_chunk(pos)
+	}
+
+	/// Changes a block in this world
+	pub fn set_block(
+		&self,
+		chunk_pos: ChunkPos,
+		block_pos: LocalBlockPos,
+		block: BlockData,
+	) -> io::Result<()> {
+		self.chunks
+			.lock()
+			.unwrap()
+			.set_block(chunk_pos, block_pos, block)
+	}
+
+	/// Starts sending events about a chunk to a connection
+	pub fn subscribe(&self, conn: ConnectionId, events: &Sender<ServerEvent>, chunk: ChunkPos) {
+		self.subscribers
+			.lock()
+			.unwrap()
+			.entry(conn)
+			.or_insert_with(|| Subscriber {
+				chunks: HashSet::new(),
+				events: events.clone(),
+			})
+			.chunks
+			.insert(chunk);
+	}
+
+	/// Stops sending events about a chunk to a connection
+	pub fn unsubscribe(&self, conn: ConnectionId, chunk: ChunkPos) {
+		if let Some(subscriber) = self.subscribers.lock().unwrap().get_mut(&conn) {
+			subscriber.chunks.remove(&chunk);
+		}
+	}
+
+	/// Stops sending any events to a connection
+	pub fn remove_subscriber(&self, conn: ConnectionId) {
+		self.subscribers.lock().unwrap().remove(&conn);
+	}
+
+	/// Sends an event about a chunk to every connection that has it loaded, except the one that
+	/// caused it
+	pub fn broadcast(&self, from: ConnectionId, chunk: ChunkPos, event: ServerEvent) {
+		let mut subscribers = self.subscribers.lock().unwrap();
+		// connections that have gone away without unsubscribing get dropped
+		subscribers.retain(|conn, subscriber| {
+			if *conn == from || !subscriber.chunks.contains(&chunk) {
+				return true;
+			}
+			subscriber.events.send(event.clone()).is_ok()
+		});
+	}
+
+	/// Writes all modified chunks and the world's time to disk
+	///
+	/// Regions that no connection has chunks loaded in are dropped from memory afterwards
+	pub fn flush(&self) -> io::Result<()> {
+		let time = rmp_serde::encode::to_vec(&self.time())
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		storage::write_atomically(&self.dir.join(WORLD_TIME_FILENAME), &[&time])?;
+
+		let in_use: HashSet<RegionPos> = self
+			.subscribers
+			.lock()
+			.unwrap()
+			.values()
+			.flat_map(|subscriber| subscriber.chunks.iter())
+			.map(|chunk| RegionPos::of_chunk(*chunk))
+			.collect();
+		let mut chunks = self.chunks.lock().unwrap();
+		chunks.flush()?;
+		chunks.evict_clean_regions(&in_use);
+		Ok(())
+	}
+}
+
+/// A connection listening for events in some chunks
+#[derive(Debug)]
+struct Subscriber {
+	chunks: HashSet<ChunkPos>,
+	events: Sender<ServerEvent>,
+}
+
+/// Checks that a world name can safely be used as a directory name
+pub fn is_valid_world_name(name: &str) -> bool {
+	!name.is_empty()
+		&& name.len() <= 64
+		&& name
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == ' ')
+		&& !name.starts_with(' ')
+		&& !name.ends_with(' ')
+}
+
+/// Directory of a world inside the server's save directory
+pub fn world_dir(save_dir: &Path, name: &str) -> PathBuf {
+	save_dir.join("worlds").join(name)
+}
@@ -0,0 +1,169 @@
+use std::{
+	net::{TcpListener, TcpStream},
+	sync::Arc,
+	thread,
+	time::Duration,
+};
+
+use cubegame_lib::{
+	communication::{
+		capabilities, Communication, ServerEvent, ServerMessage, ServerResponse, PROTOCOL_VERSION,
+	},
+	BlockData, ChunkPos, LocalBlockPos, WorldGenesisData,
+};
+use cubegame_server::ServerState;
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// Starts a server on a free port, giving its address
+fn start_server(dir: &std::path::Path) -> String {
+	let state = Arc::new(ServerState::new(dir).unwrap());
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let addr = format!("ws://{}", listener.local_addr().unwrap());
+	thread::spawn(move || cubegame_server::serve(listener, state));
+	addr
+}
+
+fn send(socket: &mut Socket, msg: ServerMessage) {
+	socket.send(Message::binary(msg.encode().unwrap())).unwrap();
+}
+
+fn recv(socket: &mut Socket) -> ServerResponse {
+	loop {
+		match socket.read().unwrap() {
+			Message::Binary(data) => return ServerResponse::decode(&data).unwrap(),
+			Message::Ping(_) | Message::Pong(_) => {}
+			other => panic!("unexpected message: {:?}", other),
+		}
+	}
+}
+
+fn request(socket: &mut Socket, msg: ServerMessage) -> ServerResponse {
+	send(socket, msg);
+	recv(socket)
+}
+
+/// Connects to the server and loads a world, creating it if needed
+fn join(addr: &str, world: &str, caps: &[&str]) -> Socket {
+	let (mut socket, _) = tungstenite::connect(addr).unwrap();
+	// a broken server should fail the test rather than hang it
+	if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+		stream
+			.set_read_timeout(Some(Duration::from_secs(5)))
+			.unwrap();
+	}
+
+	let hello = ServerMessage::Hello {
+		protocol_version: PROTOCOL_VERSION,
+		player_name: "tester".to_string(),
+		capabilities: caps.iter().map(|c| c.to_string()).collect(),
+	};
+	assert!(matches!(
+		request(&mut socket, hello),
+		ServerResponse::Welcome { .. }
+	));
+	// the world might have been created by another client already
+	request(
+		&mut socket,
+		ServerMessage::CreateWorld(world.to_string(), WorldGenesisData { seed: 5 }),
+	);
+	assert!(matches!(
+		request(&mut socket, ServerMessage::LoadWorld(world.to_string())),
+		ServerResponse::WorldLoaded(..)
+	));
+	socket
+}
+
+fn load_chunk(socket: &mut Socket, chunk: ChunkPos) {
+	assert!(matches!(
+		request(socket, ServerMessage::LoadChunk(chunk)),
+		ServerResponse::LoadChunkOK(_)
+	));
+}
+
+/// Checks that no events are waiting, by making sure the next thing received answers a request
+fn assert_no_events(socket: &mut Socket) {
+	match request(socket, ServerMessage::ListWorlds) {
+		ServerResponse::WorldList(_) => {}
+		other => panic!("expected no events, got {:?}", other),
+	}
+}
+
+#[test]
+fn block_updates_reach_clients_with_the_chunk_loaded() {
+	let dir = tempfile::tempdir().unwrap();
+	let addr = start_server(dir.path());
+	let caps = [capabilities::WORLD_MANAGEMENT, capabilities::BLOCK_EVENTS];
+	let chunk = ChunkPos { x: 2, z: -3 };
+	let pos = LocalBlockPos::new(4u8, 70, 9);
+	let block = BlockData::new(3);
+
+	let mut placer = join(&addr, "shared", &caps);
+	let mut watcher = join(&addr, "shared", &caps);
+	let mut far_away = join(&addr, "shared", &caps);
+	let mut other_world = join(&addr, "other", &caps);
+	let mut old_client = join(&addr, "shared", &[capabilities::WORLD_MANAGEMENT]);
+	load_chunk(&mut placer, chunk);
+	load_chunk(&mut watcher, chunk);
+	load_chunk(&mut far_away, ChunkPos { x: 50, z: 50 });
+	load_chunk(&mut other_world, chunk);
+	load_chunk(&mut old_client, chunk);
+
+	assert!(matches!(
+		request(&mut placer, ServerMessage::BlockUpdate(chunk, pos, block)),
+		ServerResponse::Ack
+	));
+	match recv(&mut watcher) {
+		ServerResponse::Event(event) => {
+			assert_eq!(event, ServerEvent::BlockChanged(chunk, pos, block))
+		}
+		other => panic!("expected an event, got {:?}", other),
+	}
+	// the change is only sent to clients that need it
+	assert_no_events(&mut placer);
+	assert_no_events(&mut far_away);
+	assert_no_events(&mut other_world);
+	assert_no_events(&mut old_client);
+
+	// unloaded chunks don't get events anymore
+	assert!(matches!(
+		request(&mut watcher, ServerMessage::UnloadChunk(chunk)),
+		ServerResponse::Ack
+	));
+	assert!(matches!(
+		request(&mut placer, ServerMessage::BlockUpdate(chunk, pos, block)),
+		ServerResponse::Ack
+	));
+	assert_no_events(&mut watcher);
+}
+
+#[test]
+fn disconnected_clients_do_not_break_broadcasts() {
+	let dir = tempfile::tempdir().unwrap();
+	let addr = start_server(dir.path());
+	let caps = [capabilities::WORLD_MANAGEMENT, capabilities::BLOCK_EVENTS];
+	let chunk = ChunkPos { x: 0, z: 0 };
+	let block = BlockData::new(1);
+
+	let mut placer = join(&addr, "world", &caps);
+	let mut watcher = join(&addr, "world", &caps);
+	load_chunk(&mut placer, chunk);
+	load_chunk(&mut watcher, chunk);
+
+	let mut gone = join(&addr, "world", &caps);
+	load_chunk(&mut gone, chunk);
+	drop(gone);
+
+	for i in 0..3u8 {
+		let pos = LocalBlockPos::new(i, 10, 0);
+		assert!(matches!(
+			request(&mut placer, ServerMessage::BlockUpdate(chunk, pos, block)),
+			ServerResponse::Ack
+		));
+		assert!(matches!(
+			recv(&mut watcher),
+			ServerResponse::Event(ServerEvent::BlockChanged(_, p, _)) if p == pos
+		));
+	}
+}
@@ -0,0 +1,22 @@
+use cubegame_lib::communication::{ServerMessage, ServerResponse, PROTOCOL_VERSION};
+use cubegame_server::{ConnectionState, ServerState};
+
+/// Creates a connection that has completed the handshake
+pub fn connect(state: &ServerState) -> ConnectionState {
+	connect_with(state, &[])
+}
+
+/// Creates a connection that has completed the handshake, advertising some capabilities
+#[allow(dead_code)]
+pub fn connect_with(state: &ServerState, capabilities: &[&str]) -> ConnectionState {
+	let mut conn = ConnectionState::new();
+	let hello = ServerMessage::Hello {
+		protocol_version: PROTOCOL_VERSION,
+		player_name: "tester".to_string(),
+		capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+	};
+	match state.make_response(&mut conn, &hello) {
+		ServerResponse::Welcome { .. } => conn,
+		other => panic!("unexpected response to handshake: {:?}", other),
+	}
+}
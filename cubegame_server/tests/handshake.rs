@@ -0,0 +1,77 @@
+use cubegame_lib::{
+	communication::{ErrorMessage, ServerMessage, ServerResponse, PROTOCOL_VERSION},
+	ChunkPos,
+};
+use cubegame_server::{ConnectionState, ServerState};
+
+#[test]
+fn messages_before_hello_are_refused() {
+	let dir = tempfile::tempdir().unwrap();
+	let state = ServerState::new(dir.path()).unwrap();
+	let mut conn = ConnectionState::new();
+
+	for msg in [
+		ServerMessage::ListWorlds,
+		ServerMessage::LoadChunk(ChunkPos { x: 0, z: 0 }),
+	] {
+		assert!(matches!(
+			state.make_response(&mut conn, &msg),
+			ServerResponse::Err(ErrorMessage::HandshakeRequired)
+		));
+	}
+	assert_eq!(conn.player_name(), None);
+}
+
+#[test]
+fn hello_with_matching_version_is_welcomed() {
+	let dir = tempfile::tempdir().unwrap();
+	let state = ServerState::new(dir.path()).unwrap();
+	let mut conn = ConnectionState::new();
+
+	let hello = ServerMessage::Hello {
+		protocol_version: PROTOCOL_VERSION,
+		player_name: "steve".to_string(),
+		capabilities: vec!["some_feature".to_string()],
+	};
+	match state.make_response(&mut conn, &hello) {
+		ServerResponse::Welcome {
+			protocol_version, ..
+		} => assert_eq!(protocol_version, PROTOCOL_VERSION),
+		other => panic!("unexpected response: {:?}", other),
+	}
+	assert_eq!(conn.player_name(), Some("steve"));
+	assert!(conn.has_capability("some_feature"));
+	assert!(!conn.has_capability("other_feature"));
+	assert!(matches!(
+		state.make_response(&mut conn, &ServerMessage::ListWorlds),
+		ServerResponse::WorldList(_)
+	));
+}
+
+#[test]
+fn hello_with_other_version_is_refused() {
+	let dir = tempfile::tempdir().unwrap();
+	let state = ServerState::new(dir.path()).unwrap();
+	let mut conn = ConnectionState::new();
+
+	let hello = ServerMessage::Hello {
+		protocol_version: PROTOCOL_VERSION + 1,
+		player_name: "from the future".to_string(),
+		capabilities: Vec::new(),
+	};
+	let response = state.make_response(&mut conn, &hello);
+	let ServerResponse::Err(err) = response else {
+		panic!("unexpected response: {:?}", response);
+	};
+	assert_eq!(
+		err,
+		ErrorMessage::IncompatibleProtocolVersion {
+			server: PROTOCOL_VERSION,
+			client: PROTOCOL_VERSION + 1,
+		}
+	);
+	assert!(matches!(
+		state.make_response(&mut conn, &ServerMessage::ListWorlds),
+		ServerResponse::Err(ErrorMessage::HandshakeRequired)
+	));
+}
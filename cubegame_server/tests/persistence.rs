@@ -0,0 +1,224 @@
+mod common;
+
+use std::{collections::HashSet, fs, thread, time::Duration};
+
+use cubegame_lib::{
+	communication::{ServerMessage, ServerResponse},
+	time::WorldTime,
+	BlockData, BlockTypeId, ChunkDeltaData, ChunkPos, LocalBlockPos, WorldGenesisData,
+};
+use cubegame_server::{
+	storage::{ChunkStore, RegionPos},
+	ConnectionState, ServerState,
+};
+
+/// Sends a message to the server that should be acknowledged
+fn expect_ack(state: &ServerState, conn: &mut ConnectionState, msg: ServerMessage) {
+	match state.make_response(conn, &msg) {
+		ServerResponse::Ack => {}
+		other => panic!("unexpected response to {:?}: {:?}", msg, other),
+	}
+}
+
+/// Loads a world, giving its genesis data
+fn load_world(state: &ServerState, conn: &mut ConnectionState, name: &str) -> WorldGenesisData {
+	match state.make_response(conn, &ServerMessage::LoadWorld(name.to_string())) {
+		ServerResponse::WorldLoaded(genesis, _) => genesis,
+		other => panic!("unexpected response: {:?}", other),
+	}
+}
+
+/// Loads a world, giving its time
+fn world_time(state: &ServerState, conn: &mut ConnectionState, name: &str) -> WorldTime {
+	match state.make_response(conn, &ServerMessage::LoadWorld(name.to_string())) {
+		ServerResponse::WorldLoaded(_, time) => time,
+		other => panic!("unexpected response: {:?}", other),
+	}
+}
+
+/// Loads a chunk through the server, panicking on an unexpected response
+fn load_chunk(state: &ServerState, conn: &mut ConnectionState, pos: ChunkPos) -> ChunkDeltaData {
+	match state.make_response(conn, &ServerMessage::LoadChunk(pos)) {
+		ServerResponse::LoadChunkOK(delta) => delta,
+		other => panic!("unexpected response: {:?}", other),
+	}
+}
+
+#[test]
+fn block_updates_survive_restart() {
+	let dir = tempfile::tempdir().unwrap();
+	let chunk = ChunkPos { x: 3, z: -7 };
+	let stone = BlockData::new(2);
+	let dirt = BlockData::new(3);
+
+	{
+		let state = ServerState::new(dir.path()).unwrap();
+		let mut conn = common::connect(&state);
+		let genesis = WorldGenesisData { seed: 1 };
+		expect_ack(
+			&state,
+			&mut conn,
+			ServerMessage::CreateWorld("test".to_string(), genesis),
+		);
+		assert_eq!(load_world(&state, &mut conn, "test"), genesis);
+		assert!(load_chunk(&state, &mut conn, chunk).blocks.is_empty());
+
+		let mut update = |pos, block| {
+			expect_ack(
+				&state,
+				&mut conn,
+				ServerMessage::BlockUpdate(chunk, pos, block),
+			)
+		};
+		update(LocalBlockPos::new(1u8, 30, 4), stone);
+		update(LocalBlockPos::new(15u8, 0, 15), stone);
+		// overwriting an already modified block
+		update(LocalBlockPos::new(1u8, 30, 4), dirt);
+		state.flush();
+	}
+
+	let state = ServerState::new(dir.path()).unwrap();
+	let mut conn = common::connect(&state);
+	assert_eq!(
+		load_world(&state, &mut conn, "test"),
+		WorldGenesisData { seed: 1 }
+	);
+	let delta = load_chunk(&state, &mut conn, chunk);
+	assert_eq!(delta.pos, chunk);
+	assert_eq!(
+		delta.blocks,
+		vec![
+			(LocalBlockPos::new(1u8, 30, 4), dirt),
+			(LocalBlockPos::new(15u8, 0, 15), stone),
+		]
+	);
+	// untouched chunks stay empty
+	assert!(load_chunk(&state, &mut conn, ChunkPos { x: 3, z: -6 })
+		.blocks
+		.is_empty());
+}
+
+#[test]
+fn chunks_in_different_regions_survive_drop() {
+	let dir = tempfile::tempdir().unwrap();
+	// chunks on either side of region borders, including negative coordinates
+	let chunks = [
+		ChunkPos { x: 0, z: 0 },
+		ChunkPos { x: 31, z: 31 },
+		ChunkPos { x: 32, z: 0 },
+		ChunkPos { x: -1, z: -1 },
+		ChunkPos { x: -32, z: 5 },
+		ChunkPos { x: -33, z: -100 },
+	];
+
+	{
+		let mut store = ChunkStore::open(dir.path()).unwrap();
+		for (i, chunk) in chunks.iter().enumerate() {
+			let block = BlockData::new(i as BlockTypeId);
+			store
+				.set_block(*chunk, LocalBlockPos::new(i as u8, 100, 0), block)
+				.unwrap();
+		}
+		// dropping the store flushes it
+	}
+
+	let mut store = ChunkStore::open(dir.path()).unwrap();
+	for (i, chunk) in chunks.iter().enumerate() {
+		let delta = store.load_chunk(*chunk).unwrap();
+		assert_eq!(delta.pos, *chunk);
+		assert_eq!(
+			delta.blocks,
+			vec![(
+				LocalBlockPos::new(i as u8, 100, 0),
+				BlockData::new(i as BlockTypeId)
+			)]
+		);
+	}
+}
+
+#[test]
+fn region_files_from_before_palettes_are_read() {
+	let dir = tempfile::tempdir().unwrap();
+	let chunk = ChunkPos { x: 0, z: 0 };
+	let pos = LocalBlockPos::new(1u8, 2, 3);
+
+	// a version 1 region, with one chunk whose blocks are just their type ids
+	let data = rmp_serde::to_vec(&(chunk, vec![(pos, [3 as BlockTypeId])])).unwrap();
+	let header_size = 8 + 32 * 32 * 8;
+	let mut bytes = vec![0u8; header_size];
+	bytes[0..4].copy_from_slice(b"CGRG");
+	bytes[4..8].copy_from_slice(&1u32.to_le_bytes());
+	bytes[8..12].copy_from_slice(&(header_size as u32).to_le_bytes());
+	bytes[12..16].copy_from_slice(&(data.len() as u32).to_le_bytes());
+	bytes.extend_from_slice(&data);
+	std::fs::write(dir.path().join("r.0.0.region"), bytes).unwrap();
+
+	let mut store = ChunkStore::open(dir.path()).unwrap();
+	assert_eq!(
+		store.load_chunk(chunk).unwrap().blocks,
+		vec![(pos, BlockData::new(3))]
+	);
+
+	// and it's upgraded when written again, ids too wide for the old format included
+	let wide = BlockData {
+		type_id: 300,
+		state: 2,
+	};
+	store.set_block(chunk, pos, wide).unwrap();
+	store.flush().unwrap();
+	let mut store = ChunkStore::open(dir.path()).unwrap();
+	assert_eq!(store.load_chunk(chunk).unwrap().blocks, vec![(pos, wide)]);
+}
+
+#[test]
+fn world_time_survives_restart() {
+	let dir = tempfile::tempdir().unwrap();
+	let saved_at = {
+		let state = ServerState::new(dir.path()).unwrap();
+		let mut conn = common::connect(&state);
+		expect_ack(
+			&state,
+			&mut conn,
+			ServerMessage::CreateWorld("test".to_string(), WorldGenesisData { seed: 1 }),
+		);
+		let start = world_time(&state, &mut conn, "test");
+		assert_eq!(start, WorldTime(0));
+
+		// a few ticks
+		thread::sleep(Duration::from_millis(200));
+		state.flush();
+		start.advanced(Duration::from_millis(200))
+	};
+
+	let state = ServerState::new(dir.path()).unwrap();
+	let mut conn = common::connect(&state);
+	assert!(world_time(&state, &mut conn, "test") >= saved_at);
+}
+
+#[test]
+fn only_unused_saved_regions_are_evicted() {
+	let dir = tempfile::tempdir().unwrap();
+	let used = ChunkPos { x: 0, z: 0 };
+	let unused = ChunkPos { x: 40, z: 0 };
+	let unsaved = ChunkPos { x: -40, z: 0 };
+	let pos = LocalBlockPos::new(1u8, 2, 3);
+	let block = BlockData::new(2);
+
+	let mut store = ChunkStore::open(dir.path()).unwrap();
+	store.set_block(used, pos, block).unwrap();
+	store.set_block(unused, pos, block).unwrap();
+	store.flush().unwrap();
+	store.set_block(unsaved, pos, block).unwrap();
+	store.evict_clean_regions(&HashSet::from([RegionPos::of_chunk(used)]));
+
+	// evicted regions get read from disk again, so without the files only the kept ones remain
+	for entry in fs::read_dir(dir.path()).unwrap() {
+		fs::remove_file(entry.unwrap().path()).unwrap();
+	}
+	assert_eq!(store.load_chunk(used).unwrap().blocks, vec![(pos, block)]);
+	assert_eq!(
+		store.load_chunk(unsaved).unwrap().blocks,
+		vec![(pos, block)]
+	);
+	assert!(store.load_chunk(unused).unwrap().blocks.is_empty());
+}
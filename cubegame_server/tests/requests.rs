@@ -0,0 +1,127 @@
+mod common;
+
+use cubegame_lib::{
+	blocks::{self, BlockOpacity, BlockRegistry, BlockTextureLayout, BlockType},
+	communication::{ErrorMessage, ServerMessage, ServerResponse},
+	BlockData, ChunkPos, LocalBlockPos, WorldGenesisData,
+};
+use cubegame_server::{ConnectionState, ServerState};
+
+fn wrap(id: u32, message: ServerMessage) -> ServerMessage {
+	ServerMessage::Request {
+		id,
+		message: Box::new(message),
+	}
+}
+
+#[test]
+fn requests_get_responses_with_their_id() {
+	let dir = tempfile::tempdir().unwrap();
+	let state = ServerState::new(dir.path()).unwrap();
+	let mut conn = common::connect(&state);
+
+	let create = ServerMessage::CreateWorld("world".to_string(), WorldGenesisData { seed: 3 });
+	assert!(matches!(
+		state.make_response(&mut conn, &create),
+		ServerResponse::Ack
+	));
+	let load = ServerMessage::LoadWorld("world".to_string());
+	assert!(matches!(
+		state.make_response(&mut conn, &load),
+		ServerResponse::WorldLoaded(..)
+	));
+
+	for id in [0, 1, 500, u32::MAX] {
+		let chunk = ChunkPos {
+			x: id as i32,
+			z: -(id as i32),
+		};
+		match state.make_response(&mut conn, &wrap(id, ServerMessage::LoadChunk(chunk))) {
+			ServerResponse::Response {
+				id: response_id,
+				response,
+			} => {
+				assert_eq!(response_id, id);
+				assert!(matches!(*response, ServerResponse::LoadChunkOK(d) if d.pos == chunk));
+			}
+			other => panic!("unexpected response: {:?}", other),
+		}
+	}
+}
+
+#[test]
+fn requests_before_hello_are_refused_with_their_id() {
+	let dir = tempfile::tempdir().unwrap();
+	let state = ServerState::new(dir.path()).unwrap();
+	let mut conn = ConnectionState::new();
+
+	match state.make_response(&mut conn, &wrap(9, ServerMessage::ListWorlds)) {
+		ServerResponse::Response { id: 9, response } => assert!(matches!(
+			*response,
+			ServerResponse::Err(ErrorMessage::HandshakeRequired)
+		)),
+		other => panic!("unexpected response: {:?}", other),
+	}
+	assert_eq!(conn.player_name(), None);
+}
+
+#[test]
+fn block_registry_is_sent_to_clients() {
+	let dir = tempfile::tempdir().unwrap();
+	let mut types: Vec<BlockType> = BlockRegistry::builtin().iter().cloned().collect();
+	types.push(BlockType {
+		id: 42,
+		name: "custom_block".to_string(),
+		texture_layout: BlockTextureLayout::Uniform("custom_block.png".to_string()),
+		light_emission: 3,
+		opacity: BlockOpacity::Cutout,
+		properties: Vec::new(),
+		texture_variants: Vec::new(),
+		axis_property: None,
+	});
+	let blocks = BlockRegistry::new(types).unwrap();
+	let state = ServerState::with_blocks(dir.path(), blocks.clone()).unwrap();
+	let mut conn = common::connect(&state);
+
+	match state.make_response(&mut conn, &wrap(3, ServerMessage::GetBlockRegistry)) {
+		ServerResponse::Response { id: 3, response } => match *response {
+			ServerResponse::BlockRegistry(received) => {
+				assert_eq!(received, blocks);
+				assert_eq!(received.id_of("custom_block"), Some(42));
+			}
+			other => panic!("unexpected response: {:?}", other),
+		},
+		other => panic!("unexpected response: {:?}", other),
+	}
+}
+
+#[test]
+fn block_updates_with_unknown_blocks_are_refused() {
+	let dir = tempfile::tempdir().unwrap();
+	let state = ServerState::new(dir.path()).unwrap();
+	let mut conn = common::connect(&state);
+	let create = ServerMessage::CreateWorld("world".to_string(), WorldGenesisData { seed: 3 });
+	state.make_response(&mut conn, &create);
+	state.make_response(&mut conn, &ServerMessage::LoadWorld("world".to_string()));
+
+	let log = blocks::registry().id_of("log_block").unwrap();
+	let chunk = ChunkPos { x: 0, z: 0 };
+	let update = |type_id, state| {
+		ServerMessage::BlockUpdate(
+			chunk,
+			LocalBlockPos::new(1u8, 2, 3),
+			BlockData { type_id, state },
+		)
+	};
+	// logs have 3 states, one per axis
+	assert!(matches!(
+		state.make_response(&mut conn, &update(log, 2)),
+		ServerResponse::Ack
+	));
+	for message in [update(log, 3), update(9999, 0)] {
+		assert!(matches!(
+			state.make_response(&mut conn, &message),
+			ServerResponse::Err(ErrorMessage::InvalidBlock)
+		));
+	}
+}
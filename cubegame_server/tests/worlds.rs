@@ -0,0 +1,157 @@
+mod common;
+
+use cubegame_lib::{
+	communication::{capabilities, ErrorMessage, ServerEvent, ServerMessage, ServerResponse},
+	BlockData, ChunkPos, LocalBlockPos, WorldGenesisData,
+};
+use cubegame_server::ServerState;
+
+fn create_world(state: &ServerState, name: &str, seed: u32) -> ServerResponse {
+	state.make_response(
+		&mut common::connect(state),
+		&ServerMessage::CreateWorld(name.to_string(), WorldGenesisData { seed }),
+	)
+}
+
+#[test]
+fn create_and_list_worlds() {
+	let dir = tempfile::tempdir().unwrap();
+	let state = ServerState::new(dir.path()).unwrap();
+
+	assert!(matches!(create_world(&state, "b", 1), ServerResponse::Ack));
+	assert!(matches!(create_world(&state, "a", 2), ServerResponse::Ack));
+	assert!(matches!(
+		create_world(&state, "a", 3),
+		ServerResponse::Err(ErrorMessage::WorldAlreadyExists)
+	));
+	assert!(matches!(
+		create_world(&state, "../escape", 3),
+		ServerResponse::Err(ErrorMessage::InvalidWorldName)
+	));
+
+	// worlds are found again after a restart
+	drop(state);
+	let state = ServerState::new(dir.path()).unwrap();
+	match state.make_response(&mut common::connect(&state), &ServerMessage::ListWorlds) {
+		ServerResponse::WorldList(names) => assert_eq!(names, vec!["a", "b"]),
+		other => panic!("unexpected response: {:?}", other),
+	}
+}
+
+#[test]
+fn world_errors() {
+	let dir = tempfile::tempdir().unwrap();
+	let state = ServerState::new(dir.path()).unwrap();
+	let mut conn = common::connect(&state);
+	let chunk = ChunkPos { x: 0, z: 0 };
+
+	// nothing loaded yet
+	for msg in [
+		ServerMessage::LoadChunk(chunk),
+		ServerMessage::BlockUpdate(chunk, LocalBlockPos::new(0u8, 0, 0), BlockData::default()),
+		ServerMessage::UnloadWorld,
+	] {
+		assert!(matches!(
+			state.make_response(&mut conn, &msg),
+			ServerResponse::Err(ErrorMessage::NoLoadedWorld)
+		));
+	}
+	assert!(matches!(
+		state.make_response(&mut conn, &ServerMessage::LoadWorld("missing".to_string())),
+		ServerResponse::Err(ErrorMessage::WorldDoesNotExist)
+	));
+
+	// loading then unloading
+	create_world(&state, "world", 7);
+	let load = ServerMessage::LoadWorld("world".to_string());
+	assert!(matches!(
+		state.make_response(&mut conn, &load),
+		ServerResponse::WorldLoaded(WorldGenesisData { seed: 7 }, _)
+	));
+	assert!(matches!(
+		state.make_response(&mut conn, &ServerMessage::LoadChunk(chunk)),
+		ServerResponse::LoadChunkOK(_)
+	));
+	assert!(matches!(
+		state.make_response(&mut conn, &ServerMessage::UnloadWorld),
+		ServerResponse::Ack
+	));
+	assert!(matches!(
+		state.make_response(&mut conn, &ServerMessage::LoadChunk(chunk)),
+		ServerResponse::Err(ErrorMessage::NoLoadedWorld)
+	));
+}
+
+#[test]
+fn worlds_are_shared_between_connections() {
+	let dir = tempfile::tempdir().unwrap();
+	let state = ServerState::new(dir.path()).unwrap();
+	create_world(&state, "shared", 0);
+	create_world(&state, "other", 0);
+
+	let mut conn_a = common::connect(&state);
+	let mut conn_b = common::connect(&state);
+	let mut conn_c = common::connect(&state);
+	for (conn, name) in [
+		(&mut conn_a, "shared"),
+		(&mut conn_b, "shared"),
+		(&mut conn_c, "other"),
+	] {
+		state.make_response(conn, &ServerMessage::LoadWorld(name.to_string()));
+	}
+
+	let chunk = ChunkPos { x: -2, z: 9 };
+	let pos = LocalBlockPos::new(4u8, 60, 4);
+	let block = BlockData::new(2);
+	state.make_response(&mut conn_a, &ServerMessage::BlockUpdate(chunk, pos, block));
+
+	let load = ServerMessage::LoadChunk(chunk);
+	let ServerResponse::LoadChunkOK(delta) = state.make_response(&mut conn_b, &load) else {
+		panic!("expected chunk data");
+	};
+	assert_eq!(delta.blocks, vec![(pos, block)]);
+	let ServerResponse::LoadChunkOK(delta) = state.make_response(&mut conn_c, &load) else {
+		panic!("expected chunk data");
+	};
+	assert!(delta.blocks.is_empty());
+
+	// the change survives every connection leaving the world
+	state.close_connection(&mut conn_a);
+	state.close_connection(&mut conn_b);
+	state.make_response(&mut conn_a, &ServerMessage::LoadWorld("shared".to_string()));
+	let ServerResponse::LoadChunkOK(delta) = state.make_response(&mut conn_a, &load) else {
+		panic!("expected chunk data");
+	};
+	assert_eq!(delta.blocks, vec![(pos, block)]);
+}
+
+#[test]
+fn time_is_only_synced_to_clients_that_want_it() {
+	let dir = tempfile::tempdir().unwrap();
+	let state = ServerState::new(dir.path()).unwrap();
+	assert!(matches!(
+		create_world(&state, "world", 1),
+		ServerResponse::Ack
+	));
+	let load = ServerMessage::LoadWorld("world".to_string());
+
+	let mut old_client = common::connect(&state);
+	state.make_response(&mut old_client, &load);
+	state.sync_time(&old_client);
+	assert_eq!(old_client.next_event(), None);
+
+	let mut client = common::connect_with(&state, &[capabilities::WORLD_TIME]);
+	// no time to sync outside of a world
+	state.sync_time(&client);
+	assert_eq!(client.next_event(), None);
+
+	let ServerResponse::WorldLoaded(_, loaded_time) = state.make_response(&mut client, &load)
+	else {
+		panic!("world failed to load");
+	};
+	state.sync_time(&client);
+	match client.next_event() {
+		Some(ServerEvent::TimeSync(time)) => assert!(time >= loaded_time),
+		other => panic!("expected a time sync, got {:?}", other),
+	}
+}